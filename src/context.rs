@@ -17,29 +17,40 @@
 // <>
 
 use crate::{Frame, sail};
+use crate::graphics::camera::Camera;
 
 use png;
 use winit::{
     dpi,
-    event::{self, DeviceEvent, ElementState, Event, VirtualKeyCode, WindowEvent},
+    event::{
+        self, DeviceEvent, ElementState, Event, MouseButton, MouseScrollDelta, VirtualKeyCode,
+        WindowEvent,
+    },
     event_loop::{ControlFlow, EventLoop},
     window::{self, WindowBuilder},
 };
 
-use std::fs::File;
+use std::sync::atomic::Ordering;
 use std::thread;
 
+/// Zoom in or out by this much for each line the mouse wheel turns
+const ZOOM_STEP: f32 = 1.2;
+
+/// Pixels of scrolling, on devices which scroll smoothly, taken as one
+/// line of the mouse wheel
+const PIXELS_PER_LINE: f64 = 40.0;
+
 /// Uses `winit` to acquire a graphical frame and create an event loop for it
 pub fn init_context(
     title: &str,
-    icon_file: &str,
+    icon_png: &[u8],
     width: u32,
     height: u32,
 ) -> (Frame, EventLoop<()>) {
     let event_loop = EventLoop::new();
     let frame = WindowBuilder::new()
         .with_title(title)
-        .with_window_icon(get_icon(icon_file))
+        .with_window_icon(get_icon(icon_png))
         .with_inner_size(dpi::Size::Physical(dpi::PhysicalSize { width, height }))
         .build(&event_loop)
         .unwrap();
@@ -54,8 +65,11 @@ pub fn run_loop<Ij: 'static>(
     sl_reg: usize,
     m_send: usize,
     r_send: usize,
+    d_send: usize,
     fr_dims: usize,
     cur_pos: usize,
+    view: usize,
+    interrupt: sail::eval::Interrupt,
 ) where
     Ij: Iterator<Item = thread::JoinHandle<()>>,
 {
@@ -86,9 +100,17 @@ pub fn run_loop<Ij: 'static>(
 
     let main_tx = m_send as *mut sail::SlHead;
     let rndr_tx = r_send as *mut sail::SlHead;
+    let draw_tx = d_send as *mut sail::SlHead;
 
     let fr_dims = fr_dims as *mut sail::SlHead;
     let cur_pos = cur_pos as *mut sail::SlHead;
+    let view = view as *mut sail::SlHead;
+
+    // These are sent with every movement of the cursor, so they are
+    // made once here rather than each time
+    let redrw = sail::sym_init(sl_reg, sail::K_CX_REDRW.0);
+    let resiz = sail::sym_init(sl_reg, sail::K_CX_RESIZ.0);
+    let vw = sail::sym_init(sl_reg, sail::K_CX_VIEW.0);
 
     // TODO: use a small region with space for a queue sender;
     // allocate objects to send in region, send them, and then
@@ -99,8 +121,15 @@ pub fn run_loop<Ij: 'static>(
 
     let mut frame_dims: [u32; 2] = [0, 0];
 
+    // last cursor position in pixels, and whether it is dragging the
+    // view along
+    let mut cursor: [f32; 2] = [0.0, 0.0];
+    let mut panning = false;
+
     let mut focus = false;
 
+    let mut modifiers = event::ModifiersState::empty();
+
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Wait;
 
@@ -110,13 +139,12 @@ pub fn run_loop<Ij: 'static>(
             }
 
             Event::RedrawRequested(..) => {
-                let redrw = sail::sym_init(sl_reg, sail::K_CX_REDRW.0);
-                sail::queue::queue_tx(rndr_tx, redrw);
+                sail::queue::queue_tx(draw_tx, redrw);
             }
 
             Event::RedrawEventsCleared => {
                 // let redrw = sail::sym_init(sl_reg, sail::K_CX_REDRW.0);
-                // sail::queue::queue_tx(rndr_tx, redrw);
+                // sail::queue::queue_tx(draw_tx, redrw);
             }
 
             Event::WindowEvent { event, .. } => match event {
@@ -131,11 +159,16 @@ pub fn run_loop<Ij: 'static>(
                 WindowEvent::Focused(f) => {
                     focus = f;
                 }
+                WindowEvent::ModifiersChanged(m) => {
+                    modifiers = m;
+                }
                 WindowEvent::Resized(dims) => {
                     frame_dims = [dims.width, dims.height];
-                    sail::arrvec_rplc(fr_dims, &[dims.width, dims.height]);
+                    sail::atom::atom_reset(
+                        fr_dims,
+                        sail::arrvec_init(sl_reg, sail::T_U32.0, 2, &frame_dims),
+                    );
 
-                    let resiz = sail::sym_init(sl_reg, sail::K_CX_RESIZ.0);
                     sail::queue::queue_tx(rndr_tx, resiz);
                 }
                 WindowEvent::ScaleFactorChanged {
@@ -143,28 +176,64 @@ pub fn run_loop<Ij: 'static>(
                     ..
                 } => {
                     frame_dims = [dims.width, dims.height];
-                    sail::arrvec_rplc(fr_dims, &[dims.width, dims.height]);
+                    sail::atom::atom_reset(
+                        fr_dims,
+                        sail::arrvec_init(sl_reg, sail::T_U32.0, 2, &frame_dims),
+                    );
 
-                    let resiz = sail::sym_init(sl_reg, sail::K_CX_RESIZ.0);
                     sail::queue::queue_tx(rndr_tx, resiz);
                 }
-                WindowEvent::MouseInput { state, button, .. } => {
+                WindowEvent::MouseInput {
+                    state,
+                    button: MouseButton::Middle,
+                    ..
+                } => {
+                    panning = state == ElementState::Pressed;
+                }
+                WindowEvent::MouseInput { state, .. } => {
                     if state == ElementState::Pressed {
                         let recrd = sail::sym_init(sl_reg, sail::K_CX_RECRD.0);
                         sail::queue::queue_tx(main_tx, recrd);
+                    } else {
+                        let rlse = sail::sym_init(sl_reg, sail::K_CX_RLSE.0);
+                        sail::queue::queue_tx(main_tx, rlse);
                     }
                 }
+                WindowEvent::MouseWheel { delta, .. } => {
+                    let lines = match delta {
+                        MouseScrollDelta::LineDelta(_, y) => y,
+                        MouseScrollDelta::PixelDelta(p) => (p.y / PIXELS_PER_LINE) as f32,
+                    };
+
+                    view_change(sl_reg, view, draw_tx, vw, |camera| {
+                        camera.zoom_at(ZOOM_STEP.powf(lines), cursor, extent(frame_dims))
+                    });
+
+                    // the cursor stays over the same world point
+                }
                 WindowEvent::CursorMoved {
                     position: dpi::PhysicalPosition { x, y },
                     ..
                 } => {
-                    sail::arrvec_rplc(cur_pos, &[
-                        (x / (frame_dims[0] / 2) as f64 - 1.0) as f32,
-                        (y / (frame_dims[1] / 2) as f64 - 1.0) as f32,
-                    ]);
-
-                    let redrw = sail::sym_init(sl_reg, sail::K_CX_REDRW.0);
-                    sail::queue::queue_tx(rndr_tx, redrw);
+                    let moved = [x as f32, y as f32];
+
+                    let camera = if panning {
+                        view_change(sl_reg, view, draw_tx, vw, |camera| {
+                            camera.pan([moved[0] - cursor[0], moved[1] - cursor[1]])
+                        })
+                    } else {
+                        view_get(sail::atom::atom_deref(view))
+                    };
+                    cursor = moved;
+
+                    // scripts see the cursor in world coordinates
+                    let world = camera.to_world(cursor, extent(frame_dims));
+                    sail::atom::atom_reset(
+                        cur_pos,
+                        sail::arrvec_init(sl_reg, sail::T_F32.0, 2, &world),
+                    );
+
+                    sail::queue::queue_tx(draw_tx, redrw);
                 }
                 _ => {}
             },
@@ -178,6 +247,10 @@ pub fn run_loop<Ij: 'static>(
                 }) => {
                     if focus && state == ElementState::Pressed {
                         match virtual_keycode {
+                            Some(VirtualKeyCode::G) if modifiers.ctrl() => {
+                                // stop whatever the shell is evaluating
+                                interrupt.store(true, Ordering::Relaxed);
+                            }
                             Some(VirtualKeyCode::U) => {
                                 // move up
                                 let key_u = sail::sym_init(sl_reg, sail::K_CX_KEY_U.0);
@@ -223,6 +296,21 @@ pub fn run_loop<Ij: 'static>(
                                 let key_m = sail::sym_init(sl_reg, sail::K_CX_KEY_M.0);
                                 sail::queue::queue_tx(main_tx, key_m);
                             }
+                            Some(VirtualKeyCode::V) => {
+                                // switch between drawing and selecting
+                                let key_v = sail::sym_init(sl_reg, sail::K_CX_KEY_V.0);
+                                sail::queue::queue_tx(main_tx, key_v);
+                            }
+                            Some(VirtualKeyCode::X) => {
+                                // delete what is selected
+                                let key_x = sail::sym_init(sl_reg, sail::K_CX_KEY_X.0);
+                                sail::queue::queue_tx(main_tx, key_x);
+                            }
+                            Some(VirtualKeyCode::Z) => {
+                                // zoom to fit everything drawn
+                                let zfit = sail::sym_init(sl_reg, sail::K_CX_ZFIT.0);
+                                sail::queue::queue_tx(rndr_tx, zfit);
+                            }
                             Some(VirtualKeyCode::Space) => {
                                 // enter the point
                                 let recrd = sail::sym_init(sl_reg, sail::K_CX_RECRD.0);
@@ -230,6 +318,10 @@ pub fn run_loop<Ij: 'static>(
                             }
                             _ => {}
                         }
+                    } else if focus && virtual_keycode == Some(VirtualKeyCode::Space) {
+                        // let go of the point, ending a selection box
+                        let rlse = sail::sym_init(sl_reg, sail::K_CX_RLSE.0);
+                        sail::queue::queue_tx(main_tx, rlse);
                     }
                 }
                 // DeviceEvent::MouseWheel {
@@ -246,14 +338,63 @@ pub fn run_loop<Ij: 'static>(
     });
 }
 
-/// Retrieves an icon from a PNG file and outputs it in the format desired by `winit`
-fn get_icon(filename: &str) -> Option<window::Icon> {
-    let decoder = png::Decoder::new(File::open(filename).unwrap());
-    let (info, mut reader) = decoder.read_info().unwrap();
+/// Frame dimensions as the extent cameras map into
+fn extent(frame_dims: [u32; 2]) -> [f32; 2] {
+    [frame_dims[0] as f32, frame_dims[1] as f32]
+}
+
+/// Reads the camera from a snapshot of the view atom
+fn view_get(vec: *mut sail::SlHead) -> Camera {
+    assert_eq!(sail::arrvec_get_len(vec), 3);
+
+    let mut fields = [0.0; 3];
+    for (i, field) in fields.iter_mut().enumerate() {
+        *field =
+            unsafe { std::ptr::read_unaligned(sail::value_ptr(vec).add(8 + 4 * i) as *const f32) };
+    }
+
+    Camera::from_slice(&fields)
+}
+
+/// Changes the camera in the view atom, and has the render thread
+/// draw through it
+///
+/// The render thread may store a view of its own meanwhile, so the
+/// change is made again to whichever view is current until it sticks.
+/// Returns the camera stored.
+fn view_change(
+    sl_reg: *mut sail::memmgt::Region,
+    view: *mut sail::SlHead,
+    draw_tx: *mut sail::SlHead,
+    vw: *mut sail::SlHead,
+    change: impl Fn(&mut Camera),
+) -> Camera {
+    let camera = loop {
+        let old = sail::atom::atom_deref(view);
+
+        let mut camera = view_get(old);
+        change(&mut camera);
+
+        // nothing else refers to the new view, so it is its own snapshot
+        let new = sail::arrvec_init(sl_reg, sail::T_F32.0, 3, &camera.to_array());
+        sail::set_shared(new);
+        if sail::atom::atom_cmpxcg(sl_reg, view, old, new) {
+            break camera;
+        }
+    };
+
+    sail::queue::queue_tx(draw_tx, vw);
+    camera
+}
+
+/// Decodes an icon from PNG data and outputs it in the format desired by `winit`
+fn get_icon(png_data: &[u8]) -> Option<window::Icon> {
+    let decoder = png::Decoder::new(png_data);
+    let (info, mut reader) = decoder.read_info().ok()?;
 
     let mut buf = vec![0; info.buffer_size()];
 
-    reader.next_frame(&mut buf).unwrap();
+    reader.next_frame(&mut buf).ok()?;
 
-    Some(window::Icon::from_rgba(buf, info.width, info.height).unwrap())
+    window::Icon::from_rgba(buf, info.width, info.height).ok()
 }
@@ -0,0 +1,279 @@
+// STARK, a system for computer augmented design.
+// Copyright (C) 2021 Matthew Rothlisberger
+
+// STARK is licensed under the terms of the GNU Affero General Public
+// License. See the top level LICENSE file for the license text.
+
+// Find full copyright information in the top level COPYRIGHT file.
+
+// <>
+
+// src/graphics/atlas.rs
+
+// Glyph atlas on the GPU: an image holding the coverage of every glyph
+// rasterized so far, the sampler and descriptor set the text pipeline
+// reads it through, and a buffer new glyphs are staged in on their way
+// to the image.
+
+// <>
+
+use super::text::ATLAS_SIZE;
+use super::GraphicsState;
+
+use gfx_hal::{
+    command::{BufferImageCopy, CommandBuffer},
+    device::Device,
+    format::{Aspects, Format, Swizzle},
+    image::{
+        Access, Extent, Filter, Kind, Layout, Offset, SamplerDesc, SubresourceLayers,
+        SubresourceRange, Tiling, Usage, ViewCapabilities, ViewKind, WrapMode,
+    },
+    memory::{Barrier, Dependencies, Properties, Segment, SparseFlags},
+    pso::{
+        Descriptor, DescriptorPool, DescriptorPoolCreateFlags, DescriptorRangeDesc,
+        DescriptorSetLayoutBinding, DescriptorSetWrite, DescriptorType, ImageDescriptorType,
+        PipelineStage, ShaderStageFlags,
+    },
+};
+
+use std::iter;
+
+/// Atlas image and everything needed to fill it and sample from it
+pub struct AtlasImage<B: gfx_hal::Backend> {
+    image: B::Image,
+    image_memory: B::Memory,
+    view: B::ImageView,
+    sampler: B::Sampler,
+    pub set_layout: B::DescriptorSetLayout,
+    pool: B::DescriptorPool,
+    pub set: B::DescriptorSet,
+    staging: B::Buffer,
+    /// Staging memory, mapped for as long as the buffer lives
+    staging_memory: B::Memory,
+    map: *mut u8,
+    /// Whether the image has ever been filled, and so has contents to
+    /// keep when it is next changed
+    filled: bool,
+    /// Whether the staging buffer holds pixels not yet in the image
+    staged: bool,
+}
+
+impl<B: gfx_hal::Backend> AtlasImage<B> {
+    /// Creates an empty atlas, and a descriptor set binding it for the
+    /// text fragment shader
+    pub fn new(state: &mut GraphicsState<B>) -> Self {
+        let range = Self::range();
+
+        unsafe {
+            let mut image = state
+                .device
+                .create_image(
+                    Kind::D2(ATLAS_SIZE, ATLAS_SIZE, 1, 1),
+                    1,
+                    Format::R8Unorm,
+                    Tiling::Optimal,
+                    Usage::SAMPLED | Usage::TRANSFER_DST,
+                    SparseFlags::empty(),
+                    ViewCapabilities::empty(),
+                )
+                .unwrap();
+
+            let req = state.device.get_image_requirements(&image);
+            let image_memory = state
+                .device
+                .allocate_memory(
+                    state.memory_type(req.type_mask, Properties::DEVICE_LOCAL),
+                    req.size,
+                )
+                .unwrap();
+            state
+                .device
+                .bind_image_memory(&image_memory, 0, &mut image)
+                .unwrap();
+
+            let view = state
+                .device
+                .create_image_view(
+                    &image,
+                    ViewKind::D2,
+                    Format::R8Unorm,
+                    Swizzle::NO,
+                    Usage::SAMPLED,
+                    range,
+                )
+                .unwrap();
+
+            let sampler = state
+                .device
+                .create_sampler(&SamplerDesc::new(Filter::Linear, WrapMode::Clamp))
+                .unwrap();
+
+            let ty = DescriptorType::Image {
+                ty: ImageDescriptorType::Sampled { with_sampler: true },
+            };
+
+            let set_layout = state
+                .device
+                .create_descriptor_set_layout(
+                    iter::once(DescriptorSetLayoutBinding {
+                        binding: 0,
+                        ty,
+                        count: 1,
+                        stage_flags: ShaderStageFlags::FRAGMENT,
+                        immutable_samplers: false,
+                    }),
+                    iter::empty(),
+                )
+                .unwrap();
+
+            let mut pool = state
+                .device
+                .create_descriptor_pool(
+                    1,
+                    iter::once(DescriptorRangeDesc { ty, count: 1 }),
+                    DescriptorPoolCreateFlags::empty(),
+                )
+                .unwrap();
+            let mut set = pool.allocate_one(&set_layout).unwrap();
+
+            state.device.write_descriptor_set(DescriptorSetWrite {
+                set: &mut set,
+                binding: 0,
+                array_offset: 0,
+                descriptors: iter::once(Descriptor::CombinedImageSampler(
+                    &view,
+                    Layout::ShaderReadOnlyOptimal,
+                    &sampler,
+                )),
+            });
+
+            // coherent, like the vertex buffers, so it can stay mapped
+            let (mut staging_memory, staging) = state.make_buffer(
+                (ATLAS_SIZE * ATLAS_SIZE) as u64,
+                gfx_hal::buffer::Usage::TRANSFER_SRC,
+                Properties::CPU_VISIBLE | Properties::COHERENT,
+            );
+            let map = state
+                .device
+                .map_memory(&mut staging_memory, Segment::ALL)
+                .unwrap();
+
+            Self {
+                image,
+                image_memory,
+                view,
+                sampler,
+                set_layout,
+                pool,
+                set,
+                staging,
+                staging_memory,
+                map,
+                filled: false,
+                staged: false,
+            }
+        }
+    }
+
+    /// The whole of the atlas image
+    fn range() -> SubresourceRange {
+        SubresourceRange {
+            aspects: Aspects::COLOR,
+            level_start: 0,
+            level_count: Some(1),
+            layer_start: 0,
+            layer_count: Some(1),
+        }
+    }
+
+    /// Stages the atlas pixels, to be copied into the image by the next
+    /// command buffer recorded; the last submission must be complete
+    pub fn stage(&mut self, pixels: &[u8]) {
+        assert_eq!(pixels.len(), (ATLAS_SIZE * ATLAS_SIZE) as usize);
+
+        unsafe {
+            std::ptr::copy_nonoverlapping(pixels.as_ptr(), self.map, pixels.len());
+        }
+
+        self.staged = true;
+    }
+
+    /// Records a copy of any staged pixels into the image, leaving it
+    /// ready to be sampled; must come before the render pass
+    pub unsafe fn record_upload(&mut self, buffer: &mut B::CommandBuffer) {
+        if !self.staged {
+            return;
+        }
+
+        // the first upload has no earlier contents to keep
+        let before = if self.filled {
+            (Access::SHADER_READ, Layout::ShaderReadOnlyOptimal)
+        } else {
+            (Access::empty(), Layout::Undefined)
+        };
+        let during = (Access::TRANSFER_WRITE, Layout::TransferDstOptimal);
+        let after = (Access::SHADER_READ, Layout::ShaderReadOnlyOptimal);
+
+        buffer.pipeline_barrier(
+            PipelineStage::TOP_OF_PIPE | PipelineStage::FRAGMENT_SHADER..PipelineStage::TRANSFER,
+            Dependencies::empty(),
+            iter::once(Barrier::Image {
+                states: before..during,
+                target: &self.image,
+                range: Self::range(),
+                families: None,
+            }),
+        );
+
+        buffer.copy_buffer_to_image(
+            &self.staging,
+            &self.image,
+            Layout::TransferDstOptimal,
+            iter::once(BufferImageCopy {
+                buffer_offset: 0,
+                buffer_width: ATLAS_SIZE,
+                buffer_height: ATLAS_SIZE,
+                image_layers: SubresourceLayers {
+                    aspects: Aspects::COLOR,
+                    level: 0,
+                    layers: 0..1,
+                },
+                image_offset: Offset::ZERO,
+                image_extent: Extent {
+                    width: ATLAS_SIZE,
+                    height: ATLAS_SIZE,
+                    depth: 1,
+                },
+            }),
+        );
+
+        buffer.pipeline_barrier(
+            PipelineStage::TRANSFER..PipelineStage::FRAGMENT_SHADER,
+            Dependencies::empty(),
+            iter::once(Barrier::Image {
+                states: during..after,
+                target: &self.image,
+                range: Self::range(),
+                families: None,
+            }),
+        );
+
+        self.filled = true;
+        self.staged = false;
+    }
+
+    /// Releases the atlas's resources
+    pub fn destroy(mut self, device: &B::Device) {
+        unsafe {
+            device.destroy_descriptor_pool(self.pool);
+            device.destroy_descriptor_set_layout(self.set_layout);
+            device.destroy_sampler(self.sampler);
+            device.destroy_image_view(self.view);
+            device.destroy_image(self.image);
+            device.free_memory(self.image_memory);
+            device.unmap_memory(&mut self.staging_memory);
+            device.destroy_buffer(self.staging);
+            device.free_memory(self.staging_memory);
+        }
+    }
+}
@@ -0,0 +1,189 @@
+// STARK, a system for computer augmented design.
+// Copyright (C) 2021 Matthew Rothlisberger
+
+// STARK is licensed under the terms of the GNU Affero General Public
+// License. See the top level LICENSE file for the license text.
+
+// Find full copyright information in the top level COPYRIGHT file.
+
+// <>
+
+// src/graphics/camera.rs
+
+// Camera: maps world coordinates, which drawings are made in, onto the
+// frame. A world unit is the same number of pixels along both axes,
+// whatever the frame's shape, and y grows down the screen as it does
+// in normalized device coordinates.
+
+// <>
+
+type Point = [f32; 2];
+
+/// Fewest and most pixels per world unit a camera may show
+const ZOOM_LIMITS: [f32; 2] = [1e-4, 1e6];
+
+/// Share of the frame left empty on each side of a drawing zoomed to
+/// fit it
+const FIT_MARGIN: f32 = 0.05;
+
+/// Where the frame looks in the world, and how closely
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera {
+    /// World point shown in the middle of the frame
+    pub center: Point,
+    /// Pixels per world unit
+    pub zoom: f32,
+}
+
+impl Camera {
+    /// Makes a camera, keeping its zoom within limits
+    pub fn new(center: Point, zoom: f32) -> Self {
+        Self {
+            center,
+            zoom: zoom.clamp(ZOOM_LIMITS[0], ZOOM_LIMITS[1]),
+        }
+    }
+
+    /// Reads a camera from its center and zoom, as stored in Sail
+    pub fn from_slice(view: &[f32]) -> Self {
+        assert_eq!(view.len(), 3);
+        Self::new([view[0], view[1]], view[2])
+    }
+
+    /// The camera's center and zoom, as stored in Sail
+    pub fn to_array(&self) -> [f32; 3] {
+        [self.center[0], self.center[1], self.zoom]
+    }
+
+    /// Scale and offset along x and y taking world points to normalized
+    /// device coordinates, in a frame `extent` pixels in size
+    pub fn transform(&self, extent: Point) -> [f32; 4] {
+        let scale = [2.0 * self.zoom / extent[0], 2.0 * self.zoom / extent[1]];
+
+        [
+            scale[0],
+            scale[1],
+            -self.center[0] * scale[0],
+            -self.center[1] * scale[1],
+        ]
+    }
+
+    /// The world point under a pixel position
+    pub fn to_world(&self, pixel: Point, extent: Point) -> Point {
+        [
+            self.center[0] + (pixel[0] - extent[0] / 2.0) / self.zoom,
+            self.center[1] + (pixel[1] - extent[1] / 2.0) / self.zoom,
+        ]
+    }
+
+    /// The pixel position of a world point
+    pub fn to_pixel(&self, world: Point, extent: Point) -> Point {
+        [
+            (world[0] - self.center[0]) * self.zoom + extent[0] / 2.0,
+            (world[1] - self.center[1]) * self.zoom + extent[1] / 2.0,
+        ]
+    }
+
+    /// Zooms in by `factor`, or out if it is below one, keeping the
+    /// world point under a pixel position where it is
+    pub fn zoom_at(&mut self, factor: f32, pixel: Point, extent: Point) {
+        let before = self.to_world(pixel, extent);
+        self.zoom = (self.zoom * factor).clamp(ZOOM_LIMITS[0], ZOOM_LIMITS[1]);
+        let after = self.to_world(pixel, extent);
+
+        self.center[0] += before[0] - after[0];
+        self.center[1] += before[1] - after[1];
+    }
+
+    /// Moves the view so the drawing follows a cursor dragged by
+    /// `delta` pixels
+    pub fn pan(&mut self, delta: Point) {
+        self.center[0] -= delta[0] / self.zoom;
+        self.center[1] -= delta[1] / self.zoom;
+    }
+
+    /// Centers the view on a box, given by its least and greatest
+    /// corners, zoomed to fill the frame with it; a box with no width
+    /// or height is centered at the same zoom
+    pub fn fit(&mut self, bounds: [f32; 4], extent: Point) {
+        let size = [bounds[2] - bounds[0], bounds[3] - bounds[1]];
+        self.center = [(bounds[0] + bounds[2]) / 2.0, (bounds[1] + bounds[3]) / 2.0];
+
+        let room = 1.0 - 2.0 * FIT_MARGIN;
+        let zoom = (0..2)
+            .filter(|&i| size[i] > 0.0)
+            .map(|i| extent[i] * room / size[i])
+            .fold(f32::INFINITY, f32::min);
+
+        if zoom.is_finite() {
+            self.zoom = zoom.clamp(ZOOM_LIMITS[0], ZOOM_LIMITS[1]);
+        }
+    }
+}
+
+/// Smallest box holding some points, as its least and greatest corners
+pub fn bounds<'a>(points: impl IntoIterator<Item = &'a Point>) -> Option<[f32; 4]> {
+    points.into_iter().fold(None, |acc, p| {
+        let [x0, y0, x1, y1] = acc.unwrap_or([p[0], p[1], p[0], p[1]]);
+        Some([x0.min(p[0]), y0.min(p[1]), x1.max(p[0]), y1.max(p[1])])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Point, b: Point) -> bool {
+        (a[0] - b[0]).abs() < 1e-4 && (a[1] - b[1]).abs() < 1e-4
+    }
+
+    #[test]
+    fn mapping() {
+        let extent = [200.0, 100.0];
+        let camera = Camera::new([1.0, -2.0], 50.0);
+
+        assert!(close(camera.to_world([100.0, 50.0], extent), [1.0, -2.0]));
+        assert!(close(camera.to_world([150.0, 0.0], extent), [2.0, -3.0]));
+        assert!(close(camera.to_pixel([2.0, -3.0], extent), [150.0, 0.0]));
+
+        // the shader's transform lands on the same pixels
+        let [sx, sy, ox, oy] = camera.transform(extent);
+        let ndc = [2.0 * sx + ox, -3.0 * sy + oy];
+        assert!(close(ndc, [0.5, -1.0]));
+
+        // a wide frame shows more of the world, not a stretched one
+        let wide = camera.to_world([200.0, 100.0], [400.0, 100.0]);
+        assert!(close(wide, [1.0, -1.0]));
+
+        assert_eq!(Camera::from_slice(&camera.to_array()), camera);
+        assert_eq!(Camera::new([0.0, 0.0], 0.0).zoom, ZOOM_LIMITS[0]);
+    }
+
+    #[test]
+    fn moves() {
+        let extent = [200.0, 100.0];
+        let mut camera = Camera::new([0.0, 0.0], 10.0);
+
+        let cursor = [170.0, 20.0];
+        let under = camera.to_world(cursor, extent);
+        camera.zoom_at(4.0, cursor, extent);
+        assert_eq!(camera.zoom, 40.0);
+        assert!(close(camera.to_world(cursor, extent), under));
+
+        camera.pan([20.0, -40.0]);
+        let moved = camera.to_world([cursor[0] + 20.0, cursor[1] - 40.0], extent);
+        assert!(close(moved, under));
+
+        camera.fit([-1.0, 0.0, 3.0, 1.0], extent);
+        assert_eq!(camera.center, [1.0, 0.5]);
+        assert!((camera.zoom - 45.0).abs() < 1e-3);
+
+        camera.fit([2.0, 2.0, 2.0, 2.0], extent);
+        assert_eq!(camera.center, [2.0, 2.0]);
+        assert!((camera.zoom - 45.0).abs() < 1e-3);
+
+        let points = [[1.0, 5.0], [-2.0, 3.0], [0.0, 7.0]];
+        assert_eq!(bounds(points.iter()), Some([-2.0, 3.0, 1.0, 7.0]));
+        assert_eq!(bounds([].iter()), None);
+    }
+}
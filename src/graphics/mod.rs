@@ -27,9 +27,20 @@
 
 // <>
 
+mod atlas;
+pub mod camera;
+mod offscreen;
+pub mod scene;
+pub mod soft;
+pub mod tess;
+pub mod text;
+
 use crate::sail::{self, SlHead};
 use crate::FrameHandle;
 
+use atlas::AtlasImage;
+use offscreen::Offscreen;
+
 use gfx_hal::{
     adapter::{Adapter, PhysicalDevice},
     command::{
@@ -64,6 +75,7 @@ pub fn render_loop(
     sl_reg: usize,
     sl_tbl: usize,
     sl_env: usize,
+    search: &crate::scripts::SearchPath,
 ) {
     let sl_reg = sl_reg as *mut sail::memmgt::Region;
     let sl_tbl = sl_tbl as *mut SlHead;
@@ -72,8 +84,65 @@ pub fn render_loop(
     let mut engine: Engine<backend::Backend> =
         Engine::new(GraphicsState::new(window, name, size[0], size[1]));
 
+    engine_bind(&mut engine, sl_reg, sl_tbl, sl_env);
+
+    engine.setup();
+    engine.set_clear([1.0, 1.0, 1.0, 1.0]);
+
+    let prog_expr = search.rndr_script(sl_reg, sl_tbl);
+
+    let mut stack = sail::eval::EvalStack::new(10000);
+
+    let sigil = 1 as *mut SlHead;
+
+    let mut ret_slot = sigil;
+    let ret_addr: *mut *mut SlHead = &mut ret_slot;
+
+    stack.start(ret_addr, sl_env, prog_expr);
+
+    while ret_slot == sigil {
+        stack.iter_once(sl_reg, sl_tbl);
+    }
+
+    let rndr = sail::env_lookup_by_id(sl_env, sail::S_RNDR.0);
+
+    stack.push_frame_head(ret_addr, sail::eval::Opcode::Apply, sl_env);
+    stack.push(rndr);
+
+    loop {
+        if engine.should_configure_swapchain {
+            engine.state.config_swapchain();
+            engine.draw_frame();
+            engine.should_configure_swapchain = false;
+        }
+
+        stack.iter_once(sl_reg, sl_tbl);
+
+        if stack.is_empty() {
+            println!("render thread ended");
+            break;
+        }
+    }
+
+    // TODO: dispose of Sail environment first (reverse creation order)
+    drop(engine);
+}
+
+/// Binds an engine to `engine` in a Sail environment, along with the
+/// native procedures which draw with it
+///
+/// The engine must outlive the environment's use of it.
+fn engine_bind(
+    engine: &mut (dyn Renderer + 'static),
+    sl_reg: *mut sail::memmgt::Region,
+    sl_tbl: *mut SlHead,
+    sl_env: *mut SlHead,
+) {
+    // a trait object pointer is two words, so Sail holds a pointer to it
+    let handle = Box::into_raw(Box::new(engine as *mut dyn Renderer));
+
     let eng_obj = unsafe { sail::memmgt::alloc(sl_reg, 8, sail::Cfg::B8Other as u8) };
-    unsafe { sail::write_field_unchecked(eng_obj, 0, (&mut engine as *mut _) as u64) };
+    unsafe { sail::write_field_unchecked(eng_obj, 0, handle as u64) };
     sail::env_layer_ins_by_id(sl_reg, sl_env, sail::S_ENGINE.0, eng_obj);
 
     crate::sail_fn! {
@@ -83,7 +152,7 @@ pub fn render_loop(
         "redraw" 1 [eng_ptr] {
             assert_eq!(sail::get_cfg_spec(eng_ptr), sail::Cfg::B8Other);
             let engine = unsafe {
-                &mut *(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut Engine<backend::Backend>)
+                &mut **(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut *mut dyn Renderer)
             };
 
             engine.draw_frame();
@@ -94,14 +163,13 @@ pub fn render_loop(
         "frame-size" 3 [eng_ptr, w, h] {
             assert_eq!(sail::get_cfg_spec(eng_ptr), sail::Cfg::B8Other);
             let engine = unsafe {
-                &mut *(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut Engine<backend::Backend>)
+                &mut **(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut *mut dyn Renderer)
             };
 
             let w = sail::u32_get(w);
             let h = sail::u32_get(h);
 
-            engine.state.set_extent(w, h);
-            engine.should_configure_swapchain = true;
+            engine.resize(w, h);
 
             return sail::nil();
         }
@@ -109,7 +177,7 @@ pub fn render_loop(
         "add-line" 3 [eng_ptr, points, colors] {
             assert_eq!(sail::get_cfg_spec(eng_ptr), sail::Cfg::B8Other);
             let engine = unsafe {
-                &mut *(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut Engine<backend::Backend>)
+                &mut **(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut *mut dyn Renderer)
             };
 
             assert_eq!(sail::core_type(points), Some(sail::CoreType::VecArr));
@@ -125,19 +193,525 @@ pub fn render_loop(
                 )
             };
 
-            engine.add_line(ln, cl);
+            let id = engine.add_line(ln, cl);
+
+            return sail::i64_init(_reg, id as i64);
+        }
+
+        "add-triangle" 3 [eng_ptr, points, color] {
+            assert_eq!(sail::get_cfg_spec(eng_ptr), sail::Cfg::B8Other);
+            let engine = unsafe {
+                &mut **(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut *mut dyn Renderer)
+            };
+
+            let (points, color) = (f32_vec(points), f32_vec(color));
+            assert_eq!(points.len(), 6);
+            assert_eq!(color.len(), 4);
+
+            let mut corners = [0.0; 6];
+            corners.copy_from_slice(&points);
+
+            let id = engine.add_triangle(corners, [color[0], color[1], color[2], color[3]]);
+
+            return sail::i64_init(_reg, id as i64);
+        }
+
+        "add-polygon" 4 [eng_ptr, points, counts, color] {
+            assert_eq!(sail::get_cfg_spec(eng_ptr), sail::Cfg::B8Other);
+            let engine = unsafe {
+                &mut **(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut *mut dyn Renderer)
+            };
+
+            assert_eq!(sail::core_type(counts), Some(sail::CoreType::VecArr));
+            assert_eq!(sail::arrvec_get_typ(counts), sail::T_I64.0);
+
+            let (points, color) = (f32_vec(points), f32_vec(color));
+            assert_eq!(color.len(), 4);
+
+            // x y pairs for the outline, then any holes, split by the
+            // number of points in each
+            let mut rings: Vec<Vec<[f32; 2]>> = vec![];
+            let mut at = 0;
+
+            for i in 0..sail::arrvec_get_len(counts) as usize {
+                let count = unsafe {
+                    std::ptr::read_unaligned(sail::value_ptr(counts).add(8 + 8 * i) as *const i64)
+                } as usize;
+
+                assert!(2 * (at + count) <= points.len());
+                rings.push(
+                    points[2 * at..2 * (at + count)]
+                        .chunks(2)
+                        .map(|p| [p[0], p[1]])
+                        .collect(),
+                );
+                at += count;
+            }
+
+            let id = engine.add_fill(
+                tess::triangulate(&rings),
+                [color[0], color[1], color[2], color[3]],
+            );
+
+            return sail::i64_init(_reg, id as i64);
+        }
+
+        "add-point" 4 [eng_ptr, point, size, color] {
+            assert_eq!(sail::get_cfg_spec(eng_ptr), sail::Cfg::B8Other);
+            let engine = unsafe {
+                &mut **(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut *mut dyn Renderer)
+            };
+
+            let (point, color) = (f32_vec(point), f32_vec(color));
+            assert_eq!(point.len(), 2);
+            assert_eq!(color.len(), 4);
+
+            let id = engine.add_point(
+                [point[0], point[1]],
+                sail::f32_get(size),
+                [color[0], color[1], color[2], color[3]],
+            );
+
+            return sail::i64_init(_reg, id as i64);
+        }
+
+        "add-circle" 4 [eng_ptr, center, radius, color] {
+            assert_eq!(sail::get_cfg_spec(eng_ptr), sail::Cfg::B8Other);
+            let engine = unsafe {
+                &mut **(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut *mut dyn Renderer)
+            };
+
+            let (center, color) = (f32_vec(center), f32_vec(color));
+            assert_eq!(center.len(), 2);
+            assert_eq!(color.len(), 3);
+
+            let radius = sail::f32_get(radius);
+            let curve = tess::Curve::Arc {
+                center: [center[0], center[1]],
+                radii: [radius, radius],
+                start: 0.0,
+                sweep: 2.0 * std::f32::consts::PI,
+            };
+
+            let id = engine.add_curve(curve, [color[0], color[1], color[2]]);
+
+            return sail::i64_init(_reg, id as i64);
+        }
+
+        "add-ellipse" 4 [eng_ptr, center, radii, color] {
+            assert_eq!(sail::get_cfg_spec(eng_ptr), sail::Cfg::B8Other);
+            let engine = unsafe {
+                &mut **(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut *mut dyn Renderer)
+            };
+
+            let (center, radii, color) = (f32_vec(center), f32_vec(radii), f32_vec(color));
+            assert_eq!(center.len(), 2);
+            assert_eq!(radii.len(), 2);
+            assert_eq!(color.len(), 3);
+
+            let curve = tess::Curve::Arc {
+                center: [center[0], center[1]],
+                radii: [radii[0], radii[1]],
+                start: 0.0,
+                sweep: 2.0 * std::f32::consts::PI,
+            };
+
+            let id = engine.add_curve(curve, [color[0], color[1], color[2]]);
+
+            return sail::i64_init(_reg, id as i64);
+        }
+
+        "add-arc" 6 [eng_ptr, center, radius, start, sweep, color] {
+            assert_eq!(sail::get_cfg_spec(eng_ptr), sail::Cfg::B8Other);
+            let engine = unsafe {
+                &mut **(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut *mut dyn Renderer)
+            };
+
+            let (center, color) = (f32_vec(center), f32_vec(color));
+            assert_eq!(center.len(), 2);
+            assert_eq!(color.len(), 3);
+
+            let radius = sail::f32_get(radius);
+            let curve = tess::Curve::Arc {
+                center: [center[0], center[1]],
+                radii: [radius, radius],
+                start: sail::f32_get(start),
+                sweep: sail::f32_get(sweep),
+            };
+
+            let id = engine.add_curve(curve, [color[0], color[1], color[2]]);
+
+            return sail::i64_init(_reg, id as i64);
+        }
+
+        "add-quadratic" 3 [eng_ptr, points, color] {
+            assert_eq!(sail::get_cfg_spec(eng_ptr), sail::Cfg::B8Other);
+            let engine = unsafe {
+                &mut **(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut *mut dyn Renderer)
+            };
+
+            let (p, color) = (f32_vec(points), f32_vec(color));
+            assert_eq!(p.len(), 6);
+            assert_eq!(color.len(), 3);
+
+            let curve = tess::Curve::Quadratic([[p[0], p[1]], [p[2], p[3]], [p[4], p[5]]]);
+
+            let id = engine.add_curve(curve, [color[0], color[1], color[2]]);
+
+            return sail::i64_init(_reg, id as i64);
+        }
+
+        "add-cubic" 3 [eng_ptr, points, color] {
+            assert_eq!(sail::get_cfg_spec(eng_ptr), sail::Cfg::B8Other);
+            let engine = unsafe {
+                &mut **(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut *mut dyn Renderer)
+            };
+
+            let (p, color) = (f32_vec(points), f32_vec(color));
+            assert_eq!(p.len(), 8);
+            assert_eq!(color.len(), 3);
+
+            let curve = tess::Curve::Cubic([
+                [p[0], p[1]],
+                [p[2], p[3]],
+                [p[4], p[5]],
+                [p[6], p[7]],
+            ]);
+
+            let id = engine.add_curve(curve, [color[0], color[1], color[2]]);
+
+            return sail::i64_init(_reg, id as i64);
+        }
+
+        "add-polyline" 3 [eng_ptr, points, color] {
+            assert_eq!(sail::get_cfg_spec(eng_ptr), sail::Cfg::B8Other);
+            let engine = unsafe {
+                &mut **(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut *mut dyn Renderer)
+            };
+
+            let (points, color) = (f32_vec(points), f32_vec(color));
+            assert_eq!(points.len() % 2, 0);
+            assert_eq!(color.len(), 3);
+
+            let curve = tess::Curve::Polyline(points.chunks(2).map(|p| [p[0], p[1]]).collect());
+
+            let id = engine.add_curve(curve, [color[0], color[1], color[2]]);
+
+            return sail::i64_init(_reg, id as i64);
+        }
+
+        "line-style" 6 [eng_ptr, width, units, dashes, cap, join] {
+            assert_eq!(sail::get_cfg_spec(eng_ptr), sail::Cfg::B8Other);
+            let engine = unsafe {
+                &mut **(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut *mut dyn Renderer)
+            };
+
+            let units = match keyword(_tbl, units) {
+                "px" => tess::Units::Pixels,
+                "world" => tess::Units::World,
+                other => {
+                    log::error!("unknown line width units :{}", other);
+                    return sail::errcode_init(_reg, sail::SlErrCode::ErrorUnknown);
+                }
+            };
+
+            let cap = match keyword(_tbl, cap) {
+                "butt" => tess::Cap::Butt,
+                "round" => tess::Cap::Round,
+                "square" => tess::Cap::Square,
+                other => {
+                    log::error!("unknown line cap :{}", other);
+                    return sail::errcode_init(_reg, sail::SlErrCode::ErrorUnknown);
+                }
+            };
+
+            let join = match keyword(_tbl, join) {
+                "miter" => tess::Join::Miter,
+                "round" => tess::Join::Round,
+                "bevel" => tess::Join::Bevel,
+                other => {
+                    log::error!("unknown line join :{}", other);
+                    return sail::errcode_init(_reg, sail::SlErrCode::ErrorUnknown);
+                }
+            };
+
+            // anything but a vector, such as (), makes a solid line
+            let dashes = if sail::core_type(dashes) == Some(sail::CoreType::VecArr) {
+                f32_vec(dashes)
+            } else {
+                vec![]
+            };
+
+            engine.set_stroke(tess::Stroke {
+                width: sail::f32_get(width),
+                units,
+                dashes,
+                cap,
+                join,
+            });
 
             return sail::nil();
         }
 
+        "add-text" 7 [eng_ptr, string, position, size, anchor, rotation, color] {
+            assert_eq!(sail::get_cfg_spec(eng_ptr), sail::Cfg::B8Other);
+            let engine = unsafe {
+                &mut **(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut *mut dyn Renderer)
+            };
+
+            assert_eq!(sail::core_type(string), Some(sail::CoreType::VecStr));
+
+            let (position, color) = (f32_vec(position), f32_vec(color));
+            assert_eq!(position.len(), 2);
+            assert_eq!(color.len(), 4);
+
+            let anchor = match keyword(_tbl, anchor) {
+                "top-left" => text::Anchor::TopLeft,
+                "top" => text::Anchor::Top,
+                "top-right" => text::Anchor::TopRight,
+                "left" => text::Anchor::Left,
+                "center" => text::Anchor::Center,
+                "right" => text::Anchor::Right,
+                "bottom-left" => text::Anchor::BottomLeft,
+                "bottom" => text::Anchor::Bottom,
+                "bottom-right" => text::Anchor::BottomRight,
+                other => {
+                    log::error!("unknown text anchor :{}", other);
+                    return sail::errcode_init(_reg, sail::SlErrCode::ErrorUnknown);
+                }
+            };
+
+            let text = text::Text {
+                string: sail::string_get(string).to_string(),
+                position: [position[0], position[1]],
+                size: sail::f32_get(size),
+                anchor,
+                rotation: sail::f32_get(rotation),
+            };
+            let id = engine.add_text(text, [color[0], color[1], color[2], color[3]]);
+
+            return sail::i64_init(_reg, id as i64);
+        }
+
+        "view-set" 2 [eng_ptr, view] {
+            assert_eq!(sail::get_cfg_spec(eng_ptr), sail::Cfg::B8Other);
+            let engine = unsafe {
+                &mut **(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut *mut dyn Renderer)
+            };
+
+            engine.set_camera(camera::Camera::from_slice(&f32_vec(view)));
+
+            return sail::nil();
+        }
+
+        "view-fit" 1 [eng_ptr] {
+            assert_eq!(sail::get_cfg_spec(eng_ptr), sail::Cfg::B8Other);
+            let engine = unsafe {
+                &mut **(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut *mut dyn Renderer)
+            };
+
+            // the new view is returned, so it can be shared with other
+            // threads; there is none to give when nothing is drawn
+            return match engine.fit_camera() {
+                Some(camera) => sail::arrvec_init(_reg, sail::T_F32.0, 3, &camera.to_array()),
+                None => sail::nil(),
+            };
+        }
+
+        "layer-set" 2 [eng_ptr, layer] {
+            assert_eq!(sail::get_cfg_spec(eng_ptr), sail::Cfg::B8Other);
+            let engine = unsafe {
+                &mut **(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut *mut dyn Renderer)
+            };
+
+            assert_eq!(sail::core_type(layer), Some(sail::CoreType::I64));
+
+            engine.scene_mut().layer = sail::i64_get(layer) as i32;
+
+            return sail::nil();
+        }
+
+        "obj-next" 2 [eng_ptr, id] {
+            assert_eq!(sail::get_cfg_spec(eng_ptr), sail::Cfg::B8Other);
+            let engine = unsafe {
+                &mut **(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut *mut dyn Renderer)
+            };
+
+            // the next shape added replaces any object with this ID
+            engine.scene_mut().choose_id(object_id(id));
+
+            return sail::nil();
+        }
+
+        "obj-del" 2 [eng_ptr, id] {
+            assert_eq!(sail::get_cfg_spec(eng_ptr), sail::Cfg::B8Other);
+            let engine = unsafe {
+                &mut **(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut *mut dyn Renderer)
+            };
+
+            // gives whether every object named was there to remove
+            let scene = engine.scene_mut();
+            let removed = object_ids(id).into_iter().all(|id| scene.remove(id).is_some());
+
+            return sail::bool_init(_reg, removed);
+        }
+
+        "obj-color" 3 [eng_ptr, id, color] {
+            assert_eq!(sail::get_cfg_spec(eng_ptr), sail::Cfg::B8Other);
+            let engine = unsafe {
+                &mut **(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut *mut dyn Renderer)
+            };
+
+            // colors without alpha are opaque
+            let mut color = f32_vec(color);
+            assert!(color.len() == 3 || color.len() == 4);
+            color.resize(4, 1.0);
+
+            let color = [color[0], color[1], color[2], color[3]];
+            let scene = engine.scene_mut();
+            let found = object_ids(id)
+                .into_iter()
+                .all(|id| scene.update(id, |object| object.style.color = color));
+
+            return sail::bool_init(_reg, found);
+        }
+
+        "obj-layer" 3 [eng_ptr, id, layer] {
+            assert_eq!(sail::get_cfg_spec(eng_ptr), sail::Cfg::B8Other);
+            let engine = unsafe {
+                &mut **(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut *mut dyn Renderer)
+            };
+
+            assert_eq!(sail::core_type(layer), Some(sail::CoreType::I64));
+            let layer = sail::i64_get(layer) as i32;
+
+            let scene = engine.scene_mut();
+            let found = object_ids(id)
+                .into_iter()
+                .all(|id| scene.update(id, |object| object.layer = layer));
+
+            return sail::bool_init(_reg, found);
+        }
+
+        "obj-style" 2 [eng_ptr, id] {
+            assert_eq!(sail::get_cfg_spec(eng_ptr), sail::Cfg::B8Other);
+            let engine = unsafe {
+                &mut **(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut *mut dyn Renderer)
+            };
+
+            // takes the stroke last set by line-style
+            let scene = engine.scene_mut();
+            let stroke = scene.stroke.clone();
+            let found = object_ids(id).into_iter().all(|id| {
+                scene.update(id, |object| object.style.stroke = stroke.clone())
+            });
+
+            return sail::bool_init(_reg, found);
+        }
+
+        "obj-ids" 1 [eng_ptr] {
+            assert_eq!(sail::get_cfg_spec(eng_ptr), sail::Cfg::B8Other);
+            let engine = unsafe {
+                &mut **(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut *mut dyn Renderer)
+            };
+
+            let ids: Vec<scene::Id> = engine.scene().iter().map(|(_, id, _)| id).collect();
+
+            return ids_init(_reg, &ids);
+        }
+
+        "obj-move" 3 [eng_ptr, id, offset] {
+            assert_eq!(sail::get_cfg_spec(eng_ptr), sail::Cfg::B8Other);
+            let engine = unsafe {
+                &mut **(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut *mut dyn Renderer)
+            };
+
+            let offset = f32_vec(offset);
+            assert_eq!(offset.len(), 2);
+            let offset = [offset[0], offset[1]];
+
+            let scene = engine.scene_mut();
+            let found = object_ids(id)
+                .into_iter()
+                .all(|id| scene.update(id, |object| object.geometry.translate(offset)));
+
+            return sail::bool_init(_reg, found);
+        }
+
+        "hit" 3 [eng_ptr, point, tolerance] {
+            assert_eq!(sail::get_cfg_spec(eng_ptr), sail::Cfg::B8Other);
+            let engine = unsafe {
+                &mut **(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut *mut dyn Renderer)
+            };
+
+            let point = f32_vec(point);
+            assert_eq!(point.len(), 2);
+
+            return match engine.hit([point[0], point[1]], sail::f32_get(tolerance)) {
+                Some(id) => sail::i64_init(_reg, id as i64),
+                None => sail::nil(),
+            };
+        }
+
+        "enclosed" 2 [eng_ptr, corners] {
+            assert_eq!(sail::get_cfg_spec(eng_ptr), sail::Cfg::B8Other);
+            let engine = unsafe {
+                &mut **(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut *mut dyn Renderer)
+            };
+
+            let c = f32_vec(corners);
+            assert_eq!(c.len(), 4);
+
+            return ids_init(_reg, &engine.enclosed([c[0], c[1], c[2], c[3]]));
+        }
+
+        "pick" 3 [eng_ptr, corners, tolerance] {
+            assert_eq!(sail::get_cfg_spec(eng_ptr), sail::Cfg::B8Other);
+            let engine = unsafe {
+                &mut **(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut *mut dyn Renderer)
+            };
+
+            // corners are where a drag began and ended; a drag too short
+            // to be a box picks what lies under its end
+            let c = f32_vec(corners);
+            assert_eq!(c.len(), 4);
+            let picked = engine.pick([c[0], c[1], c[2], c[3]], sail::f32_get(tolerance));
+
+            return ids_init(_reg, &picked);
+        }
+
+        "sel-set" 2 [eng_ptr, id] {
+            assert_eq!(sail::get_cfg_spec(eng_ptr), sail::Cfg::B8Other);
+            let engine = unsafe {
+                &mut **(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut *mut dyn Renderer)
+            };
+
+            // anything but IDs, like nil, selects nothing
+            let ids = match sail::core_type(id) {
+                Some(sail::CoreType::I64) | Some(sail::CoreType::VecArr) => object_ids(id),
+                _ => vec![],
+            };
+            engine.scene_mut().select(ids);
+
+            return ids_init(_reg, &engine.scene().selection());
+        }
+
+        "sel-get" 1 [eng_ptr] {
+            assert_eq!(sail::get_cfg_spec(eng_ptr), sail::Cfg::B8Other);
+            let engine = unsafe {
+                &mut **(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut *mut dyn Renderer)
+            };
+
+            return ids_init(_reg, &engine.scene().selection());
+        }
+
         "pop-line" 1 [eng_ptr] {
             assert_eq!(sail::get_cfg_spec(eng_ptr), sail::Cfg::B8Other);
             let engine = unsafe {
-                &mut *(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut Engine<backend::Backend>)
+                &mut **(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut *mut dyn Renderer)
             };
 
-            engine.lines.pop();
-            engine.colors.pop();
+            engine.pop_line();
 
             return sail::nil();
         }
@@ -145,7 +719,7 @@ pub fn render_loop(
         "bg-col" 4 [eng_ptr, r, g, b] {
             assert_eq!(sail::get_cfg_spec(eng_ptr), sail::Cfg::B8Other);
             let engine = unsafe {
-                &mut *(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut Engine<backend::Backend>)
+                &mut **(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut *mut dyn Renderer)
             };
 
             engine.set_clear([
@@ -161,66 +735,354 @@ pub fn render_loop(
         "clear" 1 [eng_ptr] {
             assert_eq!(sail::get_cfg_spec(eng_ptr), sail::Cfg::B8Other);
             let engine = unsafe {
-                &mut *(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut Engine<backend::Backend>)
+                &mut **(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut *mut dyn Renderer)
             };
 
-            engine.empty_lines();
+            engine.empty_shapes();
 
             return sail::nil();
         }
+
+        "snapshot" 2 [eng_ptr, path] {
+            assert_eq!(sail::get_cfg_spec(eng_ptr), sail::Cfg::B8Other);
+            let engine = unsafe {
+                &mut **(sail::read_field_unchecked::<u64>(eng_ptr, 0) as *mut *mut dyn Renderer)
+            };
+
+            assert_eq!(sail::core_type(path), Some(sail::CoreType::VecStr));
+
+            return match engine.snapshot(sail::string_get(path)) {
+                Ok(()) => sail::nil(),
+                Err(err) => {
+                    log::error!("{}", err);
+                    sail::errcode_init(_reg, sail::SlErrCode::FileCouldNotWrite)
+                }
+            };
+        }
     }
 
     sail::insert_native_procs(sl_reg, sl_tbl, sl_env, rndr_fns);
+}
 
-    engine.setup();
-    engine.set_clear([1.0, 1.0, 1.0, 1.0]);
+/// Reads the elements of a Sail array vector of `f32`
+fn f32_vec(vec: *mut SlHead) -> Vec<f32> {
+    assert_eq!(sail::core_type(vec), Some(sail::CoreType::VecArr));
+    assert_eq!(sail::arrvec_get_typ(vec), sail::T_F32.0);
 
-    let prog_txt = &std::fs::read_to_string("scripts/rndr.sl").unwrap();
-    let prog_expr = sail::parser::parse(sl_reg, sl_tbl, prog_txt).unwrap();
+    let len = sail::arrvec_get_len(vec) as usize;
 
-    let mut stack = sail::eval::EvalStack::new(10000);
+    (0..len)
+        .map(|i| unsafe {
+            std::ptr::read_unaligned(sail::value_ptr(vec).add(8 + 4 * i) as *const f32)
+        })
+        .collect()
+}
 
-    let sigil = 1 as *mut SlHead;
+/// Reads an object ID given as a Sail `i64`
+fn object_id(id: *mut SlHead) -> scene::Id {
+    assert_eq!(sail::core_type(id), Some(sail::CoreType::I64));
+    sail::i64_get(id) as scene::Id
+}
 
-    let mut ret_slot = sigil;
-    let ret_addr: *mut *mut SlHead = &mut ret_slot;
+/// Reads object IDs given as one Sail `i64` or an array vector of them
+fn object_ids(ids: *mut SlHead) -> Vec<scene::Id> {
+    if sail::core_type(ids) == Some(sail::CoreType::I64) {
+        return vec![object_id(ids)];
+    }
 
-    stack.start(ret_addr, sl_env, prog_expr);
+    assert_eq!(sail::core_type(ids), Some(sail::CoreType::VecArr));
+    assert_eq!(sail::arrvec_get_typ(ids), sail::T_I64.0);
 
-    while ret_slot == sigil {
-        stack.iter_once(sl_reg, sl_tbl);
+    let len = sail::arrvec_get_len(ids) as usize;
+
+    (0..len)
+        .map(|i| unsafe {
+            let id = sail::value_ptr(ids).add(8 + 8 * i) as *const i64;
+            std::ptr::read_unaligned(id) as scene::Id
+        })
+        .collect()
+}
+
+/// Makes a Sail array vector of `i64` holding object IDs
+fn ids_init(reg: *mut sail::memmgt::Region, ids: &[scene::Id]) -> *mut SlHead {
+    let ids: Vec<i64> = ids.iter().map(|id| *id as i64).collect();
+    sail::arrvec_init(reg, sail::T_I64.0, ids.len() as u32, &ids)
+}
+
+/// Reads the name of a Sail keyword, without its colon
+fn keyword(tbl: *mut SlHead, sym: *mut SlHead) -> &'static str {
+    assert_eq!(sail::core_type(sym), Some(sail::CoreType::Symbol));
+
+    let id = sail::sym_get_id(sym);
+    assert_eq!(sail::mode_of_sym(id), sail::SymbolMode::Keyword);
+
+    sail::string_get(sail::sym_tab_lookup_id_num(tbl, sail::demodes_sym(id)))
+}
+
+/// Runs a Sail script with an offscreen engine bound to `engine`, then
+/// writes what it drew to a PNG file
+///
+/// Nothing is shown on screen, so this works without a window system;
+/// with `software` set it also works without a graphics driver.
+pub fn render_to_png(
+    name: &str,
+    size: [u32; 2],
+    script: &str,
+    out: &str,
+    zone_size: usize,
+    software: bool,
+) -> Result<(), String> {
+    let text = std::fs::read_to_string(script).map_err(|_| format!("could not read {}", script))?;
+
+    if software {
+        let mut engine = soft::SoftEngine::new(size[0], size[1]);
+        render_script(&mut engine, script, &text, out, zone_size)
+    } else {
+        let mut engine: Engine<backend::Backend> =
+            Engine::new(GraphicsState::headless(name, size[0], size[1]));
+        engine.setup();
+        render_script(&mut engine, script, &text, out, zone_size)
     }
+}
 
-    let rndr = sail::env_lookup_by_id(sl_env, sail::S_RNDR.0);
+/// Evaluates a script's text with `engine` bound, then saves a snapshot
+fn render_script(
+    engine: &mut (dyn Renderer + 'static),
+    script: &str,
+    text: &str,
+    out: &str,
+    zone_size: usize,
+) -> Result<(), String> {
+    let sl_reg = unsafe { sail::memmgt::acquire_mem_region(zone_size) };
+    let (sl_tbl, sl_env) =
+        sail::environment_boot(sl_reg).map_err(|err| format!("could not boot: {:?}", err))?;
+    sail::argv_set(sl_reg, sl_env, &[script.to_string()]);
 
-    stack.push_frame_head(ret_addr, sail::eval::Opcode::Apply, sl_env);
-    stack.push(rndr);
+    engine.set_clear([1.0, 1.0, 1.0, 1.0]);
+    engine_bind(engine, sl_reg, sl_tbl, sl_env);
 
-    loop {
-        if engine.should_configure_swapchain {
-            engine.state.config_swapchain();
-            engine.draw_frame();
-            engine.should_configure_swapchain = false;
+    let expr = sail::parser::parse_source(sl_reg, sl_tbl, text, script)
+        .map_err(|err| format!("{}: {:?}", script, err))?;
+    let result = sail::eval::eval(sl_reg, sl_tbl, sl_env, expr);
+
+    if sail::core_type(result) == Some(sail::CoreType::ErrCode) {
+        return Err(format!("{}: {}", script, sail::context(sl_tbl, result)));
+    }
+
+    engine.snapshot(out)
+}
+
+/// Drawing operations an engine provides to Sail; implemented by the
+/// gfx-hal engine and by the software renderer
+///
+/// Everything drawn is an object in the engine's scene. Adding a shape
+/// gives it the current stroke and layer, and returns its ID.
+pub trait Renderer {
+    /// Set the clear (background) color
+    fn set_clear(&mut self, clear: [f32; 4]);
+    /// The objects drawn
+    fn scene(&self) -> &scene::Scene;
+    /// The objects drawn, to be changed; the engine redraws what the
+    /// changes affect on the next frame
+    fn scene_mut(&mut self) -> &mut scene::Scene;
+    /// Add a line, with two endpoints and a color
+    fn add_line(&mut self, points: [f32; 4], color: [f32; 3]) -> scene::Id {
+        let color = [color[0], color[1], color[2], 1.0];
+        self.scene_mut().add(scene::Geometry::Line(points), color)
+    }
+    /// Add a filled triangle, with three corners and a color with alpha
+    fn add_triangle(&mut self, points: [f32; 6], color: [f32; 4]) -> scene::Id {
+        self.add_fill(vec![points], color)
+    }
+    /// Add a fill made of triangles, such as a triangulated polygon, as
+    /// one object
+    fn add_fill(&mut self, triangles: Vec<[f32; 6]>, color: [f32; 4]) -> scene::Id {
+        self.scene_mut()
+            .add(scene::Geometry::Fill(triangles), color)
+    }
+    /// Add a round point, with a center, a diameter in pixels, and a
+    /// color with alpha
+    fn add_point(&mut self, point: [f32; 2], size: f32, color: [f32; 4]) -> scene::Id {
+        let geometry = scene::Geometry::Point {
+            center: point,
+            size,
+        };
+        self.scene_mut().add(geometry, color)
+    }
+    /// Add a curve, drawn with lines of a color; it is flattened again
+    /// whenever the view changes, so stays smooth at any scale
+    fn add_curve(&mut self, curve: tess::Curve, color: [f32; 3]) -> scene::Id {
+        let color = [color[0], color[1], color[2], 1.0];
+        self.scene_mut().add(scene::Geometry::Curve(curve), color)
+    }
+    /// Set the width, dashes, caps, and joins of lines and curves added
+    /// after this
+    fn set_stroke(&mut self, stroke: tess::Stroke) {
+        self.scene_mut().stroke = stroke;
+    }
+    /// Add a string in a color with alpha; within a layer, text lies
+    /// above every other shape
+    fn add_text(&mut self, text: text::Text, color: [f32; 4]) -> scene::Id {
+        self.scene_mut().add(scene::Geometry::Text(text), color)
+    }
+    /// Show the world through a camera; until one is set, drawing
+    /// coordinates are normalized device coordinates
+    fn set_camera(&mut self, camera: camera::Camera);
+    /// Pixels per unit along x and y in the current view
+    fn view_scale(&self) -> [f32; 2];
+    /// The object nearest a point, if one is drawn within `tolerance`
+    /// pixels of it; the cursor's shape is never hit
+    fn hit(&mut self, at: [f32; 2], tolerance: f32) -> Option<scene::Id>;
+    /// Objects drawn wholly within a box, given by two opposite corners
+    fn enclosed(&mut self, corners: [f32; 4]) -> Vec<scene::Id>;
+    /// Select what a drag from one corner to another picks out, and
+    /// return the selection: the object hit at its end, if it moved no
+    /// further than `tolerance` pixels, or else those boxed in
+    fn pick(&mut self, corners: [f32; 4], tolerance: f32) -> Vec<scene::Id> {
+        let scale = self.view_scale();
+        let drag = [
+            (corners[2] - corners[0]) * scale[0],
+            (corners[3] - corners[1]) * scale[1],
+        ];
+
+        let picked = if drag[0].hypot(drag[1]) <= tolerance {
+            self.hit([corners[2], corners[3]], tolerance)
+                .into_iter()
+                .collect()
+        } else {
+            self.enclosed(corners)
+        };
+
+        self.scene_mut().select(picked);
+        self.scene().selection()
+    }
+    /// Center the camera on everything drawn, zoomed to fit it, and
+    /// return it; gives none if nothing is drawn
+    fn fit_camera(&mut self) -> Option<camera::Camera>;
+    /// Remove the line added last
+    fn pop_line(&mut self) {
+        let scene = self.scene_mut();
+        let last = scene.newest(|object| matches!(object.geometry, scene::Geometry::Line(_)));
+
+        if let Some(id) = last {
+            scene.remove(id);
         }
+    }
+    /// Empty the engine of every object
+    fn empty_shapes(&mut self) {
+        self.scene_mut().clear();
+    }
+    /// Change the size of the frame drawn into
+    fn resize(&mut self, width: u32, height: u32);
+    /// Draw a single frame according to the engine state
+    fn draw_frame(&mut self);
+    /// Draw the engine state and save it as a PNG file
+    fn snapshot(&mut self, path: &str) -> Result<(), String>;
+}
 
-        stack.iter_once(sl_reg, sl_tbl);
+/// Kinds of primitive the engine draws, each with its own pipeline and
+/// vertex buffer; within a layer they are drawn in this order, so fills
+/// lie beneath lines, lines beneath points, and points beneath text
+#[derive(Clone, Copy)]
+enum Shape {
+    Triangle,
+    Line,
+    /// Lines flattened from curves and styled lines, kept apart from
+    /// the others as they are replaced whenever the view changes
+    Curve,
+    /// Triangles covering strokes wider than a pixel, replaced along
+    /// with the curve lines
+    Stroke,
+    Point,
+    /// Triangles covering glyphs, textured from the atlas; laid out
+    /// again along with the curves
+    Text,
+}
 
-        if stack.is_empty() {
-            println!("render thread ended");
-            break;
+impl Shape {
+    const ALL: [Shape; 6] = [
+        Shape::Triangle,
+        Shape::Line,
+        Shape::Curve,
+        Shape::Stroke,
+        Shape::Point,
+        Shape::Text,
+    ];
+}
+
+/// Point of a shape as stored in the vertex buffers
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Vertex {
+    position: [f32; 2],
+    color: [f32; 4],
+    /// Diameter in pixels, for points
+    size: f32,
+    /// Place in the glyph atlas, for text
+    uv: [f32; 2],
+}
+
+/// Vertices of one kind of shape, and the state of their buffer
+struct Batch {
+    /// Vertices of each object in the scene's drawing order
+    vertices: Vec<Vertex>,
+    /// Place of each object with vertices here, and where they start
+    starts: Vec<(scene::Key, usize)>,
+    /// Leading vertices already in the buffer and unchanged since
+    uploaded: usize,
+    buflen: u64,
+    /// Buffer memory, mapped for as long as the buffer lives
+    map: *mut u8,
+}
+
+impl Batch {
+    fn new() -> Self {
+        Self {
+            vertices: vec![],
+            starts: vec![],
+            uploaded: 0,
+            buflen: 256,
+            map: std::ptr::null_mut(),
         }
     }
+    /// Drop the vertices of every object from a place in the drawing
+    /// order on
+    fn cut(&mut self, from: scene::Key) {
+        let keep = self.starts.partition_point(|(key, _)| *key < from);
+        let len = match self.starts.get(keep) {
+            Some((_, start)) => *start,
+            None => self.vertices.len(),
+        };
 
-    // TODO: dispose of Sail environment first (reverse creation order)
-    drop(engine);
+        self.starts.truncate(keep);
+        self.vertices.truncate(len);
+        self.uploaded = self.uploaded.min(len);
+    }
+    /// Vertices of the objects in one layer
+    fn layer(&self, layer: i32) -> std::ops::Range<usize> {
+        let at = |i: usize| match self.starts.get(i) {
+            Some((_, start)) => *start,
+            None => self.vertices.len(),
+        };
+
+        let first = self.starts.partition_point(|(key, _)| key.0 < layer);
+        let last = self.starts.partition_point(|(key, _)| key.0 <= layer);
+
+        at(first)..at(last)
+    }
 }
 
 /// Sail-specific graphics engine state
 pub struct Engine<B: gfx_hal::Backend> {
     clear: [f32; 4],
-    lines: Vec<[f32; 4]>,
-    colors: Vec<[f32; 3]>,
-    buflen: u64,
+    batches: [Batch; 6],
+    scene: scene::Scene,
+    /// Glyphs the text batch is textured from
+    atlas: text::Atlas,
+    camera: Option<camera::Camera>,
+    /// Pixels per unit along x and y the curve, stroke, and text
+    /// batches were made at
+    batch_scale: [f32; 2],
     state: GraphicsState<B>,
     should_configure_swapchain: bool,
 }
@@ -230,105 +1092,471 @@ impl<B: gfx_hal::Backend> Engine<B> {
     fn new(state: GraphicsState<B>) -> Self {
         Self {
             clear: [0.0, 0.0, 0.0, 1.0],
-            lines: vec![],
-            colors: vec![],
-            buflen: 256,
+            batches: [
+                Batch::new(),
+                Batch::new(),
+                Batch::new(),
+                Batch::new(),
+                Batch::new(),
+                Batch::new(),
+            ],
+            scene: scene::Scene::new(),
+            atlas: text::Atlas::new(),
+            camera: None,
+            batch_scale: [0.0, 0.0],
             state,
             should_configure_swapchain: true,
         }
     }
-    /// Set the clear (background) color
-    fn set_clear(&mut self, clear: [f32; 4]) {
-        self.clear = clear;
-    }
-    /// Add a line, with two endpoints and a color
-    fn add_line(&mut self, points: [f32; 4], color: [f32; 3]) {
-        self.lines.push(points);
-        self.colors.push(color);
-        self.buffer_size_check();
-    }
-    /// Empty the engine of all lines
-    fn empty_lines(&mut self) {
-        self.lines.clear();
-        self.colors.clear();
-    }
-    /// Set up an appropriate graphics pipeline for the engine
+    /// Set up a graphics pipeline for each kind of shape
     fn state_pipeline_setup(&mut self) {
+        // only text reads the atlas, but one layout serves every shape
+        let atlas = AtlasImage::new(&mut self.state);
         let pipeline_layout = unsafe {
             self.state
                 .device
                 .create_pipeline_layout(
-                    iter::empty(),
-                    iter::once((ShaderStageFlags::FRAGMENT, 0..12)),
+                    iter::once(&atlas.set_layout),
+                    iter::once((ShaderStageFlags::VERTEX, 0..size_of::<[f32; 4]>() as u32)),
+                )
+                .unwrap()
+        };
+        self.state.atlas = Some(atlas);
+
+        let shape_shaders = (
+            include_str!("shaders/shapes.vert"),
+            include_str!("shaders/shapes.frag"),
+        );
+        let point_shaders = (
+            include_str!("shaders/points.vert"),
+            include_str!("shaders/points.frag"),
+        );
+        let text_shaders = (
+            include_str!("shaders/text.vert"),
+            include_str!("shaders/text.frag"),
+        );
+
+        for shape in Shape::ALL.iter() {
+            let ((vertex_shader, fragment_shader), primitive) = match shape {
+                Shape::Triangle | Shape::Stroke => (shape_shaders, Primitive::TriangleList),
+                Shape::Line | Shape::Curve => (shape_shaders, Primitive::LineList),
+                Shape::Point => (point_shaders, Primitive::PointList),
+                Shape::Text => (text_shaders, Primitive::TriangleList),
+            };
+
+            let pipeline = unsafe {
+                self.state.make_pipeline(
+                    &pipeline_layout,
+                    vertex_shader,
+                    fragment_shader,
+                    primitive,
                 )
+            };
+
+            self.state.pipelines.push(pipeline);
+        }
+
+        self.state.pipeline_layouts.push(pipeline_layout);
+    }
+    /// Acquire memory and create a buffer for one kind of shape's
+    /// vertex data, replacing the buffer it had before
+    fn state_buffer_setup(&mut self, shape: Shape) {
+        let s = shape as usize;
+
+        // coherent memory needs no flushing, so it can stay mapped and
+        // be written a piece at a time
+        let (mut memory, buffer) = unsafe {
+            self.state.make_buffer(
+                self.batches[s].buflen,
+                gfx_hal::buffer::Usage::VERTEX,
+                gfx_hal::memory::Properties::CPU_VISIBLE | gfx_hal::memory::Properties::COHERENT,
+            )
+        };
+
+        self.batches[s].map = unsafe {
+            self.state
+                .device
+                .map_memory(&mut memory, Segment::ALL)
                 .unwrap()
         };
+        self.batches[s].uploaded = 0;
+
+        if s < self.state.vertex_buffers.len() {
+            unsafe {
+                // scene changes are built into the batches before a frame
+                // resets the fence, so the last submission is all this
+                // waits on; a timeout would mean freeing a buffer in use
+                let done = self
+                    .state
+                    .device
+                    .wait_for_fence(
+                        self.state.submission_complete_fence.as_ref().unwrap(),
+                        1_000_000_000,
+                    )
+                    .unwrap();
+                assert!(done, "vertex buffer replaced while a frame was being drawn");
+
+                let mut old_memory = std::mem::replace(&mut self.state.vertex_memory[s], memory);
+                let old_buffer = std::mem::replace(&mut self.state.vertex_buffers[s], buffer);
+
+                self.state.device.unmap_memory(&mut old_memory);
+                self.state.device.free_memory(old_memory);
+                self.state.device.destroy_buffer(old_buffer);
+            }
+        } else {
+            self.state.vertex_memory.push(memory);
+            self.state.vertex_buffers.push(buffer);
+        }
+    }
+    /// Check whether a shape's buffer has enough space for all its
+    /// vertices, growing it if not
+    fn buffer_size_check(&mut self, shape: Shape) {
+        let batch = &mut self.batches[shape as usize];
+        let vertex_vec_size = (size_of::<Vertex>() * batch.vertices.len()) as u64;
+
+        if vertex_vec_size > batch.buflen {
+            while vertex_vec_size > batch.buflen {
+                batch.buflen *= 2;
+            }
+            self.state_buffer_setup(shape);
+        }
+    }
+    /// Add one object's vertices of one kind of shape, all of one color
+    /// and size
+    fn add_vertices(
+        &mut self,
+        shape: Shape,
+        key: scene::Key,
+        points: &[f32],
+        color: [f32; 4],
+        size: f32,
+    ) {
+        let batch = &mut self.batches[shape as usize];
+        batch.starts.push((key, batch.vertices.len()));
+
+        for p in points.chunks(2) {
+            batch.vertices.push(Vertex {
+                position: [p[0], p[1]],
+                color,
+                size,
+                uv: [0.0, 0.0],
+            });
+        }
+    }
+    /// Add the glyphs of a string, laid out at the text batch's scale
+    fn add_glyphs(&mut self, key: scene::Key, text: &text::Text, color: [f32; 4]) {
+        let batch = &mut self.batches[Shape::Text as usize];
+        batch.starts.push((key, batch.vertices.len()));
+
+        for quad in self.atlas.layout(text, self.batch_scale) {
+            for (position, uv) in quad.triangles().iter() {
+                batch.vertices.push(Vertex {
+                    position: *position,
+                    color,
+                    size: 0.0,
+                    uv: *uv,
+                });
+            }
+        }
+    }
+    /// Add the lines or triangles of a curve, flattened and stroked at
+    /// the curve batch's scale
+    fn add_stroked(
+        &mut self,
+        key: scene::Key,
+        curve: &tess::Curve,
+        stroke: &tess::Stroke,
+        color: [f32; 4],
+    ) {
+        let stroked = stroke.apply(&curve.flatten(self.batch_scale), self.batch_scale);
+
+        self.add_vertices(Shape::Curve, key, &stroked.lines.concat(), color, 0.0);
+        self.add_vertices(Shape::Stroke, key, &stroked.triangles.concat(), color, 0.0);
+    }
+    /// Add the vertices of one object, in the color it is drawn in; if
+    /// `view_only`, only those of an object made anew when the view
+    /// changes
+    fn add_object(
+        &mut self,
+        key: scene::Key,
+        object: &scene::Object,
+        color: [f32; 4],
+        view_only: bool,
+    ) {
+        let style = &object.style;
+        let hairline = style.stroke == tess::Stroke::default();
+
+        match &object.geometry {
+            scene::Geometry::Line(points) if hairline => {
+                if !view_only {
+                    self.add_vertices(Shape::Line, key, points, color, 0.0);
+                }
+            }
+            scene::Geometry::Line(points) => {
+                let path = vec![[points[0], points[1]], [points[2], points[3]]];
+                let curve = tess::Curve::Polyline(path);
+                self.add_stroked(key, &curve, &style.stroke, color);
+            }
+            scene::Geometry::Fill(triangles) => {
+                if !view_only {
+                    let points = triangles.concat();
+                    self.add_vertices(Shape::Triangle, key, &points, color, 0.0);
+                }
+            }
+            scene::Geometry::Point { center, size } => {
+                if !view_only {
+                    self.add_vertices(Shape::Point, key, center, color, *size);
+                }
+            }
+            scene::Geometry::Curve(curve) => {
+                self.add_stroked(key, curve, &style.stroke, color);
+            }
+            scene::Geometry::Text(text) => {
+                self.add_glyphs(key, text, color);
+            }
+        }
+    }
+    /// Make the vertices of every object from a place in the drawing
+    /// order on again; if `view_only`, only the curves, strokes, and
+    /// text, which depend on the view
+    fn rebuild(&mut self, from: scene::Key, view_only: bool) {
+        let shapes: &[Shape] = if view_only {
+            &[Shape::Curve, Shape::Stroke, Shape::Text]
+        } else {
+            &Shape::ALL
+        };
+
+        for shape in shapes.iter() {
+            self.batches[*shape as usize].cut(from);
+        }
 
-        let vertex_shader = include_str!("shaders/lines.vert");
-        let fragment_shader = include_str!("shaders/lines.frag");
+        let scene = std::mem::take(&mut self.scene);
+        for (key, id, object) in scene.iter_from(from) {
+            self.add_object(key, object, scene.color(id, object), view_only);
+        }
+        self.scene = scene;
 
-        let pipeline = unsafe {
-            self.state.make_pipeline(
-                &pipeline_layout,
-                vertex_shader,
-                fragment_shader,
-                Primitive::LineList,
-            )
-        };
+        for shape in shapes.iter() {
+            self.buffer_size_check(*shape);
+        }
+    }
+    /// Bring the batches up to date with the scene, first flattening
+    /// curves and laying out text again if the view has changed
+    fn refresh(&mut self) {
+        let scale = self.view_scale();
+        if scale != self.batch_scale {
+            self.batch_scale = scale;
+            self.rebuild(scene::FIRST, true);
+        }
 
-        self.state.pipeline_layouts.push(pipeline_layout);
-        self.state.pipelines.push(pipeline);
+        if let Some(from) = self.scene.take_changed() {
+            self.rebuild(from, false);
+        }
+    }
+    /// Prepare the engine to accept draw calls
+    fn setup(&mut self) {
+        for shape in Shape::ALL.iter() {
+            self.state_buffer_setup(*shape);
+        }
+        self.state_pipeline_setup();
     }
-    /// Acquire memory and create buffer for vertex data
-    fn state_buffer_setup(&mut self) {
+    /// Wait for the last submission, then make the command buffer ready
+    /// for reuse
+    fn begin_frame(&mut self, timeout_ns: u64) {
         unsafe {
             self.state
                 .device
                 .wait_for_fence(
                     self.state.submission_complete_fence.as_ref().unwrap(),
-                    1_000_000_000,
+                    timeout_ns,
                 )
                 .unwrap();
+            self.state
+                .device
+                .reset_fence(self.state.submission_complete_fence.as_mut().unwrap())
+                .unwrap();
 
-            for mem in self.state.vertex_memory.drain(..) {
-                self.state.device.free_memory(mem);
-            }
+            self.state.command_pool.as_mut().unwrap().reset(false);
+        }
+    }
+    /// Copy vertices changed since the last upload into the vertex
+    /// buffers, and stage any new glyphs for the atlas image
+    ///
+    /// The batches must already be up to date with the scene: growing a
+    /// vertex buffer waits for the last submission, so `refresh` comes
+    /// before `begin_frame` resets the fence that would signal it.
+    fn upload_vertices(&mut self) {
+        let vertex_len = size_of::<Vertex>();
+
+        if self.atlas.take_changed() {
+            let pixels = self.atlas.pixels();
+            self.state.atlas.as_mut().unwrap().stage(pixels);
+        }
 
-            for buf in self.state.vertex_buffers.drain(..) {
-                self.state.device.destroy_buffer(buf);
+        for batch in self.batches.iter_mut() {
+            if batch.uploaded < batch.vertices.len() {
+                unsafe {
+                    std::ptr::copy_nonoverlapping(
+                        batch.vertices[batch.uploaded..].as_ptr() as *const u8,
+                        batch.map.add(batch.uploaded * vertex_len),
+                        (batch.vertices.len() - batch.uploaded) * vertex_len,
+                    );
+                }
             }
+
+            batch.uploaded = batch.vertices.len();
         }
+    }
+    /// Record a render pass drawing every shape into a swapchain image,
+    /// or into the offscreen target if none is given; the command
+    /// buffer is left open for further commands
+    unsafe fn record_shapes(&mut self, surface_view: Option<&B::ImageView>) {
+        let state = &mut self.state;
+
+        let (render_pass, framebuffer, image_view, extent) = match surface_view {
+            Some(view) => (
+                &state.render_passes[0],
+                state.framebuffer.as_ref().unwrap(),
+                view,
+                state.surface_extent,
+            ),
+            None => {
+                let target = state.offscreen.as_ref().unwrap();
+                (
+                    state.render_passes.last().unwrap(),
+                    &target.framebuffer,
+                    &target.view,
+                    target.extent,
+                )
+            }
+        };
 
-        let (memory, buffer) = unsafe {
-            self.state.make_buffer(
-                self.buflen,
-                gfx_hal::buffer::Usage::VERTEX,
-                gfx_hal::memory::Properties::CPU_VISIBLE,
-            )
+        let buffer = &mut state.command_buffers[0];
+
+        let viewport = Viewport {
+            rect: Rect {
+                x: 0,
+                y: 0,
+                w: extent.width as i16,
+                h: extent.height as i16,
+            },
+            depth: 0.0..1.0,
         };
 
-        self.state.vertex_memory.push(memory);
-        self.state.vertex_buffers.push(buffer);
-    }
-    /// Check whether the buffer has enough space for all vertices
-    fn buffer_size_check(&mut self) {
-        let line_vec_size = size_of::<[f32; 4]>() * self.lines.len();
+        buffer.begin_primary(CommandBufferFlags::ONE_TIME_SUBMIT);
+
+        // new glyphs reach the atlas before anything is drawn with it
+        let atlas = state.atlas.as_mut().unwrap();
+        atlas.record_upload(buffer);
+
+        // the view transform is shared by every pipeline, as they share
+        // a layout
+        let view = match &self.camera {
+            Some(camera) => camera.transform([extent.width as f32, extent.height as f32]),
+            None => [1.0, 1.0, 0.0, 0.0],
+        };
+        let view: Vec<u32> = view.iter().map(|v| v.to_bits()).collect();
+
+        buffer.set_viewports(0, iter::once(viewport.clone()));
+        buffer.set_scissors(0, iter::once(viewport.rect));
+
+        buffer.begin_render_pass(
+            render_pass,
+            framebuffer,
+            viewport.rect,
+            iter::once(RenderAttachmentInfo {
+                image_view,
+                clear_value: ClearValue {
+                    color: ClearColor {
+                        float32: self.clear,
+                    },
+                },
+            }),
+            SubpassContents::Inline,
+        );
+
+        buffer.push_graphics_constants(
+            &state.pipeline_layouts[0],
+            ShaderStageFlags::VERTEX,
+            0,
+            &view,
+        );
 
-        if line_vec_size as u64 >= self.buflen {
-            self.buflen = 2 * self.buflen;
-            self.state_buffer_setup();
+        // every kind of shape in a layer is drawn before the next layer
+        for layer in self.scene.layers() {
+            for shape in Shape::ALL.iter() {
+                let s = *shape as usize;
+                let range = self.batches[s].layer(layer);
+
+                if range.is_empty() {
+                    continue;
+                }
+
+                buffer.bind_graphics_pipeline(&state.pipelines[s]);
+
+                if let Shape::Text = shape {
+                    buffer.bind_graphics_descriptor_sets(
+                        &state.pipeline_layouts[0],
+                        0,
+                        iter::once(&atlas.set),
+                        iter::empty(),
+                    );
+                }
+
+                buffer.bind_vertex_buffers(
+                    0,
+                    iter::once((
+                        &state.vertex_buffers[s],
+                        gfx_hal::buffer::SubRange {
+                            offset: 0,
+                            size: Some((size_of::<Vertex>() * range.end) as u64),
+                        },
+                    )),
+                );
+
+                buffer.draw(range.start as u32..range.end as u32, 0..1);
+            }
         }
+
+        buffer.end_render_pass();
     }
-    /// Prepare the engine to accept draw calls
-    fn setup(&mut self) {
-        self.state_buffer_setup();
-        self.state_pipeline_setup();
-    }
-    /// Draw a single frame according to the engine state
-    fn draw_frame(&mut self) {
+    /// Draw the engine state offscreen and read back the pixels, as RGBA
+    /// rows from the top down
+    fn render_offscreen(&mut self) -> (Extent2D, Vec<u8>) {
         let timeout_ns = 1_000_000_000;
 
+        self.refresh();
+        self.begin_frame(timeout_ns);
+
+        // the target follows the frame size, so is remade after resizing
+        let stale = match &self.state.offscreen {
+            Some(target) => target.extent != self.state.surface_extent,
+            None => true,
+        };
+        if stale {
+            if let Some(target) = self.state.offscreen.take() {
+                target.destroy(&self.state.device);
+            }
+            self.state.offscreen = Some(Offscreen::new(&mut self.state));
+        }
+
+        self.upload_vertices();
+
         unsafe {
+            self.record_shapes(None);
+
+            let target = self.state.offscreen.as_ref().unwrap();
+            let buffer = &mut self.state.command_buffers[0];
+            target.record_readback(buffer);
+            buffer.finish();
+
+            self.state.queue_group.queues[0].submit(
+                iter::once(&self.state.command_buffers[0]),
+                iter::empty(),
+                iter::empty(),
+                self.state.submission_complete_fence.as_mut(),
+            );
+
             self.state
                 .device
                 .wait_for_fence(
@@ -336,14 +1564,73 @@ impl<B: gfx_hal::Backend> Engine<B> {
                     timeout_ns,
                 )
                 .unwrap();
-            self.state
-                .device
-                .reset_fence(self.state.submission_complete_fence.as_mut().unwrap())
-                .unwrap();
+        }
 
-            self.state.command_pool.as_mut().unwrap().reset(false);
+        let target = self.state.offscreen.as_mut().unwrap();
+        (target.extent, target.read_pixels(&self.state.device))
+    }
+}
+
+impl<B: gfx_hal::Backend> Renderer for Engine<B> {
+    fn set_clear(&mut self, clear: [f32; 4]) {
+        self.clear = clear;
+    }
+    fn scene(&self) -> &scene::Scene {
+        &self.scene
+    }
+    fn scene_mut(&mut self) -> &mut scene::Scene {
+        &mut self.scene
+    }
+    fn set_camera(&mut self, camera: camera::Camera) {
+        self.camera = Some(camera);
+    }
+    fn view_scale(&self) -> [f32; 2] {
+        let extent = self.state.surface_extent;
+
+        match &self.camera {
+            Some(camera) => [camera.zoom, camera.zoom],
+            None => [extent.width as f32 / 2.0, extent.height as f32 / 2.0],
+        }
+    }
+    fn hit(&mut self, at: [f32; 2], tolerance: f32) -> Option<scene::Id> {
+        let scale = self.view_scale();
+        self.scene.hit(at, tolerance, scale, &mut self.atlas)
+    }
+    fn enclosed(&mut self, corners: [f32; 4]) -> Vec<scene::Id> {
+        let scale = self.view_scale();
+        self.scene.enclosed(corners, scale, &mut self.atlas)
+    }
+    fn fit_camera(&mut self) -> Option<camera::Camera> {
+        let scale = self.view_scale();
+        let bounds = self.scene.bounds(scale, &mut self.atlas)?;
+        let extent = self.state.surface_extent;
+        let extent = [extent.width as f32, extent.height as f32];
+
+        let mut camera = self
+            .camera
+            .unwrap_or_else(|| camera::Camera::new([0.0, 0.0], extent[1] / 2.0));
+        camera.fit(bounds, extent);
+        self.camera = Some(camera);
+
+        Some(camera)
+    }
+    fn resize(&mut self, width: u32, height: u32) {
+        self.state.set_extent(width, height);
+        self.should_configure_swapchain = true;
+    }
+    // without a window there is nothing to present to, so this does
+    // nothing; `snapshot` draws the engine state when it is called
+    fn draw_frame(&mut self) {
+        let timeout_ns = 1_000_000_000;
+
+        if self.state.surface.is_none() {
+            return;
         }
 
+        self.refresh();
+
+        // a frame given up on here submits nothing, so it must not yet
+        // have reset the fence the next one waits on
         let surface_image = unsafe {
             match self
                 .state
@@ -365,96 +1652,12 @@ impl<B: gfx_hal::Backend> Engine<B> {
             }
         };
 
-        let line_vec_size = size_of::<[f32; 4]>() * self.lines.len();
-
-        if line_vec_size > 0 {
-            unsafe {
-                let mapped_memory = self
-                    .state
-                    .device
-                    .map_memory(&mut self.state.vertex_memory[0], Segment::ALL)
-                    .unwrap();
-
-                std::ptr::copy_nonoverlapping(
-                    self.lines.as_ptr() as *const u8,
-                    mapped_memory,
-                    line_vec_size,
-                );
-
-                self.state
-                    .device
-                    .flush_mapped_memory_ranges(iter::once((
-                        &self.state.vertex_memory[0],
-                        Segment::ALL,
-                    )))
-                    .unwrap();
-
-                self.state
-                    .device
-                    .unmap_memory(&mut self.state.vertex_memory[0]);
-            }
-        }
+        self.begin_frame(timeout_ns);
+        self.upload_vertices();
 
         unsafe {
-            let buffer = &mut self.state.command_buffers[0];
-
-            let viewport = Viewport {
-                rect: Rect {
-                    x: 0,
-                    y: 0,
-                    w: self.state.surface_extent.width as i16,
-                    h: self.state.surface_extent.height as i16,
-                },
-                depth: 0.0..1.0,
-            };
-
-            buffer.begin_primary(CommandBufferFlags::ONE_TIME_SUBMIT);
-
-            buffer.set_viewports(0, iter::once(viewport.clone()));
-            buffer.set_scissors(0, iter::once(viewport.rect));
-
-            buffer.begin_render_pass(
-                &self.state.render_passes[0],
-                &self.state.framebuffer.as_ref().unwrap(),
-                viewport.rect,
-                iter::once(RenderAttachmentInfo {
-                    image_view: surface_image.borrow(),
-                    clear_value: ClearValue {
-                        color: ClearColor {
-                            float32: self.clear,
-                        },
-                    },
-                }),
-                SubpassContents::Inline,
-            );
-
-            buffer.bind_graphics_pipeline(&self.state.pipelines[0]);
-
-            buffer.bind_vertex_buffers(
-                0,
-                iter::once((
-                    &self.state.vertex_buffers[0],
-                    gfx_hal::buffer::SubRange {
-                        offset: 0,
-                        size: Some(line_vec_size as u64),
-                    },
-                )),
-            );
-
-            for l in 0..self.lines.len() as u32 {
-                buffer.push_graphics_constants(
-                    &self.state.pipeline_layouts[0],
-                    ShaderStageFlags::FRAGMENT,
-                    0,
-                    std::mem::transmute::<&[f32], &[u32]>(&self.colors[l as usize][..]),
-                );
-
-                let ind = 2 * l;
-                buffer.draw(ind..(ind + 2), 0..1);
-            }
-
-            buffer.end_render_pass();
-            buffer.finish();
+            self.record_shapes(Some(surface_image.borrow()));
+            self.state.command_buffers[0].finish();
         }
 
         unsafe {
@@ -480,6 +1683,10 @@ impl<B: gfx_hal::Backend> Engine<B> {
             }
         }
     }
+    fn snapshot(&mut self, path: &str) -> Result<(), String> {
+        let (extent, pixels) = self.render_offscreen();
+        offscreen::write_png(path, extent.width, extent.height, &pixels)
+    }
 }
 
 /// Stores all persistent `gfx-hal` (Vulkan) objects
@@ -501,66 +1708,77 @@ pub struct GraphicsState<B: gfx_hal::Backend> {
     pipelines: Vec<B::GraphicsPipeline>,
     submission_complete_fence: Option<B::Fence>,
     rendering_complete_semaphore: Option<B::Semaphore>,
+    offscreen: Option<Offscreen<B>>,
+    atlas: Option<AtlasImage<B>>,
 }
 
 impl<B: gfx_hal::Backend> GraphicsState<B> {
     /// Initialize the graphics system and track necessary state
     pub fn new(window: &FrameHandle, name: &str, width: u32, height: u32) -> Self {
-        let surface_extent = Extent2D { width, height };
         let instance = B::Instance::create(name, 1).unwrap();
         let surface = unsafe { instance.create_surface(window).unwrap() };
+
+        Self::build(instance, Some(surface), width, height)
+    }
+
+    /// Initialize the graphics system without a window, to draw only
+    /// into offscreen images
+    pub fn headless(name: &str, width: u32, height: u32) -> Self {
+        let instance = B::Instance::create(name, 1).unwrap();
+
+        Self::build(instance, None, width, height)
+    }
+
+    /// Set up a device and everything needed to draw, presenting to the
+    /// surface if there is one
+    fn build(instance: B::Instance, surface: Option<B::Surface>, width: u32, height: u32) -> Self {
+        let surface_extent = Extent2D { width, height };
+        let usable = |qf: &B::QueueFamily| {
+            surface
+                .as_ref()
+                .map_or(true, |s| s.supports_queue_family(qf))
+                && qf.queue_type().supports_graphics()
+        };
         let adapter = instance
             .enumerate_adapters()
             .into_iter()
-            .find(|a| {
-                a.queue_families.iter().any(|qf| {
-                    surface.supports_queue_family(qf) && qf.queue_type().supports_graphics()
-                })
-            })
-            .unwrap();
-        let queue_family = adapter
-            .queue_families
-            .iter()
-            .find(|qf| surface.supports_queue_family(qf) && qf.queue_type().supports_graphics())
+            .find(|a| a.queue_families.iter().any(&usable))
             .unwrap();
+        let queue_family = adapter.queue_families.iter().find(|qf| usable(qf)).unwrap();
+        // points wider than a pixel need this, where the device has it
+        let features = adapter.physical_device.features() & gfx_hal::Features::POINT_SIZE;
         let mut gpu = unsafe {
             adapter
                 .physical_device
-                .open(&[(queue_family, &[1.0])], gfx_hal::Features::empty())
+                .open(&[(queue_family, &[1.0])], features)
                 .unwrap()
         };
         let device = gpu.device;
         let queue_group = gpu.queue_groups.remove(queue_family.id().0);
-        let supported_formats = surface
-            .supported_formats(&adapter.physical_device)
-            .unwrap_or(vec![]);
-        let default_format = *supported_formats.get(0).unwrap_or(&Format::Rgba8Srgb);
-        let surface_color_format = supported_formats
-            .into_iter()
-            .find(|f| f.base_format().1 == ChannelType::Srgb)
-            .unwrap_or(default_format);
-        let color_attachment = Attachment {
-            format: Some(surface_color_format),
-            samples: 1,
-            ops: AttachmentOps::new(AttachmentLoadOp::Clear, AttachmentStoreOp::Store),
-            stencil_ops: AttachmentOps::DONT_CARE,
-            layouts: Layout::Undefined..Layout::Present,
-        };
-        let subpass = SubpassDesc {
-            colors: &[(0, Layout::ColorAttachmentOptimal)],
-            depth_stencil: None,
-            inputs: &[],
-            resolves: &[],
-            preserves: &[],
+        let surface_color_format = match &surface {
+            Some(surface) => {
+                let supported_formats = surface
+                    .supported_formats(&adapter.physical_device)
+                    .unwrap_or(vec![]);
+                let default_format = *supported_formats.get(0).unwrap_or(&Format::Rgba8Srgb);
+                supported_formats
+                    .into_iter()
+                    .find(|f| f.base_format().1 == ChannelType::Srgb)
+                    .unwrap_or(default_format)
+            }
+            None => Format::Rgba8Srgb,
         };
-        let render_pass = unsafe {
-            device
-                .create_render_pass(
-                    vec![color_attachment].into_iter(),
-                    vec![subpass].into_iter(),
-                    vec![].into_iter(),
-                )
-                .unwrap()
+        // the last render pass always draws offscreen
+        let render_passes = match surface {
+            Some(_) => vec![
+                Self::make_render_pass(&device, surface_color_format, Layout::Present),
+                Self::make_render_pass(&device, surface_color_format, Layout::TransferSrcOptimal),
+            ],
+            None => vec![Self::make_render_pass(
+                &device,
+                surface_color_format,
+                Layout::TransferSrcOptimal,
+            )],
         };
         let mut command_pool = unsafe {
             device
@@ -572,19 +1790,20 @@ impl<B: gfx_hal::Backend> GraphicsState<B> {
         };
         let command_buffer = unsafe { command_pool.allocate_one(gfx_hal::command::Level::Primary) };
 
-        let submission_complete_fence = device.create_fence(false).unwrap();
+        // signalled, as though a previous frame had finished
+        let submission_complete_fence = device.create_fence(true).unwrap();
         let rendering_complete_semaphore = device.create_semaphore().unwrap();
 
         Self {
             surface_extent,
             instance,
-            surface: Some(surface),
+            surface,
             adapter,
             device,
             framebuffer: None,
             queue_group,
             surface_color_format,
-            render_passes: vec![render_pass],
+            render_passes,
             command_pool: Some(command_pool),
             command_buffers: vec![command_buffer],
             vertex_buffers: vec![],
@@ -593,6 +1812,37 @@ impl<B: gfx_hal::Backend> GraphicsState<B> {
             pipelines: vec![],
             submission_complete_fence: Some(submission_complete_fence),
             rendering_complete_semaphore: Some(rendering_complete_semaphore),
+            offscreen: None,
+            atlas: None,
+        }
+    }
+
+    /// Create a render pass which clears a single color attachment and
+    /// leaves it in the given layout
+    fn make_render_pass(device: &B::Device, format: Format, final_layout: Layout) -> B::RenderPass {
+        let color_attachment = Attachment {
+            format: Some(format),
+            samples: 1,
+            ops: AttachmentOps::new(AttachmentLoadOp::Clear, AttachmentStoreOp::Store),
+            stencil_ops: AttachmentOps::DONT_CARE,
+            layouts: Layout::Undefined..final_layout,
+        };
+        let subpass = SubpassDesc {
+            colors: &[(0, Layout::ColorAttachmentOptimal)],
+            depth_stencil: None,
+            inputs: &[],
+            resolves: &[],
+            preserves: &[],
+        };
+
+        unsafe {
+            device
+                .create_render_pass(
+                    vec![color_attachment].into_iter(),
+                    vec![subpass].into_iter(),
+                    vec![].into_iter(),
+                )
+                .unwrap()
         }
     }
 
@@ -666,12 +1916,14 @@ impl<B: gfx_hal::Backend> GraphicsState<B> {
     }
 
     /// Reconfigure the swapchain (usually due to new surface extent)
+    ///
+    /// Without a window there is no swapchain; offscreen targets follow
+    /// the extent by themselves.
     pub fn config_swapchain(&mut self) {
-        let caps = self
-            .surface
-            .as_ref()
-            .unwrap()
-            .capabilities(&self.adapter.physical_device);
+        let caps = match &self.surface {
+            Some(surface) => surface.capabilities(&self.adapter.physical_device),
+            None => return,
+        };
         let swapchain_config =
             SwapchainConfig::from_caps(&caps, self.surface_color_format, self.surface_extent);
         self.surface_extent = swapchain_config.extent;
@@ -734,31 +1986,37 @@ impl<B: gfx_hal::Backend> GraphicsState<B> {
 
         let req = self.device.get_buffer_requirements(&buffer);
 
+        // log::debug!("Buffer size: {}", req.size);
+
+        let buffer_memory = self
+            .device
+            .allocate_memory(self.memory_type(req.type_mask, properties), req.size)
+            .unwrap();
+
+        self.device
+            .bind_buffer_memory(&buffer_memory, 0, &mut buffer)
+            .unwrap();
+
+        (buffer_memory, buffer)
+    }
+
+    /// Find a memory type allowed by `type_mask` that has the properties
+    fn memory_type(&self, type_mask: u32, properties: gfx_hal::memory::Properties) -> MemoryTypeId {
         let memory_types = self
             .adapter
             .physical_device
             .memory_properties()
             .memory_types;
 
-        let memory_type = memory_types
+        memory_types
             .iter()
             .enumerate()
             .find(|(id, mem_type)| {
-                let type_supported = req.type_mask & (1_u32 << id) != 0;
+                let type_supported = type_mask & (1_u32 << id) != 0;
                 type_supported && mem_type.properties.contains(properties)
             })
             .map(|(id, _ty)| MemoryTypeId(id))
-            .unwrap();
-
-        // log::debug!("Buffer size: {}", req.size);
-
-        let buffer_memory = self.device.allocate_memory(memory_type, req.size).unwrap();
-
-        self.device
-            .bind_buffer_memory(&buffer_memory, 0, &mut buffer)
-            .unwrap();
-
-        (buffer_memory, buffer)
+            .unwrap()
     }
 
     /// Compile GLSL shader code into SPIR-V
@@ -812,17 +2070,43 @@ impl<B: gfx_hal::Backend> GraphicsState<B> {
         let primitive_assembler = PrimitiveAssemblerDesc::Vertex {
             buffers: &[VertexBufferDesc {
                 binding: 0,
-                stride: (size_of::<f32>() * 2) as u32,
+                stride: size_of::<Vertex>() as u32,
                 rate: VertexInputRate::Vertex,
             }],
-            attributes: &[AttributeDesc {
-                location: 0,
-                binding: 0,
-                element: Element {
-                    format: Format::Rg32Sfloat,
-                    offset: 0,
+            attributes: &[
+                AttributeDesc {
+                    location: 0,
+                    binding: 0,
+                    element: Element {
+                        format: Format::Rg32Sfloat,
+                        offset: 0,
+                    },
                 },
-            }],
+                AttributeDesc {
+                    location: 1,
+                    binding: 0,
+                    element: Element {
+                        format: Format::Rgba32Sfloat,
+                        offset: (size_of::<f32>() * 2) as u32,
+                    },
+                },
+                AttributeDesc {
+                    location: 2,
+                    binding: 0,
+                    element: Element {
+                        format: Format::R32Sfloat,
+                        offset: (size_of::<f32>() * 6) as u32,
+                    },
+                },
+                AttributeDesc {
+                    location: 3,
+                    binding: 0,
+                    element: Element {
+                        format: Format::Rg32Sfloat,
+                        offset: (size_of::<f32>() * 7) as u32,
+                    },
+                },
+            ],
             input_assembler: InputAssemblerDesc::new(primitive_type),
             vertex: vs_entry,
             tessellation: None,
@@ -875,8 +2159,15 @@ impl<B: gfx_hal::Backend> Drop for GraphicsState<B> {
             self.device
                 .destroy_fence(self.submission_complete_fence.take().unwrap());
 
-            self.device
-                .destroy_framebuffer(self.framebuffer.take().unwrap());
+            if let Some(framebuffer) = self.framebuffer.take() {
+                self.device.destroy_framebuffer(framebuffer);
+            }
+            if let Some(target) = self.offscreen.take() {
+                target.destroy(&self.device);
+            }
+            if let Some(atlas) = self.atlas.take() {
+                atlas.destroy(&self.device);
+            }
 
             for render_pass in self.render_passes.drain(..) {
                 self.device.destroy_render_pass(render_pass);
@@ -896,11 +2187,10 @@ impl<B: gfx_hal::Backend> Drop for GraphicsState<B> {
 
             self.device
                 .destroy_command_pool(self.command_pool.take().unwrap());
-            self.surface
-                .as_mut()
-                .unwrap()
-                .unconfigure_swapchain(&self.device);
-            self.instance.destroy_surface(self.surface.take().unwrap());
+            if let Some(mut surface) = self.surface.take() {
+                surface.unconfigure_swapchain(&self.device);
+                self.instance.destroy_surface(surface);
+            }
         }
     }
 }
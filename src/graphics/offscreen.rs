@@ -0,0 +1,262 @@
+// STARK, a system for computer augmented design.
+// Copyright (C) 2021 Matthew Rothlisberger
+
+// STARK is licensed under the terms of the GNU Affero General Public
+// License. See the top level LICENSE file for the license text.
+
+// Find full copyright information in the top level COPYRIGHT file.
+
+// <>
+
+// src/graphics/offscreen.rs
+
+// Offscreen render target: an image the engine can draw into without
+// a window, plus a buffer its pixels are copied to so they can be read
+// back and saved as PNG files.
+
+// <>
+
+use super::GraphicsState;
+
+use gfx_hal::{
+    buffer,
+    command::{BufferImageCopy, CommandBuffer},
+    device::Device,
+    format::{Aspects, Format, Swizzle},
+    image::{
+        Access, Extent, FramebufferAttachment, Kind, Layout, Offset, SubresourceLayers,
+        SubresourceRange, Tiling, Usage, ViewCapabilities, ViewKind,
+    },
+    memory::{Barrier, Dependencies, Properties, SparseFlags},
+    pso::PipelineStage,
+    window::Extent2D,
+};
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::iter;
+
+/// Bytes in each pixel of an offscreen image
+const PIXEL_LEN: u32 = 4;
+
+/// Image, framebuffer, and readback buffer for drawing offscreen
+pub struct Offscreen<B: gfx_hal::Backend> {
+    pub extent: Extent2D,
+    pub format: Format,
+    image: B::Image,
+    pub view: B::ImageView,
+    pub framebuffer: B::Framebuffer,
+    image_memory: B::Memory,
+    readback: B::Buffer,
+    readback_memory: B::Memory,
+}
+
+impl<B: gfx_hal::Backend> Offscreen<B> {
+    /// Creates a target the size of the state's extent, for the
+    /// state's offscreen render pass
+    pub fn new(state: &mut GraphicsState<B>) -> Self {
+        let extent = state.surface_extent;
+        let format = state.surface_color_format;
+
+        unsafe {
+            let mut image = state
+                .device
+                .create_image(
+                    Kind::D2(extent.width, extent.height, 1, 1),
+                    1,
+                    format,
+                    Tiling::Optimal,
+                    Usage::COLOR_ATTACHMENT | Usage::TRANSFER_SRC,
+                    SparseFlags::empty(),
+                    ViewCapabilities::empty(),
+                )
+                .unwrap();
+
+            let req = state.device.get_image_requirements(&image);
+            let image_memory = state
+                .device
+                .allocate_memory(
+                    state.memory_type(req.type_mask, Properties::DEVICE_LOCAL),
+                    req.size,
+                )
+                .unwrap();
+            state
+                .device
+                .bind_image_memory(&image_memory, 0, &mut image)
+                .unwrap();
+
+            let view = state
+                .device
+                .create_image_view(
+                    &image,
+                    ViewKind::D2,
+                    format,
+                    Swizzle::NO,
+                    Usage::COLOR_ATTACHMENT,
+                    SubresourceRange {
+                        aspects: Aspects::COLOR,
+                        level_start: 0,
+                        level_count: Some(1),
+                        layer_start: 0,
+                        layer_count: Some(1),
+                    },
+                )
+                .unwrap();
+
+            let framebuffer = state
+                .device
+                .create_framebuffer(
+                    state.render_passes.last().unwrap(),
+                    iter::once(FramebufferAttachment {
+                        usage: Usage::COLOR_ATTACHMENT,
+                        view_caps: ViewCapabilities::empty(),
+                        format,
+                    }),
+                    Extent {
+                        width: extent.width,
+                        height: extent.height,
+                        depth: 1,
+                    },
+                )
+                .unwrap();
+
+            let (readback_memory, readback) = state.make_buffer(
+                (extent.width * extent.height * PIXEL_LEN) as u64,
+                gfx_hal::buffer::Usage::TRANSFER_DST,
+                Properties::CPU_VISIBLE,
+            );
+
+            Self {
+                extent,
+                format,
+                image,
+                view,
+                framebuffer,
+                image_memory,
+                readback,
+                readback_memory,
+            }
+        }
+    }
+
+    /// Records a copy of the drawn image into the readback buffer, which
+    /// the host may read once the commands are complete; must come after
+    /// the render pass
+    pub unsafe fn record_readback(&self, buffer: &mut B::CommandBuffer) {
+        // the render pass leaves the image laid out to be copied from,
+        // but its writes must land before the copy reads it
+        buffer.pipeline_barrier(
+            PipelineStage::COLOR_ATTACHMENT_OUTPUT..PipelineStage::TRANSFER,
+            Dependencies::empty(),
+            iter::once(Barrier::Image {
+                states: (Access::COLOR_ATTACHMENT_WRITE, Layout::TransferSrcOptimal)
+                    ..(Access::TRANSFER_READ, Layout::TransferSrcOptimal),
+                target: &self.image,
+                range: SubresourceRange {
+                    aspects: Aspects::COLOR,
+                    level_start: 0,
+                    level_count: Some(1),
+                    layer_start: 0,
+                    layer_count: Some(1),
+                },
+                families: None,
+            }),
+        );
+
+        buffer.copy_image_to_buffer(
+            &self.image,
+            Layout::TransferSrcOptimal,
+            &self.readback,
+            iter::once(self.copy_region()),
+        );
+
+        buffer.pipeline_barrier(
+            PipelineStage::TRANSFER..PipelineStage::HOST,
+            Dependencies::empty(),
+            iter::once(Barrier::Buffer {
+                states: buffer::Access::TRANSFER_WRITE..buffer::Access::HOST_READ,
+                target: &self.readback,
+                range: buffer::SubRange::WHOLE,
+                families: None,
+            }),
+        );
+    }
+
+    /// Describes a copy of the whole image into the readback buffer
+    fn copy_region(&self) -> BufferImageCopy {
+        BufferImageCopy {
+            buffer_offset: 0,
+            buffer_width: self.extent.width,
+            buffer_height: self.extent.height,
+            image_layers: SubresourceLayers {
+                aspects: Aspects::COLOR,
+                level: 0,
+                layers: 0..1,
+            },
+            image_offset: Offset::ZERO,
+            image_extent: Extent {
+                width: self.extent.width,
+                height: self.extent.height,
+                depth: 1,
+            },
+        }
+    }
+
+    /// Reads the pixels last copied into the readback buffer, as RGBA
+    /// rows from the top of the image down
+    pub fn read_pixels(&mut self, device: &B::Device) -> Vec<u8> {
+        let len = (self.extent.width * self.extent.height * PIXEL_LEN) as usize;
+        let mut pixels = vec![0; len];
+
+        unsafe {
+            let mapped = device
+                .map_memory(&mut self.readback_memory, gfx_hal::memory::Segment::ALL)
+                .unwrap();
+            device
+                .invalidate_mapped_memory_ranges(iter::once((
+                    &self.readback_memory,
+                    gfx_hal::memory::Segment::ALL,
+                )))
+                .unwrap();
+
+            std::ptr::copy_nonoverlapping(mapped, pixels.as_mut_ptr(), len);
+
+            device.unmap_memory(&mut self.readback_memory);
+        }
+
+        // surfaces often prefer blue first; PNG wants red first
+        if let Format::Bgra8Srgb | Format::Bgra8Unorm = self.format {
+            for px in pixels.chunks_mut(PIXEL_LEN as usize) {
+                px.swap(0, 2);
+            }
+        }
+
+        pixels
+    }
+
+    /// Releases the target's resources
+    pub fn destroy(self, device: &B::Device) {
+        unsafe {
+            device.destroy_framebuffer(self.framebuffer);
+            device.destroy_image_view(self.view);
+            device.destroy_image(self.image);
+            device.free_memory(self.image_memory);
+            device.destroy_buffer(self.readback);
+            device.free_memory(self.readback_memory);
+        }
+    }
+}
+
+/// Writes RGBA pixels, in rows from the top down, to a PNG file
+pub fn write_png(path: &str, width: u32, height: u32, pixels: &[u8]) -> Result<(), String> {
+    let file = File::create(path).map_err(|err| format!("could not create {}: {}", path, err))?;
+
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(pixels))
+        .map_err(|err| format!("could not write {}: {}", path, err))
+}
@@ -0,0 +1,649 @@
+// STARK, a system for computer augmented design.
+// Copyright (C) 2021 Matthew Rothlisberger
+
+// STARK is licensed under the terms of the GNU Affero General Public
+// License. See the top level LICENSE file for the license text.
+
+// Find full copyright information in the top level COPYRIGHT file.
+
+// <>
+
+// src/graphics/scene.rs
+
+// Scene: the objects a drawing is made of, each with a stable ID, a
+// style, and a layer, kept apart from how any engine draws them. The
+// scene notes the earliest place in its drawing order that has changed,
+// so engines need only redo what comes after it. It also keeps which
+// objects are selected, and finds those under a cursor or within a box
+// from their shapes as drawn, in pixels.
+
+// <>
+
+use super::camera;
+use super::tess::{Curve, Stroke, Units};
+use super::text::{Atlas, Text};
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+type Point = [f32; 2];
+
+/// Name an object keeps for as long as it is in the scene
+pub type Id = u64;
+
+/// Place in the drawing order: the layer, then the order objects were
+/// first added in
+pub type Key = (i32, u64);
+
+/// Place before any object's
+pub const FIRST: Key = (i32::MIN, 0);
+
+/// ID kept for the shape following the cursor, like a line being drawn
+/// or a selection box; it is never hit or selected
+pub const CURSOR: Id = 0;
+
+/// Highest ID handed out or chosen so far, by any thread
+static LAST_ID: AtomicU64 = AtomicU64::new(CURSOR);
+
+/// Hands out an ID no object has had, for a new object in any scene
+///
+/// The manager thread names the objects it sends for drawing with
+/// these, so they never clash with those a scene gives out itself.
+pub fn new_id() -> Id {
+    LAST_ID.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+/// Color selected objects are drawn in, keeping their own alpha
+pub const HIGHLIGHT: [f32; 3] = [1.0, 0.5, 0.0];
+
+/// Shape of an object, in drawing coordinates
+#[derive(Clone, Debug, PartialEq)]
+pub enum Geometry {
+    Line([f32; 4]),
+    /// Filled triangles, from a triangle or a triangulated polygon
+    Fill(Vec<[f32; 6]>),
+    /// Round point, its size in pixels across
+    Point {
+        center: [f32; 2],
+        size: f32,
+    },
+    Curve(Curve),
+    Text(Text),
+}
+
+impl Geometry {
+    /// Moves the shape by an offset in drawing coordinates
+    pub fn translate(&mut self, offset: Point) {
+        let shift = |p: &mut Point| {
+            p[0] += offset[0];
+            p[1] += offset[1];
+        };
+
+        match self {
+            Geometry::Line(points) => {
+                for pair in points.chunks_mut(2) {
+                    pair[0] += offset[0];
+                    pair[1] += offset[1];
+                }
+            }
+            Geometry::Fill(triangles) => {
+                for pair in triangles.iter_mut().flat_map(|t| t.chunks_mut(2)) {
+                    pair[0] += offset[0];
+                    pair[1] += offset[1];
+                }
+            }
+            Geometry::Point { center, .. } => shift(center),
+            Geometry::Curve(Curve::Arc { center, .. }) => shift(center),
+            Geometry::Curve(Curve::Quadratic(points)) => points.iter_mut().for_each(shift),
+            Geometry::Curve(Curve::Cubic(points)) => points.iter_mut().for_each(shift),
+            Geometry::Curve(Curve::Polyline(points)) => points.iter_mut().for_each(shift),
+            Geometry::Text(text) => shift(&mut text.position),
+        }
+    }
+}
+
+/// How an object is drawn
+#[derive(Clone, Debug, PartialEq)]
+pub struct Style {
+    pub color: [f32; 4],
+    /// Stroke for lines and curves; other shapes ignore it
+    pub stroke: Stroke,
+}
+
+/// One thing drawn
+#[derive(Clone, Debug, PartialEq)]
+pub struct Object {
+    pub geometry: Geometry,
+    pub style: Style,
+    /// Objects in higher layers are drawn above those in lower ones
+    pub layer: i32,
+}
+
+/// An object and when it was first added
+#[derive(Debug)]
+struct Entry {
+    object: Object,
+    seq: u64,
+}
+
+impl Entry {
+    fn key(&self) -> Key {
+        (self.object.layer, self.seq)
+    }
+}
+
+/// Every object drawn, by ID and in drawing order
+#[derive(Debug, Default)]
+pub struct Scene {
+    objects: HashMap<Id, Entry>,
+    order: BTreeMap<Key, Id>,
+    last_seq: u64,
+    /// ID the next object added takes, if chosen by the caller
+    chosen: Option<Id>,
+    /// Stroke given to lines and curves as they are added
+    pub stroke: Stroke,
+    /// Layer objects are added to
+    pub layer: i32,
+    /// Earliest place in the drawing order changed since last taken
+    changed: Option<Key>,
+    /// Objects drawn in the highlight color
+    selected: BTreeSet<Id>,
+}
+
+impl Scene {
+    /// Makes an empty scene
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of objects in the scene
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    /// Whether the scene has no objects
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    /// Adds an object in the current stroke and layer, returning its ID
+    pub fn add(&mut self, geometry: Geometry, color: [f32; 4]) -> Id {
+        let id = match self.chosen.take() {
+            Some(id) => id,
+            None => new_id(),
+        };
+
+        self.insert(
+            id,
+            Object {
+                geometry,
+                style: Style {
+                    color,
+                    stroke: self.stroke.clone(),
+                },
+                layer: self.layer,
+            },
+        );
+
+        id
+    }
+
+    /// Has the next object added take a chosen ID, replacing any object
+    /// which has it already
+    pub fn choose_id(&mut self, id: Id) {
+        self.chosen = Some(id);
+    }
+
+    /// Puts an object in the scene under an ID; an object replaced this
+    /// way keeps its place among those in its layer
+    pub fn insert(&mut self, id: Id, object: Object) {
+        let seq = match self.objects.get(&id).map(|old| (old.key(), old.seq)) {
+            Some((key, seq)) => {
+                self.order.remove(&key);
+                self.mark(key);
+                seq
+            }
+            None => {
+                self.last_seq += 1;
+                self.last_seq
+            }
+        };
+
+        let entry = Entry { object, seq };
+        self.order.insert(entry.key(), id);
+        self.mark(entry.key());
+        self.objects.insert(id, entry);
+
+        LAST_ID.fetch_max(id, Ordering::Relaxed);
+    }
+
+    /// The object with an ID, if there is one
+    pub fn get(&self, id: Id) -> Option<&Object> {
+        self.objects.get(&id).map(|entry| &entry.object)
+    }
+
+    /// Changes the object with an ID, returning whether there was one
+    pub fn update(&mut self, id: Id, change: impl FnOnce(&mut Object)) -> bool {
+        let entry = match self.objects.get_mut(&id) {
+            Some(entry) => entry,
+            None => return false,
+        };
+
+        let before = entry.key();
+        change(&mut entry.object);
+        let after = entry.key();
+
+        self.order.remove(&before);
+        self.order.insert(after, id);
+        self.mark(before.min(after));
+
+        true
+    }
+
+    /// Takes the object with an ID out of the scene
+    pub fn remove(&mut self, id: Id) -> Option<Object> {
+        let entry = self.objects.remove(&id)?;
+
+        self.selected.remove(&id);
+        self.order.remove(&entry.key());
+        self.mark(entry.key());
+
+        Some(entry.object)
+    }
+
+    /// Removes every object; IDs already handed out are not reused
+    pub fn clear(&mut self) {
+        if !self.is_empty() {
+            self.mark(FIRST);
+        }
+
+        self.objects.clear();
+        self.order.clear();
+        self.selected.clear();
+    }
+
+    /// ID of the object most recently added of those matching `test`
+    pub fn newest(&self, test: impl Fn(&Object) -> bool) -> Option<Id> {
+        self.objects
+            .iter()
+            .filter(|(_, entry)| test(&entry.object))
+            .max_by_key(|(_, entry)| entry.seq)
+            .map(|(id, _)| *id)
+    }
+
+    /// Every object in drawing order, with its place and ID
+    pub fn iter(&self) -> impl Iterator<Item = (Key, Id, &Object)> {
+        self.iter_from(FIRST)
+    }
+
+    /// Objects in drawing order from a place on
+    pub fn iter_from(&self, from: Key) -> impl Iterator<Item = (Key, Id, &Object)> {
+        self.order
+            .range(from..)
+            .map(move |(key, id)| (*key, *id, &self.objects[id].object))
+    }
+
+    /// Layers with objects in them, lowest first
+    pub fn layers(&self) -> Vec<i32> {
+        let mut layers: Vec<i32> = self.order.keys().map(|key| key.0).collect();
+        layers.dedup();
+        layers
+    }
+
+    /// The earliest place in the drawing order changed since this was
+    /// last called; everything drawn from there on may differ
+    pub fn take_changed(&mut self) -> Option<Key> {
+        self.changed.take()
+    }
+
+    /// Selects objects in place of those selected before; IDs of no
+    /// object, and the cursor's, are passed over
+    pub fn select(&mut self, ids: impl IntoIterator<Item = Id>) {
+        let ids: BTreeSet<Id> = ids
+            .into_iter()
+            .filter(|id| *id != CURSOR && self.objects.contains_key(id))
+            .collect();
+
+        // only objects selected or deselected are drawn differently
+        let first = self
+            .selected
+            .symmetric_difference(&ids)
+            .map(|id| self.objects[id].key())
+            .min();
+        if let Some(key) = first {
+            self.mark(key);
+        }
+
+        self.selected = ids;
+    }
+
+    /// IDs of the objects selected, in drawing order
+    pub fn selection(&self) -> Vec<Id> {
+        self.order
+            .values()
+            .filter(|id| self.selected.contains(id))
+            .copied()
+            .collect()
+    }
+
+    /// Color an object is drawn in: its own, or the highlight while it
+    /// is selected
+    pub fn color(&self, id: Id, object: &Object) -> [f32; 4] {
+        let color = object.style.color;
+
+        if self.selected.contains(&id) {
+            [HIGHLIGHT[0], HIGHLIGHT[1], HIGHLIGHT[2], color[3]]
+        } else {
+            color
+        }
+    }
+
+    /// The object nearest a point, if any lies within `tolerance`
+    /// pixels of it, when drawn at `scale` pixels per unit; of objects
+    /// as near, the one drawn on top is taken
+    pub fn hit(&self, at: Point, tolerance: f32, scale: [f32; 2], atlas: &mut Atlas) -> Option<Id> {
+        let at = [at[0] * scale[0], at[1] * scale[1]];
+        let mut nearest = None;
+
+        for (_, id, object) in self.iter().filter(|(_, id, _)| *id != CURSOR) {
+            let distance = Outline::of(object, scale, atlas).distance(at);
+
+            if distance <= nearest.map_or(tolerance, |(_, d)| d) {
+                nearest = Some((id, distance));
+            }
+        }
+
+        nearest.map(|(id, _)| id)
+    }
+
+    /// Objects lying wholly within a box, given by two opposite corners,
+    /// when drawn at `scale` pixels per unit; in drawing order
+    pub fn enclosed(&self, corners: [f32; 4], scale: [f32; 2], atlas: &mut Atlas) -> Vec<Id> {
+        let [x0, y0, x1, y1] = corners;
+        let least = [x0.min(x1) * scale[0], y0.min(y1) * scale[1]];
+        let most = [x0.max(x1) * scale[0], y0.max(y1) * scale[1]];
+
+        let inside = |p: &Point| (0..2).all(|i| least[i] <= p[i] && p[i] <= most[i]);
+
+        self.iter()
+            .filter(|(_, id, object)| {
+                let points = Outline::of(object, scale, atlas).points();
+                *id != CURSOR && !points.is_empty() && points.iter().all(inside)
+            })
+            .map(|(_, id, _)| id)
+            .collect()
+    }
+
+    /// Box holding everything drawn at `scale` pixels per unit but the
+    /// cursor's shape, in drawing coordinates; none if there is nothing
+    pub fn bounds(&self, scale: [f32; 2], atlas: &mut Atlas) -> Option<[f32; 4]> {
+        let mut points = vec![];
+
+        for (_, _, object) in self.iter().filter(|(_, id, _)| *id != CURSOR) {
+            match &object.geometry {
+                Geometry::Line(l) => points.extend_from_slice(&[[l[0], l[1]], [l[2], l[3]]]),
+                Geometry::Fill(triangles) => {
+                    for t in triangles.iter() {
+                        points.extend_from_slice(&[[t[0], t[1]], [t[2], t[3]], [t[4], t[5]]]);
+                    }
+                }
+                Geometry::Point { center, .. } => points.push(*center),
+                Geometry::Curve(curve) => points.extend(curve.flatten(scale)),
+                Geometry::Text(text) => {
+                    for quad in atlas.layout(text, scale) {
+                        points.extend_from_slice(&quad.corners);
+                    }
+                }
+            }
+        }
+
+        camera::bounds(points.iter())
+    }
+
+    fn mark(&mut self, key: Key) {
+        self.changed = Some(self.changed.map_or(key, |changed| changed.min(key)));
+    }
+}
+
+/// An object's shape as drawn, in pixels from the origin, to measure
+/// how near a point is to it
+#[derive(Debug, Default)]
+struct Outline {
+    /// Line segments, each with half its width
+    segments: Vec<([Point; 2], f32)>,
+    triangles: Vec<[Point; 3]>,
+    /// Round points, each with its radius
+    dots: Vec<(Point, f32)>,
+}
+
+impl Outline {
+    /// Outline of an object drawn at `scale` pixels per unit
+    fn of(object: &Object, scale: [f32; 2], atlas: &mut Atlas) -> Self {
+        let to_pixel = |p: Point| [p[0] * scale[0], p[1] * scale[1]];
+
+        let stroke = &object.style.stroke;
+        let unit = match stroke.units {
+            Units::Pixels => 1.0,
+            Units::World => (scale[0] * scale[1]).sqrt(),
+        };
+        let half_width = (stroke.width * unit / 2.0).max(0.5);
+
+        let mut outline = Self::default();
+
+        let path = |outline: &mut Self, points: &[Point]| {
+            for pair in points.windows(2) {
+                let segment = [to_pixel(pair[0]), to_pixel(pair[1])];
+                outline.segments.push((segment, half_width));
+            }
+        };
+
+        match &object.geometry {
+            Geometry::Line(l) => path(&mut outline, &[[l[0], l[1]], [l[2], l[3]]]),
+            Geometry::Fill(triangles) => {
+                for t in triangles.iter() {
+                    let corners = [[t[0], t[1]], [t[2], t[3]], [t[4], t[5]]];
+                    outline.triangles.push([
+                        to_pixel(corners[0]),
+                        to_pixel(corners[1]),
+                        to_pixel(corners[2]),
+                    ]);
+                }
+            }
+            Geometry::Point { center, size } => {
+                outline.dots.push((to_pixel(*center), size / 2.0));
+            }
+            Geometry::Curve(curve) => path(&mut outline, &curve.flatten(scale)),
+            Geometry::Text(text) => {
+                for quad in atlas.layout(text, scale) {
+                    let c = quad.corners;
+                    outline
+                        .triangles
+                        .push([to_pixel(c[0]), to_pixel(c[1]), to_pixel(c[2])]);
+                    outline
+                        .triangles
+                        .push([to_pixel(c[0]), to_pixel(c[2]), to_pixel(c[3])]);
+                }
+            }
+        }
+
+        outline
+    }
+
+    /// Pixels from a point to the nearest part of the shape; none if
+    /// the point lies on it
+    fn distance(&self, at: Point) -> f32 {
+        let segments = self
+            .segments
+            .iter()
+            .map(|(s, half_width)| to_segment(at, s[0], s[1]) - half_width);
+
+        let triangles = self.triangles.iter().map(|t| {
+            if in_triangle(at, t) {
+                0.0
+            } else {
+                (0..3)
+                    .map(|i| to_segment(at, t[i], t[(i + 1) % 3]))
+                    .fold(f32::INFINITY, f32::min)
+            }
+        });
+
+        let dots = self
+            .dots
+            .iter()
+            .map(|(c, radius)| (at[0] - c[0]).hypot(at[1] - c[1]) - radius);
+
+        segments
+            .chain(triangles)
+            .chain(dots)
+            .fold(f32::INFINITY, f32::min)
+            .max(0.0)
+    }
+
+    /// Every point the shape is drawn through
+    fn points(&self) -> Vec<Point> {
+        let segments = self.segments.iter().flat_map(|(s, _)| s.iter());
+        let triangles = self.triangles.iter().flat_map(|t| t.iter());
+        let dots = self.dots.iter().map(|(center, _)| center);
+
+        segments.chain(triangles).chain(dots).copied().collect()
+    }
+}
+
+/// Distance from a point to the nearest on a line segment
+fn to_segment(p: Point, a: Point, b: Point) -> f32 {
+    let ab = [b[0] - a[0], b[1] - a[1]];
+    let ap = [p[0] - a[0], p[1] - a[1]];
+    let len_sq = ab[0] * ab[0] + ab[1] * ab[1];
+
+    let t = if len_sq > 0.0 {
+        ((ap[0] * ab[0] + ap[1] * ab[1]) / len_sq).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+
+    (ap[0] - t * ab[0]).hypot(ap[1] - t * ab[1])
+}
+
+/// Whether a point lies within a triangle, whichever way it winds
+fn in_triangle(p: Point, t: &[Point; 3]) -> bool {
+    let side = |a: Point, b: Point| (b[0] - a[0]) * (p[1] - a[1]) - (b[1] - a[1]) * (p[0] - a[0]);
+    let sides = [side(t[0], t[1]), side(t[1], t[2]), side(t[2], t[0])];
+
+    sides.iter().all(|s| *s >= 0.0) || sides.iter().all(|s| *s <= 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(x: f32) -> Geometry {
+        Geometry::Line([x, 0.0, x, 1.0])
+    }
+
+    fn ids(scene: &Scene) -> Vec<Id> {
+        scene.iter().map(|(_, id, _)| id).collect()
+    }
+
+    #[test]
+    fn order() {
+        let mut scene = Scene::new();
+        let black = [0.0, 0.0, 0.0, 1.0];
+
+        let a = scene.add(line(0.0), black);
+        scene.layer = -1;
+        let b = scene.add(line(1.0), black);
+        scene.layer = 0;
+        let c = scene.add(line(2.0), black);
+        assert_eq!(ids(&scene), [b, a, c]);
+        assert_eq!(scene.layers(), [-1, 0]);
+        assert_eq!(scene.take_changed(), Some((-1, 2)));
+        assert_eq!(scene.take_changed(), None);
+
+        // raising an object moves it above the rest
+        assert!(scene.update(b, |object| object.layer = 1));
+        assert_eq!(ids(&scene), [a, c, b]);
+        assert_eq!(scene.take_changed(), Some((-1, 2)));
+
+        // a replacement keeps its place, and only later places change
+        scene.choose_id(c);
+        assert_eq!(scene.add(line(5.0), black), c);
+        assert_eq!(ids(&scene), [a, c, b]);
+        assert_eq!(scene.get(c).unwrap().geometry, line(5.0));
+        assert_eq!(scene.take_changed(), Some((0, 3)));
+
+        assert_eq!(scene.newest(|_| true), Some(c));
+        assert_eq!(scene.remove(a).unwrap().geometry, line(0.0));
+        assert_eq!(scene.remove(a), None);
+        assert!(!scene.update(a, |_| ()));
+        assert_eq!(scene.take_changed(), Some((0, 1)));
+
+        // chosen IDs are not handed out again, by this scene or any
+        let chosen = new_id() + 10;
+        scene.choose_id(chosen);
+        scene.add(line(3.0), black);
+        assert!(scene.add(line(4.0), black) > chosen);
+        assert!(new_id() > chosen);
+
+        scene.clear();
+        assert!(scene.is_empty());
+        assert_eq!(scene.take_changed(), Some(FIRST));
+        assert!(scene.add(line(0.0), black) > chosen);
+    }
+
+    #[test]
+    fn selection() {
+        let mut scene = Scene::new();
+        let mut atlas = Atlas::new();
+        let black = [0.0, 0.0, 0.0, 0.5];
+        let scale = [10.0, 10.0];
+
+        let a = scene.add(line(0.0), black);
+        let b = scene.add(Geometry::Fill(vec![[2.0, 0.0, 4.0, 0.0, 4.0, 2.0]]), black);
+        let c = scene.add(
+            Geometry::Point {
+                center: [6.0, 0.0],
+                size: 10.0,
+            },
+            black,
+        );
+        scene.choose_id(CURSOR);
+        scene.add(line(0.2), black);
+        scene.take_changed();
+
+        // near enough in pixels, but not the cursor's line over it
+        assert_eq!(scene.hit([0.3, 0.5], 4.0, scale, &mut atlas), Some(a));
+        assert_eq!(scene.hit([0.5, 0.5], 4.0, scale, &mut atlas), None);
+        assert_eq!(scene.hit([3.5, 0.5], 0.0, scale, &mut atlas), Some(b));
+        assert_eq!(scene.hit([6.8, 0.0], 4.0, scale, &mut atlas), Some(c));
+
+        // a wide stroke is hit further out
+        assert!(scene.update(a, |object| object.style.stroke.width = 8.0));
+        assert_eq!(scene.hit([0.7, 0.5], 4.0, scale, &mut atlas), Some(a));
+
+        assert_eq!(
+            scene.enclosed([-1.0, -1.0, 4.5, 2.5], scale, &mut atlas),
+            [a, b]
+        );
+        assert_eq!(scene.enclosed([4.5, 2.5, 3.0, -1.0], scale, &mut atlas), []);
+
+        // the cursor's line reaches past the rest, but is left out
+        scene.update(CURSOR, |object| object.geometry.translate([0.0, 9.0]));
+        assert_eq!(scene.bounds(scale, &mut atlas), Some([0.0, 0.0, 6.0, 2.0]));
+
+        scene.take_changed();
+        scene.select(vec![c, a, 40, CURSOR]);
+        assert_eq!(scene.selection(), [a, c]);
+        assert_eq!(scene.take_changed(), Some((0, 1)));
+        assert_eq!(scene.color(a, scene.get(a).unwrap()), [1.0, 0.5, 0.0, 0.5]);
+        assert_eq!(scene.color(b, scene.get(b).unwrap()), black);
+
+        scene.select(vec![a, b]);
+        assert_eq!(scene.take_changed(), Some((0, 2)));
+        scene.remove(a);
+        assert_eq!(scene.selection(), [b]);
+
+        assert!(scene.update(b, |object| object.geometry.translate([1.0, -1.0])));
+        let moved = Geometry::Fill(vec![[3.0, -1.0, 5.0, -1.0, 5.0, 1.0]]);
+        assert_eq!(scene.get(b).unwrap().geometry, moved);
+    }
+}
@@ -8,22 +8,24 @@
 
 // <>
 
-// src/graphics/shaders/lines.vert
+// src/graphics/shaders/points.frag
 
-// Vertex shader for drawing simple 2D shapes; here used exclusively
-// for lines.
+// Fragment shader for drawing points as round sprites; the corners of
+// each point's square are left undrawn.
 
 // <>
 
 #version 460
 #extension GL_ARB_separate_shader_objects : enable
 
-layout (location = 0) in vec2 position;
+layout(location = 0) in vec4 rgba_color;
 
-out gl_PerVertex {
-    vec4 gl_Position;
-};
+layout(location = 0) out vec4 frag_color;
 
 void main() {
-    gl_Position = vec4(position, 0.0, 1.0);
+    if (length(gl_PointCoord - vec2(0.5)) > 0.5) {
+        discard;
+    }
+
+    frag_color = rgba_color;
 }
@@ -0,0 +1,42 @@
+// STARK, a system for computer augmented design.
+// Copyright (C) 2021 Matthew Rothlisberger
+
+// STARK is licensed under the terms of the GNU Affero General Public
+// License. See the top level LICENSE file for the license text.
+
+// Find full copyright information in the top level COPYRIGHT file.
+
+// <>
+
+// src/graphics/shaders/points.vert
+
+// Vertex shader for drawing points as sprites; each vertex gives the
+// point's center, color, and diameter in pixels.
+
+// <>
+
+#version 460
+#extension GL_ARB_separate_shader_objects : enable
+
+layout (location = 0) in vec2 position;
+layout (location = 1) in vec4 color;
+layout (location = 2) in float size;
+
+layout (location = 0) out vec4 rgba_color;
+
+// same view transform as the shape shaders
+layout (push_constant) uniform View {
+    vec2 scale;
+    vec2 offset;
+} view;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+    float gl_PointSize;
+};
+
+void main() {
+    rgba_color = color;
+    gl_PointSize = size;
+    gl_Position = vec4(position * view.scale + view.offset, 0.0, 1.0);
+}
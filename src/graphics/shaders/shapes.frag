@@ -8,22 +8,20 @@
 
 // <>
 
-// src/graphics/shaders/lines.frag
+// src/graphics/shaders/shapes.frag
 
-// Fragment shader for drawing lines in a 2D plane; colors provided
-// via push constants.
+// Fragment shader for drawing shapes in a 2D plane; colors, with
+// alpha, provided by the vertex shader.
 
 // <>
 
 #version 460
 #extension GL_ARB_separate_shader_objects : enable
 
-layout( push_constant ) uniform push_const {
-    vec3 rgb_color;
-} push;
+layout(location = 0) in vec4 rgba_color;
 
 layout(location = 0) out vec4 frag_color;
 
 void main() {
-    frag_color = vec4(push.rgb_color, 1.0);
+    frag_color = rgba_color;
 }
@@ -0,0 +1,41 @@
+// STARK, a system for computer augmented design.
+// Copyright (C) 2021 Matthew Rothlisberger
+
+// STARK is licensed under the terms of the GNU Affero General Public
+// License. See the top level LICENSE file for the license text.
+
+// Find full copyright information in the top level COPYRIGHT file.
+
+// <>
+
+// src/graphics/shaders/shapes.vert
+
+// Vertex shader for drawing simple 2D shapes; here used for lines and
+// filled triangles. Each vertex carries its own color, so every shape
+// of a kind can be drawn in one call.
+
+// <>
+
+#version 460
+#extension GL_ARB_separate_shader_objects : enable
+
+layout (location = 0) in vec2 position;
+layout (location = 1) in vec4 color;
+
+layout (location = 0) out vec4 rgba_color;
+
+// scale and offset taking world coordinates to normalized device
+// coordinates, set from the camera each frame
+layout (push_constant) uniform View {
+    vec2 scale;
+    vec2 offset;
+} view;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+void main() {
+    rgba_color = color;
+    gl_Position = vec4(position * view.scale + view.offset, 0.0, 1.0);
+}
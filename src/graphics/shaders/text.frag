@@ -0,0 +1,31 @@
+// STARK, a system for computer augmented design.
+// Copyright (C) 2021 Matthew Rothlisberger
+
+// STARK is licensed under the terms of the GNU Affero General Public
+// License. See the top level LICENSE file for the license text.
+
+// Find full copyright information in the top level COPYRIGHT file.
+
+// <>
+
+// src/graphics/shaders/text.frag
+
+// Fragment shader for text; the glyph atlas holds how much of each
+// pixel a glyph covers, which scales the alpha of the text color.
+
+// <>
+
+#version 460
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) in vec4 rgba_color;
+layout(location = 1) in vec2 atlas_uv;
+
+layout(set = 0, binding = 0) uniform sampler2D atlas;
+
+layout(location = 0) out vec4 frag_color;
+
+void main() {
+    float coverage = texture(atlas, atlas_uv).r;
+    frag_color = vec4(rgba_color.rgb, rgba_color.a * coverage);
+}
@@ -0,0 +1,42 @@
+// STARK, a system for computer augmented design.
+// Copyright (C) 2021 Matthew Rothlisberger
+
+// STARK is licensed under the terms of the GNU Affero General Public
+// License. See the top level LICENSE file for the license text.
+
+// Find full copyright information in the top level COPYRIGHT file.
+
+// <>
+
+// src/graphics/shaders/text.vert
+
+// Vertex shader for text; each glyph is a pair of triangles carrying
+// their place in the glyph atlas along with their color.
+
+// <>
+
+#version 460
+#extension GL_ARB_separate_shader_objects : enable
+
+layout (location = 0) in vec2 position;
+layout (location = 1) in vec4 color;
+layout (location = 3) in vec2 uv;
+
+layout (location = 0) out vec4 rgba_color;
+layout (location = 1) out vec2 atlas_uv;
+
+// glyphs move with the camera, like every other shape
+layout (push_constant) uniform View {
+    vec2 scale;
+    vec2 offset;
+} view;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+void main() {
+    rgba_color = color;
+    atlas_uv = uv;
+    gl_Position = vec4(position * view.scale + view.offset, 0.0, 1.0);
+}
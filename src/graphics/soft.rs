@@ -0,0 +1,749 @@
+// STARK, a system for computer augmented design.
+// Copyright (C) 2021 Matthew Rothlisberger
+
+// STARK is licensed under the terms of the GNU Affero General Public
+// License. See the top level LICENSE file for the license text.
+
+// Find full copyright information in the top level COPYRIGHT file.
+
+// <>
+
+// src/graphics/soft.rs
+
+// Software renderer: draws the engine's shapes into a framebuffer in
+// main memory, with no graphics driver involved. Colors are stored as
+// sRGB, like the gfx-hal engine's images, and output is the same on
+// every machine, so drawings can be checked pixel for pixel.
+
+// <>
+
+use super::camera::Camera;
+use super::scene::{Geometry, Id, Object, Scene};
+use super::tess::{Curve, Stroke};
+use super::text::{self, Atlas, Quad};
+use super::{offscreen, Renderer};
+
+/// Bytes in each framebuffer pixel
+const PIXEL_LEN: usize = 4;
+
+/// Steps each pixel is divided into along x and y; corners are snapped
+/// to these, as GPUs snap them, so edges shared by triangles are found
+/// exactly the same way for both
+const SUBPIXELS: f32 = 256.0;
+
+/// Engine state for drawing on the CPU
+pub struct SoftEngine {
+    clear: [f32; 4],
+    scene: Scene,
+    atlas: Atlas,
+    camera: Option<Camera>,
+    width: u32,
+    height: u32,
+    frame: Vec<u8>,
+}
+
+impl SoftEngine {
+    /// Initialize a software engine with a frame of the given size
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            clear: [0.0, 0.0, 0.0, 1.0],
+            scene: Scene::new(),
+            atlas: Atlas::new(),
+            camera: None,
+            width,
+            height,
+            frame: vec![0; width as usize * height as usize * PIXEL_LEN],
+        }
+    }
+    /// Width and height of the frame
+    pub fn size(&self) -> [u32; 2] {
+        [self.width, self.height]
+    }
+    /// The frame as of the last draw, as RGBA rows from the top down
+    pub fn pixels(&self) -> &[u8] {
+        &self.frame
+    }
+    /// Where a point in drawing coordinates lies in the frame, in
+    /// pixels from its top left corner
+    fn pixel(&self, p: [f32; 2]) -> [f32; 2] {
+        let (w, h) = (self.width as f32, self.height as f32);
+
+        let [x, y] = match &self.camera {
+            Some(camera) => camera.to_pixel(p, [w, h]),
+            None => [to_pixel(p[0], w), to_pixel(p[1], h)],
+        };
+
+        [
+            (x * SUBPIXELS).round() / SUBPIXELS,
+            (y * SUBPIXELS).round() / SUBPIXELS,
+        ]
+    }
+    /// Color one pixel, ignoring any outside the frame
+    fn plot(&mut self, x: i64, y: i64, rgba: [u8; 4]) {
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            return;
+        }
+
+        let at = (y as usize * self.width as usize + x as usize) * PIXEL_LEN;
+        self.frame[at..at + PIXEL_LEN].copy_from_slice(&rgba);
+    }
+    /// Blend a linear color over one pixel, by its alpha, ignoring any
+    /// pixel outside the frame
+    ///
+    /// Blending happens in linear space, as it does on the GPU when
+    /// drawing into an sRGB image.
+    fn blend(&mut self, x: i64, y: i64, color: [f32; 4]) {
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            return;
+        }
+
+        let at = (y as usize * self.width as usize + x as usize) * PIXEL_LEN;
+        let px = &mut self.frame[at..at + PIXEL_LEN];
+        let a = color[3].clamp(0.0, 1.0);
+
+        for c in 0..3 {
+            let under = srgb_decode(px[c]);
+            px[c] = srgb_encode(color[c] * a + under * (1.0 - a));
+        }
+
+        px[3] = unorm_encode(a + px[3] as f32 / 255.0 * (1.0 - a));
+    }
+    /// Rasterize one line, given in drawing coordinates
+    ///
+    /// Steps along the longer axis one pixel center at a time, and
+    /// colors the pixel the line crosses there; a line's last pixel is
+    /// left for the line which continues it.
+    fn raster_line(&mut self, points: [f32; 4], rgba: [u8; 4]) {
+        let (w, h) = (self.width as f32, self.height as f32);
+        let [x0, y0] = self.pixel([points[0], points[1]]);
+        let [x1, y1] = self.pixel([points[2], points[3]]);
+
+        let x_major = (x1 - x0).abs() >= (y1 - y0).abs();
+
+        // walk along u, the major axis, reading off v
+        let (u0, v0, u1, v1, len) = if x_major {
+            (x0, y0, x1, y1, w)
+        } else {
+            (y0, x0, y1, x1, h)
+        };
+        let (u0, v0, u1, v1) = if u0 <= u1 {
+            (u0, v0, u1, v1)
+        } else {
+            (u1, v1, u0, v0)
+        };
+
+        if u0 == u1 {
+            return;
+        }
+
+        let slope = (v1 - v0) / (u1 - u0);
+
+        let first = (u0 - 0.5).ceil().max(0.0) as i64;
+        let last = (u1 - 0.5).ceil().min(len) as i64;
+
+        for u in first..last {
+            let v = v0 + (u as f32 + 0.5 - u0) * slope;
+            let v = v.floor() as i64;
+
+            if x_major {
+                self.plot(u, v, rgba);
+            } else {
+                self.plot(v, u, rgba);
+            }
+        }
+    }
+    /// Fill one triangle, given in drawing coordinates
+    ///
+    /// Pixels are filled when their centers are inside; a center on an
+    /// edge belongs to the triangle only if that is a top or left edge,
+    /// so triangles sharing an edge do not both cover it.
+    fn raster_triangle(&mut self, points: [f32; 6], color: [f32; 4]) {
+        let (w, h) = (self.width as f32, self.height as f32);
+        let mut p = [
+            self.pixel([points[0], points[1]]),
+            self.pixel([points[2], points[3]]),
+            self.pixel([points[4], points[5]]),
+        ];
+
+        // wind the corners so that the inside is right of every edge
+        if edge(p[0], p[1], p[2]) < 0.0 {
+            p.swap(1, 2);
+        }
+        if edge(p[0], p[1], p[2]) == 0.0 {
+            return;
+        }
+
+        let xs = p.iter().map(|c| c[0]);
+        let ys = p.iter().map(|c| c[1]);
+        let x0 = (xs.clone().fold(f32::MAX, f32::min) - 0.5).ceil().max(0.0) as i64;
+        let x1 = (xs.fold(f32::MIN, f32::max) - 0.5).ceil().min(w) as i64;
+        let y0 = (ys.clone().fold(f32::MAX, f32::min) - 0.5).ceil().max(0.0) as i64;
+        let y1 = (ys.fold(f32::MIN, f32::max) - 0.5).ceil().min(h) as i64;
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let c = [x as f32 + 0.5, y as f32 + 0.5];
+
+                let inside = (0..3).all(|i| {
+                    let (a, b) = (p[i], p[(i + 1) % 3]);
+                    let e = edge(a, b, c);
+                    e > 0.0 || (e == 0.0 && top_left(a, b))
+                });
+
+                if inside {
+                    self.blend(x, y, color);
+                }
+            }
+        }
+    }
+    /// Fill a round point of `size` pixels across, centered on a point
+    /// in drawing coordinates
+    fn raster_point(&mut self, point: [f32; 2], size: f32, color: [f32; 4]) {
+        let (w, h) = (self.width as f32, self.height as f32);
+        let [cx, cy] = self.pixel(point);
+        let r = size / 2.0;
+
+        let x0 = (cx - r - 0.5).ceil().max(0.0) as i64;
+        let x1 = (cx + r - 0.5).floor().min(w - 1.0) as i64;
+        let y0 = (cy - r - 0.5).ceil().max(0.0) as i64;
+        let y1 = (cy + r - 0.5).floor().min(h - 1.0) as i64;
+
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                let (dx, dy) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+
+                if dx * dx + dy * dy <= r * r {
+                    self.blend(x, y, color);
+                }
+            }
+        }
+    }
+    /// Draw one glyph quad, given in drawing coordinates, by
+    /// its coverage in the atlas
+    ///
+    /// Each pixel center inside the quad is mapped back into the atlas,
+    /// and blended by the coverage sampled there, as the GPU's linear
+    /// filtering would.
+    fn raster_glyph(&mut self, quad: &Quad, color: [f32; 4]) {
+        let (w, h) = (self.width as f32, self.height as f32);
+        let p: Vec<[f32; 2]> = quad.corners.iter().map(|c| self.pixel(*c)).collect();
+
+        // the quad is a parallelogram, spanned by its top and left edges
+        let across = [p[1][0] - p[0][0], p[1][1] - p[0][1]];
+        let down = [p[3][0] - p[0][0], p[3][1] - p[0][1]];
+        let det = across[0] * down[1] - across[1] * down[0];
+        if det == 0.0 {
+            return;
+        }
+
+        let xs = p.iter().map(|c| c[0]);
+        let ys = p.iter().map(|c| c[1]);
+        let x0 = (xs.clone().fold(f32::MAX, f32::min) - 0.5).ceil().max(0.0) as i64;
+        let x1 = (xs.fold(f32::MIN, f32::max) - 0.5).ceil().min(w) as i64;
+        let y0 = (ys.clone().fold(f32::MAX, f32::min) - 0.5).ceil().max(0.0) as i64;
+        let y1 = (ys.fold(f32::MIN, f32::max) - 0.5).ceil().min(h) as i64;
+
+        let uv = quad.uvs;
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let (dx, dy) = (x as f32 + 0.5 - p[0][0], y as f32 + 0.5 - p[0][1]);
+                let s = (dx * down[1] - dy * down[0]) / det;
+                let t = (across[0] * dy - across[1] * dx) / det;
+
+                if !(0.0..1.0).contains(&s) || !(0.0..1.0).contains(&t) {
+                    continue;
+                }
+
+                let at = [
+                    uv[0][0] + s * (uv[1][0] - uv[0][0]) + t * (uv[3][0] - uv[0][0]),
+                    uv[0][1] + s * (uv[1][1] - uv[0][1]) + t * (uv[3][1] - uv[0][1]),
+                ];
+                let coverage = text::sample(self.atlas.pixels(), at);
+
+                if coverage > 0.0 {
+                    let [r, g, b, a] = color;
+                    self.blend(x, y, [r, g, b, a * coverage]);
+                }
+            }
+        }
+    }
+    /// Draw the objects of one layer, each in the color it is drawn in:
+    /// fills, then lines, then points, then text, as the gfx-hal engine
+    /// does
+    fn draw_layer(&mut self, objects: &[(&Object, [f32; 4])]) {
+        for (object, color) in objects.iter() {
+            if let Geometry::Fill(triangles) = &object.geometry {
+                for tri in triangles.iter() {
+                    self.raster_triangle(*tri, *color);
+                }
+            }
+        }
+
+        for (object, color) in objects.iter() {
+            if let Geometry::Line(line) = object.geometry {
+                if object.style.stroke == Stroke::default() {
+                    self.raster_line(line, opaque(*color));
+                }
+            }
+        }
+
+        // curves and styled lines are flattened and stroked anew each
+        // frame, so they always suit the frame size; as with the
+        // gfx-hal engine, wide strokes lie above hairlines
+        let scale = self.view_scale();
+        let mut strokes = vec![];
+        for (object, color) in objects.iter() {
+            let stroke = &object.style.stroke;
+
+            let curve = match &object.geometry {
+                Geometry::Line(l) if *stroke != Stroke::default() => {
+                    Curve::Polyline(vec![[l[0], l[1]], [l[2], l[3]]])
+                }
+                Geometry::Curve(curve) => curve.clone(),
+                _ => continue,
+            };
+
+            let rgba = opaque(*color);
+
+            let stroked = stroke.apply(&curve.flatten(scale), scale);
+            for line in stroked.lines {
+                self.raster_line(line, rgba);
+            }
+
+            strokes.push((stroked.triangles, *color));
+        }
+
+        for (triangles, color) in strokes {
+            for tri in triangles {
+                self.raster_triangle(tri, color);
+            }
+        }
+
+        for (object, color) in objects.iter() {
+            if let Geometry::Point { center, size } = object.geometry {
+                self.raster_point(center, size, *color);
+            }
+        }
+
+        // text lies above everything else, so labels stay readable
+        for (object, color) in objects.iter() {
+            if let Geometry::Text(text) = &object.geometry {
+                for quad in self.atlas.layout(text, scale) {
+                    self.raster_glyph(&quad, *color);
+                }
+            }
+        }
+    }
+}
+
+impl Renderer for SoftEngine {
+    fn set_clear(&mut self, clear: [f32; 4]) {
+        self.clear = clear;
+    }
+    fn scene(&self) -> &Scene {
+        &self.scene
+    }
+    fn scene_mut(&mut self) -> &mut Scene {
+        &mut self.scene
+    }
+    fn set_camera(&mut self, camera: Camera) {
+        self.camera = Some(camera);
+    }
+    fn view_scale(&self) -> [f32; 2] {
+        match &self.camera {
+            Some(camera) => [camera.zoom, camera.zoom],
+            None => [self.width as f32 / 2.0, self.height as f32 / 2.0],
+        }
+    }
+    fn hit(&mut self, at: [f32; 2], tolerance: f32) -> Option<Id> {
+        let scale = self.view_scale();
+        self.scene.hit(at, tolerance, scale, &mut self.atlas)
+    }
+    fn enclosed(&mut self, corners: [f32; 4]) -> Vec<Id> {
+        let scale = self.view_scale();
+        self.scene.enclosed(corners, scale, &mut self.atlas)
+    }
+    fn fit_camera(&mut self) -> Option<Camera> {
+        let scale = self.view_scale();
+        let bounds = self.scene.bounds(scale, &mut self.atlas)?;
+        let extent = [self.width as f32, self.height as f32];
+
+        let mut camera = self
+            .camera
+            .unwrap_or_else(|| Camera::new([0.0, 0.0], extent[1] / 2.0));
+        camera.fit(bounds, extent);
+        self.camera = Some(camera);
+
+        Some(camera)
+    }
+    fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.frame = vec![0; width as usize * height as usize * PIXEL_LEN];
+    }
+    fn draw_frame(&mut self) {
+        let clear = [
+            srgb_encode(self.clear[0]),
+            srgb_encode(self.clear[1]),
+            srgb_encode(self.clear[2]),
+            unorm_encode(self.clear[3]),
+        ];
+
+        for px in self.frame.chunks_mut(PIXEL_LEN) {
+            px.copy_from_slice(&clear);
+        }
+
+        let scene = std::mem::take(&mut self.scene);
+        let objects: Vec<(&Object, [f32; 4])> = scene
+            .iter()
+            .map(|(_, id, object)| (object, scene.color(id, object)))
+            .collect();
+
+        // the scene is in drawing order, so each layer is a run of it
+        let mut first = 0;
+        for end in 1..=objects.len() {
+            if end == objects.len() || objects[end].0.layer != objects[first].0.layer {
+                self.draw_layer(&objects[first..end]);
+                first = end;
+            }
+        }
+
+        self.scene = scene;
+    }
+    fn snapshot(&mut self, path: &str) -> Result<(), String> {
+        self.draw_frame();
+        offscreen::write_png(path, self.width, self.height, &self.frame)
+    }
+}
+
+/// Encodes a color as an opaque sRGB pixel, as lines are drawn
+fn opaque(color: [f32; 4]) -> [u8; 4] {
+    [
+        srgb_encode(color[0]),
+        srgb_encode(color[1]),
+        srgb_encode(color[2]),
+        255,
+    ]
+}
+
+/// Maps a normalized device coordinate onto a frame axis of `len`
+/// pixels; -1 is the left or top edge
+fn to_pixel(ndc: f32, len: f32) -> f32 {
+    (ndc + 1.0) / 2.0 * len
+}
+
+/// Twice the signed area of the triangle `a b p`; positive when `p` is
+/// right of the line from `a` through `b`, with y pointing down
+///
+/// For snapped points this is exact, so its sign never depends on
+/// which end of an edge it is measured from.
+fn edge(a: [f32; 2], b: [f32; 2], p: [f32; 2]) -> f64 {
+    let wide = |q: [f32; 2]| [q[0] as f64, q[1] as f64];
+    let (a, b, p) = (wide(a), wide(b), wide(p));
+    (b[0] - a[0]) * (p[1] - a[1]) - (b[1] - a[1]) * (p[0] - a[0])
+}
+
+/// Whether an edge of a triangle wound as `raster_triangle` winds it
+/// is a top edge (flat, with the inside below) or a left edge
+fn top_left(a: [f32; 2], b: [f32; 2]) -> bool {
+    (a[1] == b[1] && b[0] > a[0]) || b[1] < a[1]
+}
+
+/// Reads an 8 bit sRGB value as a linear color channel
+fn srgb_decode(s: u8) -> f32 {
+    let s = s as f32 / 255.0;
+
+    if s <= 0.040_45 {
+        s / 12.92
+    } else {
+        ((s + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Stores a linear color channel as an 8 bit sRGB value
+fn srgb_encode(c: f32) -> u8 {
+    let c = c.clamp(0.0, 1.0);
+    let s = if c <= 0.003_130_8 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    };
+
+    unorm_encode(s)
+}
+
+/// Stores a channel in [0, 1] as an 8 bit value
+fn unorm_encode(c: f32) -> u8 {
+    (c.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Makes an engine with a white background
+    fn blank(width: u32, height: u32) -> SoftEngine {
+        let mut engine = SoftEngine::new(width, height);
+        engine.set_clear([1.0, 1.0, 1.0, 1.0]);
+        engine
+    }
+
+    /// Draws the engine, then maps each pixel to '.' if it is white
+    /// and '#' otherwise
+    fn trace(engine: &mut SoftEngine) -> String {
+        engine.draw_frame();
+
+        let mut out = String::new();
+
+        for row in engine.pixels().chunks(engine.width as usize * PIXEL_LEN) {
+            for px in row.chunks(PIXEL_LEN) {
+                out.push(if px == [255; 4] { '.' } else { '#' });
+            }
+            out.push('\n');
+        }
+
+        out
+    }
+
+    #[test]
+    fn clear_is_srgb() {
+        let mut engine = SoftEngine::new(2, 2);
+        engine.set_clear([1.0, 0.0, 0.5, 1.0]);
+        engine.draw_frame();
+
+        assert_eq!(engine.pixels(), &[255, 0, 188, 255].repeat(4)[..]);
+    }
+
+    #[test]
+    fn lines() {
+        let mut engine = blank(6, 4);
+        engine.add_line([-1.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0]);
+        assert_eq!(trace(&mut engine), "......\n......\n######\n......\n");
+
+        engine.empty_shapes();
+        engine.add_line([-1.0, -1.0, 1.0 / 3.0, 1.0], [0.0, 0.0, 0.0]);
+        assert_eq!(trace(&mut engine), "#.....\n.#....\n..#...\n...#..\n");
+
+        engine.add_line([0.5, -1.0, 0.5, 1.0], [0.0, 1.0, 0.0]);
+        assert_eq!(trace(&mut engine), "#...#.\n.#..#.\n..#.#.\n...##.\n");
+        assert_eq!(&engine.pixels()[16..20], &[0, 255, 0, 255]);
+
+        engine.pop_line();
+        assert_eq!(trace(&mut engine), "#.....\n.#....\n..#...\n...#..\n");
+    }
+
+    #[test]
+    fn fills() {
+        let mut engine = blank(6, 4);
+
+        // two triangles sharing an edge cover the frame exactly once
+        let black = [0.0, 0.0, 0.0, 0.5];
+        engine.add_triangle([-1.0, -1.0, 1.0, -1.0, 1.0, 1.0], black);
+        engine.add_triangle([-1.0, -1.0, -1.0, 1.0, 1.0, 1.0], black);
+        engine.draw_frame();
+        assert_eq!(engine.pixels(), &[188, 188, 188, 255].repeat(24)[..]);
+
+        engine.empty_shapes();
+        engine.add_triangle([-1.0, 1.0, 0.0, -1.0, 1.0, 1.0], [0.0, 0.0, 1.0, 1.0]);
+        assert_eq!(trace(&mut engine), "......\n..##..\n.####.\n######\n");
+        assert_eq!(&engine.pixels()[32..36], &[0, 0, 255, 255]);
+
+        engine.empty_shapes();
+        engine.add_point([0.0, 0.0], 3.0, [1.0, 0.0, 0.0, 1.0]);
+        engine.add_point([-0.5, -0.5], 1.0, [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(trace(&mut engine), ".#....\n.###..\n..##..\n......\n");
+    }
+
+    #[test]
+    fn curves() {
+        let mut engine = blank(6, 4);
+        let poly = vec![[-1.0, -1.0], [1.0 / 3.0, 1.0], [0.5, 1.0], [0.5, -1.0]];
+        engine.add_curve(Curve::Polyline(poly), [0.0, 0.0, 0.0]);
+        assert_eq!(trace(&mut engine), "#...#.\n.#..#.\n..#.#.\n...##.\n");
+
+        engine.empty_shapes();
+        let circle = Curve::Arc {
+            center: [0.0, 0.0],
+            radii: [0.75, 0.75],
+            start: 0.0,
+            sweep: 2.0 * std::f32::consts::PI,
+        };
+        engine.add_curve(circle, [0.0, 0.0, 0.0]);
+        engine.resize(16, 16);
+
+        let rows = [
+            "................",
+            "................",
+            ".....######.....",
+            "....#......#....",
+            "...#........#...",
+            "..#..........#..",
+            "..#..........#..",
+            "..#..........#..",
+            "..#..........#..",
+            "..#..........#..",
+            "..#..........#..",
+            "...#........#...",
+            "....#......#....",
+            ".....######.....",
+            "................",
+            "................",
+        ];
+        assert_eq!(trace(&mut engine), rows.join("\n") + "\n");
+    }
+
+    #[test]
+    fn strokes() {
+        let mut engine = blank(8, 6);
+        engine.add_line([-1.0, -0.3, 1.0, -0.3], [0.0, 0.0, 0.0]);
+
+        engine.set_stroke(Stroke {
+            width: 2.0,
+            dashes: vec![2.0, 1.0],
+            ..Stroke::default()
+        });
+        engine.add_line([-1.0, 1.0 / 3.0, 1.0, 1.0 / 3.0], [0.0, 0.0, 0.0]);
+        assert_eq!(
+            trace(&mut engine),
+            "........\n........\n########\n##.##.##\n##.##.##\n........\n"
+        );
+
+        engine.pop_line();
+        assert_eq!(
+            trace(&mut engine),
+            "........\n........\n########\n........\n........\n........\n"
+        );
+    }
+
+    #[test]
+    fn text() {
+        let mut engine = blank(24, 12);
+        let black = [0.0, 0.0, 0.0, 1.0];
+        let label = text::Text {
+            string: "T".to_string(),
+            position: [0.0, 0.0],
+            size: 12.0,
+            anchor: text::Anchor::Center,
+            rotation: 0.0,
+        };
+        engine.add_text(label.clone(), black);
+        let upright = trace(&mut engine);
+        let rows: Vec<&str> = upright.lines().collect();
+
+        // a T is widest at the top, and the stem runs down the middle
+        let inked = |row: &str| row.chars().filter(|c| *c == '#').count();
+        let top = rows.iter().position(|row| inked(row) > 0).unwrap();
+        let bottom = rows.iter().rposition(|row| inked(row) > 0).unwrap();
+        assert!(bottom - top >= 5);
+        assert!(inked(rows[top]) > inked(rows[bottom]));
+        assert!(rows[bottom][10..14].contains('#'));
+
+        // turned a quarter, the bar runs down the left side instead
+        engine.empty_shapes();
+        engine.add_text(
+            text::Text {
+                rotation: std::f32::consts::FRAC_PI_2,
+                ..label
+            },
+            black,
+        );
+        let turned = trace(&mut engine);
+        let rows: Vec<&str> = turned.lines().collect();
+        let column = |x: usize| rows.iter().filter(|row| &row[x..=x] == "#").count();
+        let left = (0..24).position(|x| column(x) > 0).unwrap();
+        let right = (0..24).rposition(|x| column(x) > 0).unwrap();
+        assert!(column(left) > column(right));
+
+        engine.empty_shapes();
+        assert!(!trace(&mut engine).contains('#'));
+    }
+
+    #[test]
+    fn camera() {
+        let mut engine = blank(8, 4);
+        assert_eq!(engine.fit_camera(), None);
+
+        // a unit is the same number of pixels across and down
+        engine.set_camera(Camera::new([0.0, 0.0], 2.0));
+        engine.add_triangle([-1.0, -1.0, 1.0, -1.0, 1.0, 1.0], [0.0, 0.0, 0.0, 1.0]);
+        engine.add_triangle([-1.0, -1.0, -1.0, 1.0, 1.0, 1.0], [0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(
+            trace(&mut engine),
+            "..####..\n..####..\n..####..\n..####..\n"
+        );
+
+        engine.set_camera(Camera::new([1.0, 0.5], 1.0));
+        assert_eq!(
+            trace(&mut engine),
+            "..##....\n..##....\n........\n........\n"
+        );
+
+        // fitting fills the frame's height with the square, less a margin
+        let fitted = engine.fit_camera().unwrap();
+        assert_eq!(fitted.center, [0.0, 0.0]);
+        assert!((fitted.zoom - 1.8).abs() < 1e-4);
+        assert_eq!(
+            trace(&mut engine),
+            "..####..\n..####..\n..####..\n..####..\n"
+        );
+    }
+
+    #[test]
+    fn scene() {
+        let mut engine = blank(6, 4);
+
+        // a line added later, but to a lower layer, lies beneath the fill
+        engine.scene_mut().layer = 1;
+        let fill = engine.add_triangle([-1.0, -1.0, 1.0, -1.0, 1.0, 1.0], [1.0, 0.0, 0.0, 1.0]);
+        engine.scene_mut().layer = 0;
+        let line = engine.add_line([-1.0, -0.5, 1.0, -0.5], [0.0, 0.0, 1.0]);
+        engine.draw_frame();
+        assert_eq!(&engine.pixels()[24..28], &[0, 0, 255, 255]);
+        assert_eq!(&engine.pixels()[44..48], &[255, 0, 0, 255]);
+
+        let scene = engine.scene_mut();
+        assert!(scene.update(line, |object| object.layer = 2));
+        assert!(scene.update(fill, |object| object.style.color = [0.0, 1.0, 0.0, 1.0]));
+        engine.draw_frame();
+        assert_eq!(&engine.pixels()[44..48], &[0, 0, 255, 255]);
+        assert_eq!(&engine.pixels()[20..24], &[0, 255, 0, 255]);
+
+        assert!(engine.scene_mut().remove(line).is_some());
+        assert_eq!(trace(&mut engine), ".#####\n..####\n....##\n.....#\n");
+    }
+
+    #[test]
+    fn selection() {
+        let mut engine = blank(6, 4);
+
+        let fill = engine.add_triangle([-1.0, -1.0, 1.0, -1.0, 1.0, 1.0], [1.0, 0.0, 0.0, 1.0]);
+        let line = engine.add_line([-1.0, -0.5, 1.0, -0.5], [0.0, 0.0, 1.0]);
+
+        // a click lands on both, and takes the line drawn over the fill
+        assert_eq!(engine.pick([-0.5, -0.5, -0.4, -0.5], 2.0), [line]);
+        assert_eq!(engine.hit([-0.9, 0.9], 2.0), None);
+        engine.draw_frame();
+        assert_eq!(&engine.pixels()[24..28], &[255, 188, 0, 255]);
+        assert_eq!(&engine.pixels()[20..24], &[255, 0, 0, 255]);
+
+        // a box takes only what lies wholly inside it
+        assert_eq!(engine.pick([1.0, 1.0, -1.0, -0.5], 2.0), [line]);
+        assert_eq!(engine.pick([-1.0, -1.0, 1.0, 1.0], 2.0), [fill, line]);
+        assert_eq!(engine.pick([-0.9, 0.9, -0.9, 0.9], 2.0), []);
+        engine.draw_frame();
+        assert_eq!(&engine.pixels()[24..28], &[0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn clipped() {
+        let mut engine = blank(4, 4);
+        engine.add_line([-3.0, 5.0, 3.0, -5.0], [0.0, 0.0, 0.0]);
+        engine.add_line([2.0, 2.0, 4.0, 4.0], [0.0, 0.0, 0.0]);
+        engine.add_line([0.0, 0.0, 0.0, 0.0], [0.0, 0.0, 0.0]);
+        assert_eq!(trace(&mut engine), "..#.\n..#.\n.#..\n.#..\n");
+
+        engine.resize(2, 1);
+        assert_eq!(engine.size(), [2, 1]);
+        assert_eq!(trace(&mut engine), "##\n");
+    }
+}
@@ -0,0 +1,854 @@
+// STARK, a system for computer augmented design.
+// Copyright (C) 2021 Matthew Rothlisberger
+
+// STARK is licensed under the terms of the GNU Affero General Public
+// License. See the top level LICENSE file for the license text.
+
+// Find full copyright information in the top level COPYRIGHT file.
+
+// <>
+
+// src/graphics/tess.rs
+
+// Tessellation: breaks shapes the engines cannot draw directly into
+// triangles and line segments. Polygons with holes are cut open along
+// bridges joining each hole to the outline, then triangulated by ear
+// clipping; curves are flattened finely enough for the scale they are
+// drawn at. Strokes wider than a pixel are dashed, then expanded into
+// triangles with their caps and joins, in pixels.
+
+// <>
+
+use std::cmp::Ordering;
+
+type Point = [f32; 2];
+
+/// Farthest, in pixels, a flattened curve may stray from the true one
+const TOLERANCE: f32 = 0.25;
+
+/// Most segments one curve is broken into
+const MAX_SEGMENTS: usize = 1 << 14;
+
+/// Longest a miter may reach past its corner, in stroke widths, before
+/// the join is beveled instead
+const MITER_LIMIT: f32 = 4.0;
+
+/// Curves drawn as runs of line segments
+#[derive(Clone, Debug, PartialEq)]
+pub enum Curve {
+    /// Part of an ellipse with axes along x and y, given its center,
+    /// radii, and start and sweep angles in radians (turning from x
+    /// toward y); a full turn closes it
+    Arc {
+        center: Point,
+        radii: [f32; 2],
+        start: f32,
+        sweep: f32,
+    },
+    /// Quadratic Bézier curve: start, control, and end points
+    Quadratic([Point; 3]),
+    /// Cubic Bézier curve: start, two control, and end points
+    Cubic([Point; 4]),
+    /// Points joined in order by straight segments
+    Polyline(Vec<Point>),
+}
+
+impl Curve {
+    /// Points along the curve, close enough that the segments between
+    /// them stray no more than `TOLERANCE` pixels from it, when drawn
+    /// at `scale` pixels per unit along x and y
+    pub fn flatten(&self, scale: [f32; 2]) -> Vec<Point> {
+        match self {
+            Curve::Arc {
+                center,
+                radii,
+                start,
+                sweep,
+            } => {
+                let r = (radii[0] * scale[0]).abs().max((radii[1] * scale[1]).abs());
+
+                // each segment's sagitta, r (1 - cos(step / 2)), must
+                // stay within tolerance
+                let step = if r > TOLERANCE {
+                    2.0 * (1.0 - TOLERANCE / r).acos()
+                } else {
+                    std::f32::consts::PI
+                };
+                let n = segments(sweep.abs() / step);
+
+                (0..=n)
+                    .map(|i| {
+                        let t = start + sweep * i as f32 / n as f32;
+                        [
+                            center[0] + radii[0] * t.cos(),
+                            center[1] + radii[1] * t.sin(),
+                        ]
+                    })
+                    .collect()
+            }
+            Curve::Quadratic(p) => {
+                // the second derivative is constant, 2 (p0 - 2 p1 + p2),
+                // and a step h strays at most a quarter of it times h^2
+                let d = pixels(sub(add(p[0], p[2]), mul(p[1], 2.0)), scale);
+                let n = segments((d / (4.0 * TOLERANCE)).sqrt());
+
+                (0..=n).map(|i| quadratic(p, i as f32 / n as f32)).collect()
+            }
+            Curve::Cubic(p) => {
+                // the second derivative is at most six times the larger
+                // of these, and a step h strays an eighth of it times h^2
+                let d = pixels(sub(add(p[0], p[2]), mul(p[1], 2.0)), scale)
+                    .max(pixels(sub(add(p[1], p[3]), mul(p[2], 2.0)), scale));
+                let n = segments((6.0 * d / (8.0 * TOLERANCE)).sqrt());
+
+                (0..=n).map(|i| cubic(p, i as f32 / n as f32)).collect()
+            }
+            Curve::Polyline(points) => points.clone(),
+        }
+    }
+}
+
+/// Segments needed for a curve, from a fractional estimate
+fn segments(estimate: f32) -> usize {
+    (estimate.ceil() as usize).clamp(1, MAX_SEGMENTS)
+}
+
+/// Length in pixels of a vector, at `scale` pixels per unit
+fn pixels(a: Point, scale: [f32; 2]) -> f32 {
+    len([a[0] * scale[0], a[1] * scale[1]])
+}
+
+/// Point at `t` along a quadratic curve
+fn quadratic(p: &[Point; 3], t: f32) -> Point {
+    let u = 1.0 - t;
+    add(
+        add(mul(p[0], u * u), mul(p[1], 2.0 * u * t)),
+        mul(p[2], t * t),
+    )
+}
+
+/// Point at `t` along a cubic curve
+fn cubic(p: &[Point; 4], t: f32) -> Point {
+    let u = 1.0 - t;
+    add(
+        add(mul(p[0], u * u * u), mul(p[1], 3.0 * u * u * t)),
+        add(mul(p[2], 3.0 * u * t * t), mul(p[3], t * t * t)),
+    )
+}
+
+fn add(a: Point, b: Point) -> Point {
+    [a[0] + b[0], a[1] + b[1]]
+}
+
+fn sub(a: Point, b: Point) -> Point {
+    [a[0] - b[0], a[1] - b[1]]
+}
+
+fn mul(a: Point, k: f32) -> Point {
+    [a[0] * k, a[1] * k]
+}
+
+fn len(a: Point) -> f32 {
+    (a[0] * a[0] + a[1] * a[1]).sqrt()
+}
+
+/// How the open ends of a stroke are drawn
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cap {
+    /// Square, at the end point
+    Butt,
+    /// Round, centered on the end point
+    Round,
+    /// Square, reaching half the width past the end point
+    Square,
+}
+
+/// How a stroke turns the corners between its segments
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Join {
+    /// Sharp, unless the miter would reach too far
+    Miter,
+    Round,
+    /// Cut off across the corner
+    Bevel,
+}
+
+/// Units a stroke's width and dashes are measured in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Units {
+    /// Pixels on screen, whatever the scale of the view
+    Pixels,
+    /// Units of the coordinates drawn in, which grow and shrink with
+    /// the view
+    World,
+}
+
+/// How a line or curve is drawn: its width, dashes, and the shape of
+/// its ends and corners
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stroke {
+    pub width: f32,
+    pub units: Units,
+    /// Lengths of alternating dashes and gaps, starting with a dash;
+    /// empty for a solid line
+    pub dashes: Vec<f32>,
+    pub cap: Cap,
+    pub join: Join,
+}
+
+impl Default for Stroke {
+    /// A solid hairline, one pixel wide
+    fn default() -> Self {
+        Self {
+            width: 1.0,
+            units: Units::Pixels,
+            dashes: vec![],
+            cap: Cap::Butt,
+            join: Join::Miter,
+        }
+    }
+}
+
+/// Line segments and triangles covering a stroked path
+#[derive(Debug, Default)]
+pub struct Stroked {
+    pub lines: Vec<[f32; 4]>,
+    pub triangles: Vec<[f32; 6]>,
+}
+
+impl Stroke {
+    /// Whether the stroke is drawn as one pixel lines rather than filled
+    pub fn hairline(&self) -> bool {
+        self.units == Units::Pixels && (self.width - 1.0).abs() < f32::EPSILON
+    }
+
+    /// Lines or triangles drawing a flattened path with this stroke, at
+    /// `scale` pixels per unit along x and y
+    ///
+    /// World units are taken at the mean of the two scales. A path
+    /// which ends where it starts is closed, with a join in place of
+    /// its caps, unless it is dashed.
+    pub fn apply(&self, path: &[Point], scale: [f32; 2]) -> Stroked {
+        let unit = match self.units {
+            Units::Pixels => 1.0,
+            Units::World => (scale[0] * scale[1]).sqrt(),
+        };
+
+        let mut points: Vec<Point> = path
+            .iter()
+            .map(|p| [p[0] * scale[0], p[1] * scale[1]])
+            .collect();
+        points.dedup();
+
+        let closed = points.len() > 2 && len(sub(points[0], points[points.len() - 1])) < 1e-3;
+        if closed {
+            let first = points[0];
+            *points.last_mut().unwrap() = first;
+        }
+
+        // an odd number of lengths is repeated, so dashes and gaps
+        // alternate; patterns too short to see are drawn solid
+        let mut pattern: Vec<f32> = self.dashes.iter().map(|d| d * unit).collect();
+        if pattern.len() % 2 == 1 {
+            pattern = pattern.repeat(2);
+        }
+        let dashed = pattern.iter().all(|d| *d >= 0.0) && pattern.iter().sum::<f32>() > TOLERANCE;
+
+        let pieces = if dashed {
+            dash(&points, &pattern)
+        } else {
+            vec![points]
+        };
+        let unscale = |p: Point| [p[0] / scale[0], p[1] / scale[1]];
+
+        let mut out = Stroked::default();
+        for piece in pieces.iter().filter(|piece| piece.len() > 1) {
+            if self.hairline() {
+                for pair in piece.windows(2) {
+                    let (a, b) = (unscale(pair[0]), unscale(pair[1]));
+                    out.lines.push([a[0], a[1], b[0], b[1]]);
+                }
+            } else {
+                for tri in self.expand(piece, self.width * unit / 2.0, closed && !dashed) {
+                    let (a, b, c) = (unscale(tri[0]), unscale(tri[1]), unscale(tri[2]));
+                    out.triangles.push([a[0], a[1], b[0], b[1], c[0], c[1]]);
+                }
+            }
+        }
+
+        out
+    }
+
+    /// Triangles covering a path in pixels, out to `half` pixels either
+    /// side of it
+    fn expand(&self, path: &[Point], half: f32, closed: bool) -> Vec<[Point; 3]> {
+        let mut tris = vec![];
+        let n = path.len();
+
+        for pair in path.windows(2) {
+            let off = mul(normal(pair[0], pair[1]), half);
+            let (a0, a1) = (add(pair[0], off), sub(pair[0], off));
+            let (b0, b1) = (add(pair[1], off), sub(pair[1], off));
+            tris.push([a0, a1, b1]);
+            tris.push([a0, b1, b0]);
+        }
+
+        // a closed path also turns a corner where it meets itself
+        let corners = if closed { 1..n } else { 1..n - 1 };
+        for i in corners {
+            let next = if i == n - 1 { path[1] } else { path[i + 1] };
+            self.join(path[i - 1], path[i], next, half, &mut tris);
+        }
+
+        if !closed {
+            let start = sub(path[0], path[1]);
+            let end = sub(path[n - 1], path[n - 2]);
+            self.cap(path[0], mul(start, 1.0 / len(start)), half, &mut tris);
+            self.cap(path[n - 1], mul(end, 1.0 / len(end)), half, &mut tris);
+        }
+
+        tris
+    }
+
+    /// Adds triangles filling the outside of the corner at `v`
+    fn join(&self, prev: Point, v: Point, next: Point, half: f32, tris: &mut Vec<[Point; 3]>) {
+        let (n0, n1) = (normal(prev, v), normal(v, next));
+
+        // straight on, there is no corner to fill
+        if (n0[0] * n1[1] - n0[1] * n1[0]).abs() < 1e-4 && n0[0] * n1[0] + n0[1] * n1[1] > 0.0 {
+            return;
+        }
+
+        // the path turns toward its left normal when this is positive,
+        // leaving the gap between segments on the right
+        let side = if cross(prev, v, next) > 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        let a = add(v, mul(n0, side * half));
+        let b = add(v, mul(n1, side * half));
+
+        match self.join {
+            Join::Miter => {
+                // the miter reaches half / cos(turn / 2) along the
+                // bisector of the normals, and |n0 + n1| = 2 cos(turn / 2)
+                let bisector = add(n0, n1);
+                let length = len(bisector);
+                if length > 2.0 / MITER_LIMIT {
+                    let m = add(v, mul(bisector, side * 2.0 * half / (length * length)));
+                    tris.push([v, a, m]);
+                    tris.push([v, m, b]);
+                } else {
+                    tris.push([v, a, b]);
+                }
+            }
+            Join::Round => {
+                let start = (a[1] - v[1]).atan2(a[0] - v[0]);
+                let mut sweep = (b[1] - v[1]).atan2(b[0] - v[0]) - start;
+                if sweep > std::f32::consts::PI {
+                    sweep -= 2.0 * std::f32::consts::PI;
+                } else if sweep < -std::f32::consts::PI {
+                    sweep += 2.0 * std::f32::consts::PI;
+                }
+                fan(v, half, start, sweep, tris);
+            }
+            Join::Bevel => tris.push([v, a, b]),
+        }
+    }
+
+    /// Adds triangles for the cap at `end`, facing the unit direction
+    /// `out`
+    fn cap(&self, end: Point, out: Point, half: f32, tris: &mut Vec<[Point; 3]>) {
+        let across = mul([-out[1], out[0]], half);
+
+        match self.cap {
+            Cap::Butt => (),
+            Cap::Round => fan(
+                end,
+                half,
+                across[1].atan2(across[0]),
+                -std::f32::consts::PI,
+                tris,
+            ),
+            Cap::Square => {
+                let (a, b) = (add(end, across), sub(end, across));
+                let reach = mul(out, half);
+                tris.push([a, b, add(b, reach)]);
+                tris.push([a, add(b, reach), add(a, reach)]);
+            }
+        }
+    }
+}
+
+/// Unit normal to the segment from `a` to `b`, turned a quarter from x
+/// toward y relative to it
+fn normal(a: Point, b: Point) -> Point {
+    let d = sub(b, a);
+    let l = len(d);
+    [-d[1] / l, d[0] / l]
+}
+
+/// Adds triangles filling part of a circle, from the angle `start`
+/// through `sweep`, flattened as arcs are
+fn fan(center: Point, radius: f32, start: f32, sweep: f32, tris: &mut Vec<[Point; 3]>) {
+    let step = if radius > TOLERANCE {
+        2.0 * (1.0 - TOLERANCE / radius).acos()
+    } else {
+        std::f32::consts::PI
+    };
+    let n = segments(sweep.abs() / step);
+
+    let at = |i: usize| {
+        let t = start + sweep * i as f32 / n as f32;
+        [center[0] + radius * t.cos(), center[1] + radius * t.sin()]
+    };
+
+    for i in 0..n {
+        tris.push([center, at(i), at(i + 1)]);
+    }
+}
+
+/// Splits a path into the pieces under the dashes of a pattern, which
+/// has an even number of lengths
+fn dash(path: &[Point], pattern: &[f32]) -> Vec<Vec<Point>> {
+    let mut pieces = vec![];
+    let mut current = vec![path[0]];
+    let (mut at, mut on) = (0, true);
+    let mut left = pattern[0];
+
+    for pair in path.windows(2) {
+        let (mut a, b) = (pair[0], pair[1]);
+        let mut remaining = len(sub(b, a));
+
+        // each time a dash or gap runs out partway along the segment,
+        // the path is cut there
+        while remaining > left {
+            let cut = add(a, mul(sub(b, a), left / remaining));
+            if on {
+                current.push(cut);
+                pieces.push(std::mem::take(&mut current));
+            } else {
+                current = vec![cut];
+            }
+
+            remaining -= left;
+            a = cut;
+            on = !on;
+            at = (at + 1) % pattern.len();
+            left = pattern[at];
+        }
+
+        left -= remaining;
+        if on {
+            current.push(b);
+        }
+    }
+
+    if on {
+        pieces.push(current);
+    }
+
+    for piece in pieces.iter_mut() {
+        piece.dedup();
+    }
+    pieces
+}
+
+/// Triangulates a polygon, given as its outline followed by any holes;
+/// each triangle is three points, flattened
+///
+/// Rings may wind either way and need not repeat their first point.
+/// Rings which cross themselves or each other may be filled only in
+/// part.
+pub fn triangulate(rings: &[Vec<Point>]) -> Vec<[f32; 6]> {
+    let mut outline = match rings.first() {
+        Some(ring) => clean(ring),
+        None => return vec![],
+    };
+
+    if outline.len() < 3 {
+        return vec![];
+    }
+
+    if area(&outline) < 0.0 {
+        outline.reverse();
+    }
+
+    let mut holes: Vec<Vec<Point>> = rings[1..]
+        .iter()
+        .map(|ring| clean(ring))
+        .filter(|ring| ring.len() >= 3)
+        .map(|mut ring| {
+            if area(&ring) > 0.0 {
+                ring.reverse();
+            }
+            ring
+        })
+        .collect();
+
+    // the rightmost hole first, so each bridge leads toward the outline
+    holes.sort_by(|a, b| max_x(b).partial_cmp(&max_x(a)).unwrap_or(Ordering::Equal));
+
+    for h in 0..holes.len() {
+        bridge(&mut outline, &holes[h], &holes[h + 1..]);
+    }
+
+    clip_ears(&outline)
+}
+
+/// Drops repeated points, including a closing copy of the first
+fn clean(ring: &[Point]) -> Vec<Point> {
+    let mut out: Vec<Point> = vec![];
+
+    for &p in ring {
+        if out.last() != Some(&p) {
+            out.push(p);
+        }
+    }
+
+    while out.len() > 1 && out.first() == out.last() {
+        out.pop();
+    }
+
+    out
+}
+
+/// Signed area of a ring, positive when it winds counterclockwise
+fn area(ring: &[Point]) -> f32 {
+    let mut sum = 0.0;
+
+    for i in 0..ring.len() {
+        let (a, b) = (ring[i], ring[(i + 1) % ring.len()]);
+        sum += a[0] * b[1] - b[0] * a[1];
+    }
+
+    sum / 2.0
+}
+
+fn max_x(ring: &[Point]) -> f32 {
+    ring.iter().map(|p| p[0]).fold(f32::MIN, f32::max)
+}
+
+/// Twice the signed area of the triangle `o a b`; positive when `b`
+/// is left of the line from `o` through `a`
+fn cross(o: Point, a: Point, b: Point) -> f32 {
+    (a[0] - o[0]) * (b[1] - o[1]) - (a[1] - o[1]) * (b[0] - o[0])
+}
+
+/// Whether segments `p q` and `r s` cross at a point inside both
+fn crosses(p: Point, q: Point, r: Point, s: Point) -> bool {
+    let (d1, d2) = (cross(p, q, r), cross(p, q, s));
+    let (d3, d4) = (cross(r, s, p), cross(r, s, q));
+
+    ((d1 > 0.0 && d2 < 0.0) || (d1 < 0.0 && d2 > 0.0))
+        && ((d3 > 0.0 && d4 < 0.0) || (d3 < 0.0 && d4 > 0.0))
+}
+
+/// Whether segment `p q` crosses any edge of a ring
+fn crosses_ring(p: Point, q: Point, ring: &[Point]) -> bool {
+    (0..ring.len()).any(|i| crosses(p, q, ring[i], ring[(i + 1) % ring.len()]))
+}
+
+/// Joins a hole into the outline, along a bridge from the hole's
+/// rightmost point to the nearest outline point it can see
+///
+/// The outline walks across the bridge, around the hole, and back, so
+/// it stays one ring with the filled area on its left.
+fn bridge(outline: &mut Vec<Point>, hole: &[Point], later: &[Vec<Point>]) {
+    let m = (0..hole.len())
+        .max_by(|&a, &b| {
+            hole[a][0]
+                .partial_cmp(&hole[b][0])
+                .unwrap_or(Ordering::Equal)
+        })
+        .unwrap();
+    let mp = hole[m];
+
+    let n = outline.len();
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|&a, &b| {
+        dist(outline[a], mp)
+            .partial_cmp(&dist(outline[b], mp))
+            .unwrap_or(Ordering::Equal)
+    });
+
+    let visible = |i: usize| {
+        let (a, p, b) = (outline[(i + n - 1) % n], outline[i], outline[(i + 1) % n]);
+
+        // the bridge must leave the outline point on its inner side
+        let inside = if cross(a, p, b) >= 0.0 {
+            cross(a, p, mp) > 0.0 && cross(p, b, mp) > 0.0
+        } else {
+            cross(a, p, mp) > 0.0 || cross(p, b, mp) > 0.0
+        };
+
+        inside
+            && !crosses_ring(mp, p, outline)
+            && !crosses_ring(mp, p, hole)
+            && !later.iter().any(|h| crosses_ring(mp, p, h))
+    };
+
+    let i = match order.into_iter().find(|&i| visible(i)) {
+        Some(i) => i,
+        None => return,
+    };
+
+    let mut joined = Vec::with_capacity(n + hole.len() + 2);
+    joined.extend_from_slice(&outline[..=i]);
+    joined.extend_from_slice(&hole[m..]);
+    joined.extend_from_slice(&hole[..=m]);
+    joined.extend_from_slice(&outline[i..]);
+
+    *outline = joined;
+}
+
+fn dist(a: Point, b: Point) -> f32 {
+    (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2)
+}
+
+/// Whether `p` is inside or on the edge of the triangle `a b c`, which
+/// winds counterclockwise
+fn in_triangle(p: Point, a: Point, b: Point, c: Point) -> bool {
+    cross(a, b, p) >= 0.0 && cross(b, c, p) >= 0.0 && cross(c, a, p) >= 0.0
+}
+
+/// Triangulates a counterclockwise ring by cutting off one ear (a
+/// corner with no other point inside it) at a time
+fn clip_ears(ring: &[Point]) -> Vec<[f32; 6]> {
+    let mut idx: Vec<usize> = (0..ring.len()).collect();
+    let mut out = vec![];
+
+    let mut i = 0;
+    let mut stalled = 0;
+
+    while idx.len() >= 3 {
+        let n = idx.len();
+        i %= n;
+
+        let (a, b, c) = (
+            ring[idx[(i + n - 1) % n]],
+            ring[idx[i]],
+            ring[idx[(i + 1) % n]],
+        );
+        let turn = cross(a, b, c);
+
+        // a point in a straight run, or the tip of a spike, covers no
+        // area and can simply be dropped
+        let ear = turn == 0.0
+            || (turn > 0.0
+                && !idx.iter().any(|&j| {
+                    let p = ring[j];
+                    p != a && p != b && p != c && in_triangle(p, a, b, c)
+                }));
+
+        if ear {
+            if turn > 0.0 {
+                out.push([a[0], a[1], b[0], b[1], c[0], c[1]]);
+            }
+            idx.remove(i);
+            stalled = 0;
+        } else {
+            i += 1;
+            stalled += 1;
+
+            // every corner has been tried; the ring must cross itself
+            if stalled > n {
+                break;
+            }
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn total_area(tris: &[[f32; 6]]) -> f32 {
+        tris.iter()
+            .map(|t| {
+                let turn = cross([t[0], t[1]], [t[2], t[3]], [t[4], t[5]]);
+                assert!(turn > 0.0);
+                turn / 2.0
+            })
+            .sum()
+    }
+
+    fn square(x: f32, y: f32, s: f32) -> Vec<Point> {
+        vec![[x, y], [x + s, y], [x + s, y + s], [x, y + s]]
+    }
+
+    #[test]
+    fn simple() {
+        assert_eq!(triangulate(&[]).len(), 0);
+        assert_eq!(triangulate(&[vec![[0.0, 0.0], [1.0, 1.0]]]).len(), 0);
+
+        let tris = triangulate(&[square(0.0, 0.0, 1.0)]);
+        assert_eq!(tris.len(), 2);
+        assert_eq!(total_area(&tris), 1.0);
+
+        // clockwise, closed, and concave
+        let ell = vec![
+            [0.0, 0.0],
+            [0.0, 2.0],
+            [1.0, 2.0],
+            [1.0, 1.0],
+            [2.0, 1.0],
+            [2.0, 0.0],
+            [0.0, 0.0],
+        ];
+        let tris = triangulate(&[ell]);
+        assert_eq!(tris.len(), 4);
+        assert_eq!(total_area(&tris), 3.0);
+    }
+
+    #[test]
+    fn curves() {
+        let circle = Curve::Arc {
+            center: [0.0, 0.0],
+            radii: [1.0, 1.0],
+            start: 0.0,
+            sweep: 2.0 * std::f32::consts::PI,
+        };
+
+        let near = circle.flatten([100.0, 100.0]);
+        let far = circle.flatten([1000.0, 1000.0]);
+        assert!(far.len() > 3 * near.len());
+        assert!((near[0][0] - 1.0).abs() < 1e-6);
+        assert!((near[near.len() - 1][0] - 1.0).abs() < 1e-6);
+
+        // every segment's middle is within tolerance of the circle
+        for pair in near.windows(2) {
+            let mid = mul(add(pair[0], pair[1]), 0.5);
+            assert!((1.0 - len(mid)) * 100.0 <= TOLERANCE + 1e-3);
+        }
+
+        let quad = Curve::Quadratic([[0.0, 0.0], [1.0, 2.0], [2.0, 0.0]]);
+        let points = quad.flatten([50.0, 50.0]);
+        assert_eq!(points[0], [0.0, 0.0]);
+        assert_eq!(points[points.len() - 1], [2.0, 0.0]);
+        assert!(points.len() > 8);
+
+        let cube = [[0.0, 0.0], [0.0, 1.0], [1.0, 1.0], [1.0, 0.0]];
+        let points = Curve::Cubic(cube).flatten([50.0, 50.0]);
+        for pair in points.windows(2) {
+            let t = 0.5 * (find_t(&cube, pair[0]) + find_t(&cube, pair[1]));
+            let mid = mul(add(pair[0], pair[1]), 0.5);
+            assert!(len(sub(cubic(&cube, t), mid)) * 50.0 <= TOLERANCE);
+        }
+
+        // straight lines need no more than one segment
+        let line = Curve::Cubic([[0.0, 0.0], [1.0, 1.0], [2.0, 2.0], [3.0, 3.0]]);
+        assert_eq!(line.flatten([1000.0, 1000.0]).len(), 2);
+
+        let poly = vec![[0.0, 0.0], [1.0, 0.0], [1.0, 1.0]];
+        assert_eq!(Curve::Polyline(poly.clone()).flatten([1.0, 1.0]), poly);
+    }
+
+    /// Total area of a set of triangles
+    fn area_of(tris: &[[f32; 6]]) -> f32 {
+        tris.iter()
+            .map(|t| cross([t[0], t[1]], [t[2], t[3]], [t[4], t[5]]).abs() / 2.0)
+            .sum()
+    }
+
+    #[test]
+    fn strokes() {
+        let line = [[0.0, 0.0], [10.0, 0.0]];
+        let corner = [[0.0, 0.0], [10.0, 0.0], [10.0, 10.0]];
+
+        let hairline = Stroke::default().apply(&line, [1.0, 1.0]);
+        assert_eq!(hairline.lines, vec![[0.0, 0.0, 10.0, 0.0]]);
+        assert!(hairline.triangles.is_empty());
+
+        let mut stroke = Stroke {
+            width: 4.0,
+            ..Stroke::default()
+        };
+        assert!((area_of(&stroke.apply(&line, [1.0, 1.0]).triangles) - 40.0).abs() < 1e-3);
+
+        // widths in world units follow the scale
+        let world = Stroke {
+            width: 0.04,
+            units: Units::World,
+            ..Stroke::default()
+        };
+        let scaled = world.apply(&[[0.0, 0.0], [0.1, 0.0]], [100.0, 100.0]);
+        assert!((area_of(&scaled.triangles) - 0.004).abs() < 1e-6);
+
+        stroke.cap = Cap::Square;
+        assert!((area_of(&stroke.apply(&line, [1.0, 1.0]).triangles) - 56.0).abs() < 1e-3);
+        stroke.cap = Cap::Round;
+        let round = stroke.apply(&line, [1.0, 1.0]).triangles;
+        let xs = round.iter().flat_map(|t| t.iter().step_by(2));
+        assert!(xs.clone().all(|x| *x >= -2.0 && *x <= 12.0));
+        assert!(xs.clone().any(|x| *x < -1.9) && xs.clone().any(|x| *x > 11.9));
+        let area = area_of(&round);
+        assert!(area < 40.0 + 4.0 * std::f32::consts::PI && area > 40.0 + 11.0);
+
+        // the segments' rectangles overlap by 4 inside the corner
+        stroke.cap = Cap::Butt;
+        let miter = area_of(&stroke.apply(&corner, [1.0, 1.0]).triangles);
+        assert!((miter - 84.0).abs() < 1e-3);
+        stroke.join = Join::Bevel;
+        let bevel = area_of(&stroke.apply(&corner, [1.0, 1.0]).triangles);
+        assert!((bevel - 82.0).abs() < 1e-3);
+        stroke.join = Join::Round;
+        let round = area_of(&stroke.apply(&corner, [1.0, 1.0]).triangles);
+        assert!(round > 82.0 && round < 80.0 + std::f32::consts::PI);
+
+        // too sharp a turn for a miter is beveled
+        stroke.join = Join::Miter;
+        let sharp = stroke.apply(&[[0.0, 0.0], [10.0, 0.0], [0.0, 1.0]], [1.0, 1.0]);
+        for tri in sharp.triangles.iter() {
+            assert!(tri.iter().step_by(2).all(|x| *x < 12.5));
+        }
+
+        let dashed = Stroke {
+            dashes: vec![2.0, 3.0],
+            ..Stroke::default()
+        };
+        assert_eq!(
+            dashed.apply(&corner, [1.0, 1.0]).lines,
+            vec![
+                [0.0, 0.0, 2.0, 0.0],
+                [5.0, 0.0, 7.0, 0.0],
+                [10.0, 0.0, 10.0, 2.0],
+                [10.0, 5.0, 10.0, 7.0],
+            ]
+        );
+    }
+
+    /// Parameter of the point on a curve nearest to `p`
+    fn find_t(curve: &[Point; 4], p: Point) -> f32 {
+        (0..=1000)
+            .map(|i| i as f32 / 1000.0)
+            .min_by(|&a, &b| {
+                let (da, db) = (len(sub(cubic(curve, a), p)), len(sub(cubic(curve, b), p)));
+                da.partial_cmp(&db).unwrap()
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn holes() {
+        let tris = triangulate(&[square(0.0, 0.0, 4.0), square(1.0, 1.0, 2.0)]);
+        assert_eq!(total_area(&tris), 12.0);
+
+        // no triangle covers the middle of the hole
+        for t in &tris {
+            let (a, b, c) = ([t[0], t[1]], [t[2], t[3]], [t[4], t[5]]);
+            assert!(!in_triangle([2.0, 2.0], a, b, c));
+        }
+
+        let tris = triangulate(&[
+            square(0.0, 0.0, 10.0),
+            square(1.0, 1.0, 2.0),
+            square(6.0, 6.0, 3.0),
+            square(6.0, 1.0, 1.0),
+        ]);
+        assert_eq!(total_area(&tris), 100.0 - 4.0 - 9.0 - 1.0);
+    }
+}
@@ -0,0 +1,395 @@
+// STARK, a system for computer augmented design.
+// Copyright (C) 2021 Matthew Rothlisberger
+
+// STARK is licensed under the terms of the GNU Affero General Public
+// License. See the top level LICENSE file for the license text.
+
+// Find full copyright information in the top level COPYRIGHT file.
+
+// <>
+
+// src/graphics/text.rs
+
+// Text: lays out strings in the bundled font as quads textured from a
+// glyph atlas. Each glyph is rasterized into the atlas once, at a fixed
+// size, the first time it is drawn; quads scale it to the size asked
+// for.
+
+// <>
+
+use rusttype::{point, Font, Scale};
+
+use std::collections::HashMap;
+
+type Point = [f32; 2];
+
+/// Font all text is drawn in
+const FONT: &[u8] = include_bytes!("../../fonts/DejaVuSansMono.ttf");
+
+/// Width and height of the atlas, in pixels
+pub const ATLAS_SIZE: u32 = 1024;
+
+/// Line height, in pixels, glyphs are rasterized at in the atlas
+const GLYPH_SIZE: f32 = 32.0;
+
+/// Empty pixels kept between glyphs, so filtering does not blend in
+/// their neighbours
+const PADDING: u32 = 1;
+
+/// Point of a string's box placed at its position
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    /// How far across and down the box the anchor lies, as fractions
+    /// of its width and height
+    fn fractions(self) -> [f32; 2] {
+        match self {
+            Anchor::TopLeft => [0.0, 0.0],
+            Anchor::Top => [0.5, 0.0],
+            Anchor::TopRight => [1.0, 0.0],
+            Anchor::Left => [0.0, 0.5],
+            Anchor::Center => [0.5, 0.5],
+            Anchor::Right => [1.0, 0.5],
+            Anchor::BottomLeft => [0.0, 1.0],
+            Anchor::Bottom => [0.5, 1.0],
+            Anchor::BottomRight => [1.0, 1.0],
+        }
+    }
+}
+
+/// A string to draw, and where and how large
+///
+/// The box anchored runs from the top of the first line to the
+/// baseline of the last, so descenders hang below a bottom anchor.
+/// Lines are split at newlines, and aligned to the anchor's side.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Text {
+    pub string: String,
+    /// Where the anchor lies, in drawing coordinates
+    pub position: Point,
+    /// Height of a line, in pixels
+    pub size: f32,
+    pub anchor: Anchor,
+    /// Turn counterclockwise on screen about the anchor, in radians
+    pub rotation: f32,
+}
+
+/// Glyph drawn as a textured quad, with its corners in drawing
+/// coordinates and in atlas coordinates from 0 to 1; corners run top
+/// left, top right, bottom right, bottom left
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quad {
+    pub corners: [Point; 4],
+    pub uvs: [Point; 4],
+}
+
+impl Quad {
+    /// Corners of the two triangles covering the quad, each with its
+    /// atlas coordinates
+    pub fn triangles(&self) -> [(Point, Point); 6] {
+        let corner = |i: usize| (self.corners[i], self.uvs[i]);
+        [
+            corner(0),
+            corner(1),
+            corner(2),
+            corner(0),
+            corner(2),
+            corner(3),
+        ]
+    }
+}
+
+/// Where a glyph is in the atlas, and how it sits on a line
+#[derive(Clone, Copy, Debug)]
+struct Glyph {
+    /// Left, top, width, and height in the atlas; empty for glyphs
+    /// with nothing to draw, like spaces
+    rect: [u32; 4],
+    /// Top left of the glyph's box from the pen on the baseline
+    offset: Point,
+    advance: f32,
+}
+
+/// Glyphs rasterized so far, packed in rows into one coverage image
+pub struct Atlas {
+    font: Font<'static>,
+    pixels: Vec<u8>,
+    glyphs: HashMap<char, Glyph>,
+    /// Left and top of the next free space, and the height of the row
+    /// it is in
+    shelf: [u32; 3],
+    /// Whether pixels have changed since `take_changed` was called
+    changed: bool,
+}
+
+impl Atlas {
+    /// Makes an empty atlas for the bundled font
+    pub fn new() -> Self {
+        Self {
+            font: Font::try_from_bytes(FONT).expect("bundled font is invalid"),
+            pixels: vec![0; (ATLAS_SIZE * ATLAS_SIZE) as usize],
+            glyphs: HashMap::new(),
+            shelf: [PADDING, PADDING, 0],
+            changed: false,
+        }
+    }
+
+    /// Coverage of each atlas pixel, in rows from the top
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// Whether glyphs have been added since this was last called
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::replace(&mut self.changed, false)
+    }
+
+    /// Lays out a string as glyph quads, at `scale` pixels per unit
+    /// along x and y
+    pub fn layout(&mut self, text: &Text, scale: [f32; 2]) -> Vec<Quad> {
+        let k = text.size / GLYPH_SIZE;
+        let v = self.font.v_metrics(Scale::uniform(GLYPH_SIZE));
+        let line_height = (v.ascent - v.descent + v.line_gap) * k;
+
+        let lines: Vec<&str> = text.string.split('\n').collect();
+        let widths: Vec<f32> = lines
+            .iter()
+            .map(|line| line.chars().map(|c| self.glyph(c).advance * k).sum())
+            .collect();
+        let width = widths.iter().cloned().fold(0.0, f32::max);
+
+        // measured in pixels from the pen at the start of the first
+        // line, with y growing down as on screen
+        let top = -v.ascent * k;
+        let bottom = (lines.len() - 1) as f32 * line_height;
+        let [across, down] = text.anchor.fractions();
+        let origin = [width * across, top + (bottom - top) * down];
+
+        // y grows down the screen, so turning counterclockwise on it
+        // turns clockwise in these coordinates
+        let (sin, cos) = text.rotation.sin_cos();
+        let place = |p: Point| {
+            let (x, y) = (p[0] - origin[0], p[1] - origin[1]);
+            [
+                text.position[0] + (x * cos + y * sin) / scale[0],
+                text.position[1] + (y * cos - x * sin) / scale[1],
+            ]
+        };
+
+        let mut quads = vec![];
+        for (i, line) in lines.iter().enumerate() {
+            let mut pen = (width - widths[i]) * across;
+            let baseline = i as f32 * line_height;
+
+            for c in line.chars() {
+                let glyph = self.glyph(c);
+                let [x, y, w, h] = glyph.rect;
+
+                if w > 0 {
+                    let left = pen + glyph.offset[0] * k;
+                    let top = baseline + glyph.offset[1] * k;
+                    let (right, bottom) = (left + w as f32 * k, top + h as f32 * k);
+
+                    let size = ATLAS_SIZE as f32;
+                    let (u0, v0) = (x as f32 / size, y as f32 / size);
+                    let (u1, v1) = ((x + w) as f32 / size, (y + h) as f32 / size);
+
+                    quads.push(Quad {
+                        corners: [
+                            place([left, top]),
+                            place([right, top]),
+                            place([right, bottom]),
+                            place([left, bottom]),
+                        ],
+                        uvs: [[u0, v0], [u1, v0], [u1, v1], [u0, v1]],
+                    });
+                }
+
+                pen += glyph.advance * k;
+            }
+        }
+
+        quads
+    }
+
+    /// Finds a glyph in the atlas, rasterizing it there first if it is
+    /// new
+    fn glyph(&mut self, c: char) -> Glyph {
+        if let Some(glyph) = self.glyphs.get(&c) {
+            return *glyph;
+        }
+
+        let scaled = self.font.glyph(c).scaled(Scale::uniform(GLYPH_SIZE));
+        let mut glyph = Glyph {
+            rect: [0; 4],
+            offset: [0.0, 0.0],
+            advance: scaled.h_metrics().advance_width,
+        };
+
+        let positioned = scaled.positioned(point(0.0, 0.0));
+        if let Some(bounds) = positioned.pixel_bounding_box() {
+            let (w, h) = (bounds.width() as u32, bounds.height() as u32);
+
+            match Self::allocate(&mut self.shelf, w, h) {
+                Some([x, y]) => {
+                    let pixels = &mut self.pixels;
+                    positioned.draw(|gx, gy, coverage| {
+                        let i = ((y + gy) * ATLAS_SIZE + x + gx) as usize;
+                        pixels[i] = (coverage * 255.0).round() as u8;
+                    });
+
+                    glyph.rect = [x, y, w, h];
+                    glyph.offset = [bounds.min.x as f32, bounds.min.y as f32];
+                    self.changed = true;
+                }
+                None => log::warn!("glyph atlas is full; {:?} will not be drawn", c),
+            }
+        }
+
+        self.glyphs.insert(c, glyph);
+        glyph
+    }
+
+    /// Finds room for a box in the atlas, starting a new row when the
+    /// current one is full
+    fn allocate(shelf: &mut [u32; 3], w: u32, h: u32) -> Option<[u32; 2]> {
+        let [mut x, mut y, mut row] = *shelf;
+
+        if x + w + PADDING > ATLAS_SIZE {
+            x = PADDING;
+            y += row;
+            row = 0;
+        }
+        if x + w + PADDING > ATLAS_SIZE || y + h + PADDING > ATLAS_SIZE {
+            return None;
+        }
+
+        *shelf = [x + w + PADDING, y, row.max(h + PADDING)];
+        Some([x, y])
+    }
+}
+
+impl Default for Atlas {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Samples atlas coverage at a point in atlas coordinates, blending
+/// the four nearest pixels
+pub fn sample(pixels: &[u8], uv: Point) -> f32 {
+    let size = ATLAS_SIZE as f32;
+    let (x, y) = (uv[0] * size - 0.5, uv[1] * size - 0.5);
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+
+    let at = |dx: f32, dy: f32| {
+        let (px, py) = (x0 + dx, y0 + dy);
+        if px < 0.0 || py < 0.0 || px >= size || py >= size {
+            0.0
+        } else {
+            pixels[py as usize * ATLAS_SIZE as usize + px as usize] as f32 / 255.0
+        }
+    };
+
+    let top = at(0.0, 0.0) * (1.0 - fx) + at(1.0, 0.0) * fx;
+    let bottom = at(0.0, 1.0) * (1.0 - fx) + at(1.0, 1.0) * fx;
+    top * (1.0 - fy) + bottom * fy
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(string: &str, anchor: Anchor) -> Text {
+        Text {
+            string: string.to_string(),
+            position: [0.0, 0.0],
+            size: 16.0,
+            anchor,
+            rotation: 0.0,
+        }
+    }
+
+    /// Smallest box holding every corner of some quads
+    fn bounds(quads: &[Quad]) -> [f32; 4] {
+        let corners = quads.iter().flat_map(|q| q.corners.iter());
+        corners.fold(
+            [f32::MAX, f32::MAX, f32::MIN, f32::MIN],
+            |[x0, y0, x1, y1], c| [x0.min(c[0]), y0.min(c[1]), x1.max(c[0]), y1.max(c[1])],
+        )
+    }
+
+    #[test]
+    fn atlas() {
+        let mut atlas = Atlas::new();
+        assert!(!atlas.take_changed());
+
+        let quads = atlas.layout(&text("a a", Anchor::TopLeft), [1.0, 1.0]);
+        assert_eq!(quads.len(), 2);
+        assert!(atlas.take_changed());
+
+        // glyphs already in the atlas are reused
+        assert_eq!(quads[0].uvs, quads[1].uvs);
+        atlas.layout(&text("aaa", Anchor::TopLeft), [1.0, 1.0]);
+        assert!(!atlas.take_changed());
+
+        let middle = [
+            (quads[0].uvs[0][0] + quads[0].uvs[2][0]) / 2.0,
+            (quads[0].uvs[0][1] + quads[0].uvs[2][1]) / 2.0,
+        ];
+        assert!(sample(atlas.pixels(), middle) >= 0.0);
+        assert!(atlas.pixels().contains(&255));
+
+        // a full atlas leaves glyphs out instead of overwriting others
+        let mut shelf = [PADDING, PADDING, 0];
+        assert_eq!(Atlas::allocate(&mut shelf, 600, 600), Some([1, 1]));
+        assert_eq!(Atlas::allocate(&mut shelf, 600, 10), Some([1, 602]));
+        assert_eq!(Atlas::allocate(&mut shelf, 10, 500), None);
+    }
+
+    #[test]
+    fn layout() {
+        let mut atlas = Atlas::new();
+
+        // monospaced, so every glyph advances the same
+        let [x0, _, x1, _] = bounds(&atlas.layout(&text("ll", Anchor::TopLeft), [1.0, 1.0]));
+        let one = bounds(&atlas.layout(&text("l", Anchor::TopLeft), [1.0, 1.0]));
+        let advance = x1 - x0 - (one[2] - one[0]);
+        assert!(advance > 8.0 && advance < 11.0);
+
+        let left = bounds(&atlas.layout(&text("Hi", Anchor::TopLeft), [1.0, 1.0]));
+        assert!(left[0] >= 0.0 && left[1] >= 0.0);
+
+        let right = bounds(&atlas.layout(&text("Hi", Anchor::BottomRight), [1.0, 1.0]));
+        assert!(right[2] <= 0.0 && right[3] <= 0.5);
+        assert!((right[2] - right[0] - (left[2] - left[0])).abs() < 1e-3);
+
+        let center = bounds(&atlas.layout(&text("Hi", Anchor::Center), [1.0, 1.0]));
+        assert!((center[0] + center[2]).abs() < 3.0);
+
+        // more lines reach further down, and scale shrinks the quads
+        let two = bounds(&atlas.layout(&text("Hi\nHi", Anchor::TopLeft), [1.0, 1.0]));
+        assert!(two[3] - left[3] > 15.0);
+        let half = bounds(&atlas.layout(&text("Hi", Anchor::TopLeft), [2.0, 2.0]));
+        assert!((half[2] * 2.0 - left[2]).abs() < 1e-3);
+
+        // a quarter turn counterclockwise runs the text up the screen
+        let mut turned = text("Hi", Anchor::Left);
+        turned.rotation = std::f32::consts::FRAC_PI_2;
+        let up = bounds(&atlas.layout(&turned, [1.0, 1.0]));
+        assert!(up[3] - up[1] > up[2] - up[0]);
+        assert!(up[1] < -10.0 && up[3] < 1.0);
+    }
+}
@@ -37,6 +37,7 @@ type Frame = winit::window::Window;
 pub mod context;
 pub mod graphics;
 pub mod sail;
+pub mod scripts;
 
 use sail::SlHead;
 
@@ -52,7 +53,18 @@ unsafe impl HasRawWindowHandle for FrameHandle {
 unsafe impl Send for FrameHandle {}
 
 /// Sail interpreter loop for the manager thread
-pub fn manager_loop(frame: Frame, sl_reg: usize, sl_tbl: usize, sl_env: usize) {
+///
+/// Loads `main.sl` from the search path, then evaluates the user's init
+/// file and the optional user script in the same environment before
+/// calling `main`
+pub fn manager_loop(
+    frame: Frame,
+    sl_reg: usize,
+    sl_tbl: usize,
+    sl_env: usize,
+    search: &scripts::SearchPath,
+    user_script: Option<String>,
+) {
     let (sl_tbl, sl_env) = (sl_tbl as *mut sail::SlHead, sl_env as *mut sail::SlHead);
     let sl_reg = sl_reg as *mut sail::memmgt::Region;
 
@@ -75,12 +87,14 @@ pub fn manager_loop(frame: Frame, sl_reg: usize, sl_tbl: usize, sl_env: usize) {
             return sail::nil();
         }
 
-        "cursor-pos" 5 [frm_ptr, w, h, x, y] {
+        "cursor-pos" 6 [frm_ptr, w, h, view, x, y] {
             assert_eq!(sail::get_cfg_spec(frm_ptr), sail::Cfg::B8Other);
             let frame = unsafe { &*(sail::read_field_unchecked::<u64>(frm_ptr, 0) as *const Frame) };
 
             assert_eq!(sail::core_type(w), Some(sail::CoreType::U32));
             assert_eq!(sail::core_type(h), Some(sail::CoreType::U32));
+            assert_eq!(sail::core_type(view), Some(sail::CoreType::VecArr));
+            assert_eq!(sail::arrvec_get_typ(view), sail::T_F32.0);
             assert_eq!(sail::core_type(x), Some(sail::CoreType::F32));
             assert_eq!(sail::core_type(y), Some(sail::CoreType::F32));
 
@@ -91,23 +105,36 @@ pub fn manager_loop(frame: Frame, sl_reg: usize, sl_tbl: usize, sl_env: usize) {
                 sail::f32_get(y),
             );
 
+            // the position is in world coordinates, seen through the view
+            let view: Vec<f32> = (0..sail::arrvec_get_len(view) as usize)
+                .map(|i| unsafe {
+                    std::ptr::read_unaligned(sail::value_ptr(view).add(8 + 4 * i) as *const f32)
+                })
+                .collect();
+            let pixel = graphics::camera::Camera::from_slice(&view)
+                .to_pixel([x, y], [w as f32, h as f32]);
+
             frame
                 .set_cursor_position(winit::dpi::Position::Physical(
                     winit::dpi::PhysicalPosition {
-                        x: ((x + 1.0) * (w / 2) as f32) as i32,
-                        y: ((y + 1.0) * (h / 2) as f32) as i32,
+                        x: pixel[0] as i32,
+                        y: pixel[1] as i32,
                     },
                 ))
                 .unwrap();
 
             return sail::nil();
         }
+
+        "obj-new" 0 [] {
+            // the render thread's scene takes its IDs from the same place
+            return sail::i64_init(_reg, graphics::scene::new_id() as i64);
+        }
     }
 
     sail::insert_native_procs(sl_reg, sl_tbl, sl_env, mngr_fns);
 
-    let prog_txt = &std::fs::read_to_string("scripts/main.sl").unwrap();
-    let prog_expr = sail::parser::parse(sl_reg, sl_tbl, prog_txt).unwrap();
+    let prog_expr = search.main_script(sl_reg, sl_tbl);
 
     let mut stack = sail::eval::EvalStack::new(10000);
 
@@ -122,6 +149,40 @@ pub fn manager_loop(frame: Frame, sl_reg: usize, sl_tbl: usize, sl_env: usize) {
         stack.iter_once(sl_reg, sl_tbl);
     }
 
+    let user_files = scripts::user_init()
+        .into_iter()
+        .chain(user_script.map(std::path::PathBuf::from));
+
+    for path in user_files {
+        if sail::exit_requested() {
+            break;
+        }
+
+        match std::fs::read_to_string(&path) {
+            Ok(text) => match sail::parser::parse_source(
+                sl_reg,
+                sl_tbl,
+                &text,
+                &path.display().to_string(),
+            ) {
+                Ok(expr) => {
+                    ret_slot = sigil;
+                    stack.start(ret_addr, sl_env, expr);
+
+                    while ret_slot == sigil {
+                        stack.iter_once(sl_reg, sl_tbl);
+                    }
+                }
+                Err(err) => log::error!("could not parse {}: {:?}", path.display(), err),
+            },
+            Err(_) => log::error!("could not read {}", path.display()),
+        }
+    }
+
+    if sail::exit_requested() {
+        return;
+    }
+
     let main = sail::env_lookup_by_id(sl_env, sail::S_MAIN.0);
 
     stack.push_frame_head(ret_addr, sail::eval::Opcode::Apply, sl_env);
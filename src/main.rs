@@ -23,55 +23,374 @@
 
 // Main function for STARK, which starts everything up. The program
 // may be started with a full graphical interface, or as a Sail REPL,
-// or may simply execute a Sail file.
+// or may simply execute, evaluate, or check Sail code, according to
+// the command given on the command line.
 
 // <>
 
-use stark::{context, graphics, manager_loop, sail, FrameHandle};
+use sail::queue::Overflow;
+use stark::{context, graphics, manager_loop, sail, scripts, FrameHandle};
 
 use raw_window_handle::HasRawWindowHandle;
 
 use std::env;
 use std::io;
+use std::process;
+use std::sync::atomic::Ordering;
 use std::thread;
 
 // TODO: Have a static base Sail environment so that native functions
 // may be added from anywhere?
 
+/// Exit status for a successful run
+const EXIT_OK: i32 = 0;
+/// Exit status when Sail code fails to parse or evaluate
+const EXIT_SAIL_ERR: i32 = 1;
+/// Exit status for an invalid command line
+const EXIT_USAGE: i32 = 2;
+/// Exit status when the REPL is ended with Ctrl-C
+const EXIT_INTERRUPTED: i32 = 130;
+
+/// Size in pixels of images written by `render`, unless asked otherwise
+const RENDER_SIZE: [u32; 2] = [1280, 720];
+
+const USAGE: &str = "\
+Usage: stark [OPTIONS] [COMMAND]
+
+Commands:
+  gui [script]            Run the graphical interface (default); the
+                          optional script is evaluated in the manager
+                          environment before the main loop starts
+  repl                    Run a Sail REPL at the command line
+  run <file> [args...]    Execute a Sail file
+  eval <expr>             Evaluate a Sail expression and print the result
+  check <file>            Parse a Sail file without evaluating it
+  render <file> -o <png>  Run a Sail file that draws with `engine`, without
+                          a window, and save what it drew as a PNG image
+
+Options:
+  --log-level <level>     off, error, warn, info, debug, or trace
+  --heap-size <bytes>     Size of each Sail memory zone (K and M suffixes allowed)
+  --script-dir <dir>      Directory searched first for main.sl, rndr.sl, and
+                          icon.png
+  --profile <file>        Profile the Sail code run by gui, run, or eval,
+                          writing folded stacks to the file and a summary
+                          to stderr
+  --profile-every <steps> Evaluation steps between profiler samples
+  --mem-stats             Print memory statistics for the main Sail region
+                          when gui, run, or eval finishes
+  --heap-dump <file>      Write every object in the main Sail region to the
+                          file when gui, run, or eval finishes
+  --image <file>          Boot gui, repl, run, eval, or render from an image
+                          written by image-save instead of a fresh
+                          environment
+  -o, --output <file>     Image file written by render
+  --size <w>x<h>          Size in pixels of the image written by render
+  --software              Draw the image written by render on the CPU, so
+                          no graphics driver is needed
+  -h, --help              Print this message
+  -V, --version           Print the version";
+
+/// Operating mode, selected by the command given on the command line
+enum Mode {
+    Gui(Option<String>),
+    Repl,
+    Run(String, Vec<String>),
+    Eval(String),
+    Check(String),
+    Render(String),
+}
+
+/// Options which apply to every mode
+#[derive(Clone)]
+struct Options {
+    log_level: log::LevelFilter,
+    heap_size: usize,
+    script_dir: Option<String>,
+    profile: Option<String>,
+    profile_every: u32,
+    mem_stats: bool,
+    heap_dump: Option<String>,
+    image: Option<String>,
+    output: Option<String>,
+    size: [u32; 2],
+    software: bool,
+}
+
 fn main() {
-    const NAME: &'static str = "STARK";
-    const ICON: &'static str = "icons/icon.png";
-    const SIZE: [u32; 2] = [1280, 720];
+    let (mode, opts) = match parse_args(env::args().skip(1)) {
+        Ok(out) => out,
+        Err(msg) => {
+            eprintln!("stark: {}\n\n{}", msg, USAGE);
+            process::exit(EXIT_USAGE);
+        }
+    };
 
     // TODO: add useful logging throughout the program
     simple_logger::SimpleLogger::new()
-        .with_level(log::LevelFilter::Debug)
+        .with_level(opts.log_level)
         .init()
         .unwrap();
 
-    // cargo run file <filename> to run a Sail file
-    // cargo run repl for Sail REPL
-    let args: Vec<String> = env::args().collect();
-    if args.len() >= 3 {
-        match sail::run_file(&args[2]) {
-            Ok(out) => println!("{}", out),
-            Err(err) => println!("{:?}", err),
+    sail::heap::report_on_exit(opts.mem_stats, opts.heap_dump.clone());
+    sail::image::boot_from(opts.image.clone());
+
+    let status = match mode {
+        Mode::Gui(script) => {
+            run_gui(&opts, script);
+            EXIT_OK
+        }
+        Mode::Repl => {
+            interrupt_on_ctrl_c();
+            sail::repl(io::stdin(), opts.heap_size);
+            sail::exit_status().unwrap_or(EXIT_OK)
+        }
+        Mode::Run(file, args) => report(profiled(&opts, || {
+            sail::run_file(&file, &args, opts.heap_size)
+        })),
+        Mode::Eval(expr) => report(profiled(&opts, || sail::interpret(&expr, opts.heap_size))),
+        Mode::Render(file) => {
+            let out = opts.output.as_deref().unwrap();
+            match profiled(&opts, || {
+                graphics::render_to_png(
+                    "STARK",
+                    opts.size,
+                    &file,
+                    out,
+                    opts.heap_size,
+                    opts.software,
+                )
+            }) {
+                Ok(()) => sail::exit_status().unwrap_or(EXIT_OK),
+                Err(msg) => {
+                    eprintln!("{}", msg);
+                    EXIT_SAIL_ERR
+                }
+            }
+        }
+        Mode::Check(file) => match sail::check_file(&file) {
+            Ok(()) => EXIT_OK,
+            Err(err) => {
+                eprintln!("{}: {:?}", file, err);
+                EXIT_SAIL_ERR
+            }
+        },
+    };
+
+    process::exit(status);
+}
+
+/// Prints the outcome of a Sail evaluation and gives an exit status
+///
+/// A program which called `exit` gets the status it asked for
+fn report(result: Result<String, sail::SlErrCode>) -> i32 {
+    if let Some(status) = sail::exit_status() {
+        return status;
+    }
+
+    match result {
+        Ok(out) => {
+            println!("{}", out);
+            EXIT_OK
+        }
+        Err(err) => {
+            eprintln!("{:?}", err);
+            EXIT_SAIL_ERR
+        }
+    }
+}
+
+/// Runs `f`, profiling the Sail code it evaluates on this thread if
+/// the options ask for a profile
+fn profiled<T>(opts: &Options, f: impl FnOnce() -> T) -> T {
+    let path = match &opts.profile {
+        Some(path) => path,
+        None => return f(),
+    };
+
+    sail::profile::start(opts.profile_every);
+    let out = f();
+
+    if let Some(prof) = sail::profile::stop() {
+        eprintln!("{}", prof.report());
+        if let Err(err) = std::fs::write(path, prof.folded()) {
+            log::error!("could not write profile to {}: {}", path, err);
+        }
+    }
+
+    out
+}
+
+/// Reads the mode and options from the command line arguments
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<(Mode, Options), String> {
+    let mut opts = Options {
+        log_level: log::LevelFilter::Info,
+        heap_size: sail::DEFAULT_ZONE_SIZE,
+        script_dir: None,
+        profile: None,
+        profile_every: sail::profile::SAMPLE_INTERVAL,
+        mem_stats: false,
+        heap_dump: None,
+        image: None,
+        output: None,
+        size: RENDER_SIZE,
+        software: false,
+    };
+    let mut command: Option<String> = None;
+    let mut operands: Vec<String> = vec![];
+
+    while let Some(arg) = args.next() {
+        // everything after a `run` file belongs to the script
+        if command.as_deref() == Some("run") && !operands.is_empty() {
+            operands.push(arg);
+            continue;
+        }
+
+        if arg == "--" {
+            operands.extend(args.by_ref());
+            break;
+        }
+
+        if arg.starts_with("--") {
+            let (name, inline) = match arg.find('=') {
+                Some(i) => (arg[..i].to_string(), Some(arg[i + 1..].to_string())),
+                None => (arg.clone(), None),
+            };
+
+            let mut value = || match inline.clone() {
+                Some(v) => Ok(v),
+                None => args
+                    .next()
+                    .ok_or_else(|| format!("option {} requires a value", name)),
+            };
+
+            match name.as_str() {
+                "--help" => {
+                    println!("{}", USAGE);
+                    process::exit(EXIT_OK);
+                }
+                "--version" => {
+                    println!("stark {}", env!("CARGO_PKG_VERSION"));
+                    process::exit(EXIT_OK);
+                }
+                "--log-level" => {
+                    let v = value()?;
+                    opts.log_level = v.parse().map_err(|_| format!("invalid log level: {}", v))?;
+                }
+                "--heap-size" => opts.heap_size = parse_size(&value()?)?,
+                "--script-dir" => opts.script_dir = Some(value()?),
+                "--profile" => opts.profile = Some(value()?),
+                "--mem-stats" => opts.mem_stats = true,
+                "--heap-dump" => opts.heap_dump = Some(value()?),
+                "--image" => opts.image = Some(value()?),
+                "--output" => opts.output = Some(value()?),
+                "--size" => opts.size = parse_dims(&value()?)?,
+                "--software" => opts.software = true,
+                "--profile-every" => {
+                    let v = value()?;
+                    opts.profile_every = match v.parse() {
+                        Ok(n) if n > 0 => n,
+                        _ => return Err(format!("invalid step count: {}", v)),
+                    };
+                }
+                _ => return Err(format!("unknown option: {}", name)),
+            }
+        } else if arg == "-o" {
+            opts.output = Some(args.next().ok_or("option -o requires a value")?);
+        } else if arg == "-h" {
+            println!("{}", USAGE);
+            process::exit(EXIT_OK);
+        } else if arg == "-V" {
+            println!("stark {}", env!("CARGO_PKG_VERSION"));
+            process::exit(EXIT_OK);
+        } else if arg.starts_with('-') && arg.len() > 1 {
+            return Err(format!("unknown option: {}", arg));
+        } else if command.is_none() {
+            command = Some(arg);
+        } else {
+            operands.push(arg);
+        }
+    }
+
+    let mut operands = operands.into_iter();
+
+    let mode = match command.as_deref() {
+        None => Mode::Gui(None),
+        Some("gui") => Mode::Gui(operands.next()),
+        Some("repl") => Mode::Repl,
+        Some("run") => Mode::Run(
+            operands.next().ok_or("run requires a file")?,
+            operands.by_ref().collect(),
+        ),
+        Some("eval") => Mode::Eval(operands.next().ok_or("eval requires an expression")?),
+        Some("check") => Mode::Check(operands.next().ok_or("check requires a file")?),
+        Some("render") => {
+            if opts.output.is_none() {
+                return Err("render requires an output file (-o <png>)".to_string());
+            }
+            Mode::Render(operands.next().ok_or("render requires a file")?)
         }
-        std::process::exit(0);
-    } else if args.len() >= 2 {
-        sail::repl(io::stdin())
+        Some(other) => return Err(format!("unknown command: {}", other)),
+    };
+
+    if let Some(extra) = operands.next() {
+        return Err(format!("unexpected argument: {}", extra));
+    }
+
+    Ok((mode, opts))
+}
+
+/// Parses image dimensions written as `<width>x<height>`
+fn parse_dims(text: &str) -> Result<[u32; 2], String> {
+    let mut parts = text.splitn(2, |c| c == 'x' || c == 'X');
+
+    match (
+        parts.next().and_then(|w| w.parse().ok()),
+        parts.next().and_then(|h| h.parse().ok()),
+    ) {
+        (Some(w), Some(h)) if w > 0 && h > 0 => Ok([w, h]),
+        _ => Err(format!("invalid size: {}", text)),
+    }
+}
+
+/// Parses a size in bytes, allowing a K or M suffix
+fn parse_size(text: &str) -> Result<usize, String> {
+    let (digits, scale) = match text.chars().last() {
+        Some('k') | Some('K') => (&text[..text.len() - 1], 1 << 10),
+        Some('m') | Some('M') => (&text[..text.len() - 1], 1 << 20),
+        _ => (text, 1),
+    };
+
+    match digits.parse::<usize>() {
+        Ok(n) if n > 0 => n
+            .checked_mul(scale)
+            .ok_or_else(|| format!("invalid size: {}", text)),
+        _ => Err(format!("invalid size: {}", text)),
     }
+}
+
+/// Starts the full graphical interface, which takes over the main thread
+fn run_gui(opts: &Options, script: Option<String>) {
+    const NAME: &'static str = "STARK";
+    const SIZE: [u32; 2] = [1280, 720];
+
+    let search = scripts::SearchPath::new(opts.script_dir.as_deref());
 
-    let (frame, event_loop) = context::init_context(NAME, ICON, SIZE[0], SIZE[1]);
+    let (frame, event_loop) = context::init_context(NAME, &search.icon(), SIZE[0], SIZE[1]);
     let handle = FrameHandle(frame.raw_window_handle());
 
-    let main_region = unsafe { sail::memmgt::acquire_mem_region(1000000) };
-    let rndr_region = unsafe { sail::memmgt::acquire_mem_region(1000000) };
+    let main_region = unsafe { sail::memmgt::acquire_mem_region(opts.heap_size) };
+    let rndr_region = unsafe { sail::memmgt::acquire_mem_region(opts.heap_size) };
     let ctxt_region = unsafe { sail::memmgt::acquire_mem_region(1000) };
 
     let (sl_tbl, main_env, rndr_env) = {
-        let (tbl, m_env) = sail::prep_environment(main_region);
-        sail::environment_setup(main_region, tbl, m_env);
+        let (tbl, m_env) = match sail::environment_boot(main_region) {
+            Ok(out) => out,
+            Err(err) => {
+                eprintln!("stark: could not boot: {:?}", err);
+                process::exit(EXIT_SAIL_ERR);
+            }
+        };
 
         let r_env = sail::env_create(rndr_region, 255);
         sail::set_next_list_elt(r_env, m_env);
@@ -79,21 +398,59 @@ fn main() {
         (tbl, m_env, r_env)
     };
 
-    let (mr_send, mr_recv) = sail::queue::queue_create(main_region, rndr_region);
+    let (mr_send, mr_recv) =
+        sail::queue::queue_create_bounded(main_region, rndr_region, 4096, Overflow::Block);
     let (cm_send, cm_recv) = sail::queue::queue_create(ctxt_region, main_region);
-    let (cr_send, cr_recv) = sail::queue::queue_create(ctxt_region, rndr_region);
+
+    // Control events (like resizes) from the context thread must all
+    // arrive, and are few enough that it waits for room for them. It
+    // must never wait on redraws and view changes, though, so these go
+    // over a queue of their own that drops repeats rather than filling
+    // up while rendering falls behind
+    let (cr_send, cr_recv) =
+        sail::queue::queue_create_bounded(ctxt_region, rndr_region, 64, Overflow::Block);
+    let (draw_send, draw_recv) =
+        sail::queue::queue_create_bounded(ctxt_region, rndr_region, 64, Overflow::Coalesce);
 
     sail::env_layer_ins_by_id(main_region, main_env, sail::S_MR_SEND.0, mr_send);
     sail::env_layer_ins_by_id(main_region, main_env, sail::S_CM_RECV.0, cm_recv);
 
     sail::env_layer_ins_by_id(rndr_region, rndr_env, sail::S_MR_RECV.0, mr_recv);
     sail::env_layer_ins_by_id(rndr_region, rndr_env, sail::S_CR_RECV.0, cr_recv);
+    sail::env_layer_ins_by_id(rndr_region, rndr_env, sail::S_CR_DRAW.0, draw_recv);
 
-    let fr_dims = sail::arrvec_init::<u32>(main_region, sail::T_U32.0, 2, &[0, 0]);
-    let cur_pos = sail::arrvec_init::<f32>(main_region, sail::T_F32.0, 2, &[0.0, 0.0]);
+    // Written by the context thread and read by the others, so these
+    // are atoms; each update swaps in a fresh snapshot
+    let fr_dims = sail::atom::atom_make(
+        main_region,
+        sail::arrvec_init::<u32>(main_region, sail::T_U32.0, 2, &[0, 0]),
+    );
+    let cur_pos = sail::atom::atom_make(
+        main_region,
+        sail::arrvec_init::<f32>(main_region, sail::T_F32.0, 2, &[0.0, 0.0]),
+    );
+    // The camera starts with a world unit half the frame's height, so
+    // the view spans -1 to 1 from top to bottom
+    let view = sail::atom::atom_make(
+        main_region,
+        sail::arrvec_init::<f32>(
+            main_region,
+            sail::T_F32.0,
+            3,
+            &[0.0, 0.0, SIZE[1] as f32 / 2.0],
+        ),
+    );
 
     sail::env_layer_ins_by_id(main_region, main_env, sail::S_FR_DIMS.0, fr_dims);
     sail::env_layer_ins_by_id(main_region, main_env, sail::S_CUR_POS.0, cur_pos);
+    sail::env_layer_ins_by_id(main_region, main_env, sail::S_VIEW.0, view);
+
+    // IDs of the objects selected, published by the render thread
+    let selected = sail::atom::atom_make(
+        main_region,
+        sail::arrvec_init::<i64>(main_region, sail::T_I64.0, 0, &[]),
+    );
+    sail::env_layer_ins_by_id(main_region, main_env, sail::S_SELECTED.0, selected);
 
     let (
         sl_tbl,
@@ -104,8 +461,10 @@ fn main() {
         rndr_env,
         cm_send,
         cr_send,
+        draw_send,
         fr_dims,
         cur_pos,
+        view,
     ) = (
         sl_tbl as usize,
         main_region as usize,
@@ -115,20 +474,47 @@ fn main() {
         rndr_env as usize,
         cm_send as usize,
         cr_send as usize,
+        draw_send as usize,
         fr_dims as usize,
         cur_pos as usize,
+        view as usize,
     );
 
+    let (rndr_search, main_search) = (search.clone(), search);
+
     // This thread handles all rendering to the graphical frame: the output interface
     let render = thread::Builder::new()
         .name("render".to_string())
-        .spawn(move || graphics::render_loop(NAME, SIZE, &handle, rndr_region, sl_tbl, rndr_env))
+        .spawn(move || {
+            graphics::render_loop(
+                NAME,
+                SIZE,
+                &handle,
+                rndr_region,
+                sl_tbl,
+                rndr_env,
+                &rndr_search,
+            );
+            exit_if_requested();
+        })
         .unwrap();
 
+    // Lets the context thread stop runaway evaluation in the manager
+    let interrupt = sail::eval::interrupt_handle();
+    let mngr_interrupt = interrupt.clone();
+    let mngr_opts = opts.clone();
+
     // This thread manages the program, treating the actual main thread as a source of user input
     let manager = thread::Builder::new()
         .name("manager".to_string())
-        .spawn(move || manager_loop(frame, main_region, sl_tbl, main_env))
+        .spawn(move || {
+            sail::eval::interrupt_handle_set(mngr_interrupt);
+            profiled(&mngr_opts, || {
+                manager_loop(frame, main_region, sl_tbl, main_env, &main_search, script)
+            });
+            sail::heap::exit_report(main_region as _, sl_tbl as _, main_env as _);
+            exit_if_requested();
+        })
         .unwrap();
 
     // This loop gets input from the user and detects changes to the context
@@ -139,7 +525,35 @@ fn main() {
         ctxt_region,
         cm_send,
         cr_send,
+        draw_send,
         fr_dims,
         cur_pos,
+        view,
+        interrupt,
     );
 }
+
+/// Makes Ctrl-C interrupt the line being evaluated in the REPL
+///
+/// A second press before the first is noticed, as at an idle prompt,
+/// ends the process.
+fn interrupt_on_ctrl_c() {
+    let interrupt = sail::eval::interrupt_handle();
+
+    let handled = ctrlc::set_handler(move || {
+        if interrupt.swap(true, Ordering::Relaxed) {
+            process::exit(EXIT_INTERRUPTED);
+        }
+    });
+
+    if let Err(err) = handled {
+        log::warn!("Ctrl-C will not interrupt evaluation: {}", err);
+    }
+}
+
+/// Ends the whole process if a Sail program on this thread called `exit`
+fn exit_if_requested() {
+    if let Some(status) = sail::exit_status() {
+        process::exit(status);
+    }
+}
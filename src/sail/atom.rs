@@ -0,0 +1,157 @@
+// STARK, a system for computer augmented design.
+// Copyright (C) 2021 Matthew Rothlisberger
+
+// STARK is licensed under the terms of the GNU Affero General Public
+// License. See the top level LICENSE file for the license text.
+
+// Find full copyright information in the top level COPYRIGHT file.
+
+// <>
+
+// src/sail/atom.rs
+
+// Atoms: cells that any thread may read and write safely. An atom
+// holds a pointer to a snapshot, a shared object that is never again
+// modified; writers make a new snapshot and swap the pointer in.
+
+// <>
+
+use super::{core::*, eval, memmgt, SlHead};
+
+/// Creates an atom whose first snapshot is a copy of `val`
+pub fn atom_make(reg: *mut memmgt::Region, val: *mut SlHead) -> *mut SlHead {
+    unsafe {
+        let atom = memmgt::alloc(reg, 8, Cfg::B8Other as u8);
+        super::set_self_type(atom, super::T_ATOM.0);
+        write_field_unchecked(atom, 0, snapshot(reg, val));
+        atom
+    }
+}
+
+/// Checks whether a Sail object is an atom
+pub fn atom_p(loc: *mut SlHead) -> bool {
+    !nil_p(loc) && get_cfg_spec(loc) == Cfg::B8Other && super::get_self_type(loc) == super::T_ATOM.0
+}
+
+/// Makes a shared snapshot of an object in the given region
+///
+/// Every part of a shared object is shared, so objects already shared
+/// cannot change and are used as they are.
+fn snapshot(reg: *mut memmgt::Region, val: *mut SlHead) -> *mut SlHead {
+    if nil_p(val) || shared_p(val) {
+        return val;
+    }
+
+    core_shared_copy(reg, val)
+}
+
+/// Gets an atom's current snapshot
+pub fn atom_deref(atom: *mut SlHead) -> *mut SlHead {
+    assert!(atom_p(atom));
+    unsafe { read_field_atomic_unchecked(atom, 0) }
+}
+
+/// Makes `val` the atom's snapshot, without copying it
+///
+/// `val` is marked shared, and its maker must not modify it afterward.
+/// It must not refer to other objects, which would be left unshared;
+/// `atom_store` takes any value.
+pub fn atom_reset(atom: *mut SlHead, val: *mut SlHead) {
+    assert!(atom_p(atom));
+
+    if !nil_p(val) {
+        assert!(flat_p(val), "value refers to other objects");
+        set_shared(val);
+    }
+
+    unsafe { write_field_atomic_unchecked(atom, 0, val) }
+}
+
+/// Checks whether an object of a core type refers to no others
+fn flat_p(val: *mut SlHead) -> bool {
+    match core_type(val) {
+        Some(CoreType::Ref) | Some(CoreType::VecStd) | Some(CoreType::VecHash) => false,
+        Some(CoreType::ProcLambda) | None => false,
+        Some(CoreType::VecArr) => super::arrvec_get_typ(val) != super::T_REF.0,
+        Some(_) => true,
+    }
+}
+
+/// Makes a snapshot of `val` the atom's, returning the snapshot
+pub fn atom_store(reg: *mut memmgt::Region, atom: *mut SlHead, val: *mut SlHead) -> *mut SlHead {
+    assert!(atom_p(atom));
+
+    let snap = snapshot(reg, val);
+    unsafe { write_field_atomic_unchecked(atom, 0, snap) };
+
+    snap
+}
+
+/// Replaces the atom's snapshot with a copy of `val` only if the
+/// current one is still `old`
+///
+/// Returns true if the snapshot was replaced
+pub fn atom_cmpxcg(
+    reg: *mut memmgt::Region,
+    atom: *mut SlHead,
+    old: *mut SlHead,
+    val: *mut SlHead,
+) -> bool {
+    assert!(atom_p(atom));
+    unsafe { write_field_cmpxcg_unchecked(atom, 0, old, snapshot(reg, val)) }
+}
+
+/// Applies `proc` to the atom's snapshot and stores the result
+///
+/// If another thread stores a snapshot in the meantime, `proc` is
+/// applied again to that one, so it should have no side effects.
+/// Returns the snapshot that was stored.
+pub fn atom_swap(
+    reg: *mut memmgt::Region,
+    tbl: *mut SlHead,
+    env: *mut SlHead,
+    atom: *mut SlHead,
+    proc: *mut SlHead,
+) -> *mut SlHead {
+    loop {
+        let old = atom_deref(atom);
+        let new = snapshot(reg, eval::apply(reg, tbl, env, proc, &[old]));
+
+        if atom_cmpxcg(reg, atom, old, new) {
+            return new;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{interpret, DEFAULT_ZONE_SIZE};
+    use super::*;
+
+    #[test]
+    fn swaps() {
+        let exp = "(do (def a (atom 1))
+                       (def w (spawn (fn [inbox outbox]
+                                       (swap! a (fn [x] (+ x 1)))
+                                       (qtx outbox ()))))
+                       (qrx-wait (vec-get w 1))
+                       (join (vec-get w 2))
+                       (swap! a (fn [x] (* x 10)))
+                       (deref a))";
+        assert_eq!("20", interpret(exp, DEFAULT_ZONE_SIZE).unwrap());
+    }
+
+    #[test]
+    fn shares_snapshots() {
+        let reg = unsafe { memmgt::acquire_mem_region(1000) };
+
+        let item = i64_init(reg, 5);
+        let atom = atom_make(reg, stdvec_init(reg, &[item]));
+
+        // every part of a snapshot is shared, but not what it copied
+        let snap = atom_deref(atom);
+        assert!(shared_p(snap));
+        assert!(shared_p(stdvec_idx(snap, 0)));
+        assert!(!shared_p(item));
+    }
+}
@@ -18,6 +18,7 @@
 
 //! TODO: Gradual typing; more extensible type system; subtypes
 
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::mem;
 use std::ptr;
@@ -82,9 +83,6 @@ pub struct SlHead {
     pub rc: u8,
 }
 
-// TODO: potential change: add a "shared" bit to indicate that an object
-// may be read and / or written by other threads
-
 /// ALL Sail objects that may be independently referenced, begin with bytes of this format
 ///
 /// size: 4 bits - base type: 3 bit - list elt: 1 bit - type pred: 1 bit - rc: 8 bits
@@ -270,7 +268,9 @@ impl TryFrom<Cfg> for CoreType {
 /// - Slice containing all Sail arguments
 pub type NativeFn = fn(*mut Region, *mut SlHead, *mut SlHead, &[*mut SlHead]) -> *mut SlHead;
 
-// TODO / NOTE: There is currently one spare, unused bit in the Sail head: the second lowest bit
+/// Head bit marking an object as shared: it may be read by other
+/// threads at any time, so it must never again be modified
+const SHARED_BIT: u8 = 0b00000010;
 
 /// Creates a nil Sail object
 #[inline(always)]
@@ -367,6 +367,19 @@ pub fn self_type_p(loc: *mut SlHead) -> bool {
     }
 }
 
+/// Checks whether a valid Sail object is shared between threads
+#[inline(always)]
+pub fn shared_p(loc: *mut SlHead) -> bool {
+    (get_cfg_all(loc) & SHARED_BIT) != 0
+}
+
+/// Marks a Sail object as shared; this cannot be undone
+#[inline(always)]
+pub fn set_shared(loc: *mut SlHead) {
+    let old = get_cfg_all(loc);
+    unsafe { ptr::write_unaligned(loc as *mut u8, old | SHARED_BIT) }
+}
+
 /// Returns the truthiness of a valid Sail object
 #[inline(always)]
 pub fn truthy(loc: *mut SlHead) -> bool {
@@ -862,7 +875,7 @@ pub fn stdvec_get_len(loc: *mut SlHead) -> u32 {
 }
 
 #[inline(always)]
-fn stdvec_get_cap(loc: *mut SlHead) -> u32 {
+pub fn stdvec_get_cap(loc: *mut SlHead) -> u32 {
     coretypck!(loc ; VecStd);
     core_read_field(loc, 0)
 }
@@ -1111,9 +1124,11 @@ pub fn proc_native_get_body(loc: *mut SlHead) -> NativeFn {
 
 /// Copies the value from a Sail object of a core type into a newly
 /// allocated object
+///
+/// The copy belongs to its maker, so it is never marked shared.
 #[inline(always)]
 pub fn core_copy_val(reg: *mut Region, src: *mut SlHead) -> *mut SlHead {
-    let (siz, cfg) = (core_size(src), get_cfg_all(src));
+    let (siz, cfg) = (core_size(src), get_cfg_all(src) & !SHARED_BIT);
 
     unsafe {
         let dst = memmgt::alloc(reg, siz, cfg);
@@ -1122,6 +1137,115 @@ pub fn core_copy_val(reg: *mut Region, src: *mut SlHead) -> *mut SlHead {
     }
 }
 
+/// Copies a Sail object and everything reachable from it into a region
+///
+/// Lists, vectors, maps, and procedure bodies are copied structurally;
+/// shared substructure stays shared and cycles are reproduced rather
+/// than followed forever. Objects not of a core type (queues and other
+/// handles to Rust-side state) are not copied, and the result refers
+/// to the originals.
+pub fn core_deep_copy(reg: *mut Region, src: *mut SlHead) -> *mut SlHead {
+    DeepCopy::run(reg, src).0
+}
+
+/// Deep copies an object as `core_deep_copy` does, marking every
+/// object in the copy shared
+pub fn core_shared_copy(reg: *mut Region, src: *mut SlHead) -> *mut SlHead {
+    let (out, copier) = DeepCopy::run(reg, src);
+
+    for &dst in copier.done.values() {
+        set_shared(dst);
+    }
+
+    out
+}
+
+/// State of a `core_deep_copy` in progress
+struct DeepCopy {
+    reg: *mut Region,
+    /// Copies already made, keyed by source and whether the list
+    /// continuing from the source was copied with it
+    done: HashMap<(usize, bool), *mut SlHead>,
+    /// Shallow copies whose pointers still refer to the source
+    todo: Vec<(*mut SlHead, *mut SlHead, bool)>,
+}
+
+impl DeepCopy {
+    /// Copies `src` and everything it refers to
+    fn run(reg: *mut Region, src: *mut SlHead) -> (*mut SlHead, Self) {
+        let mut copier = DeepCopy {
+            reg,
+            done: HashMap::new(),
+            todo: Vec::new(),
+        };
+
+        let out = copier.object(src, false);
+
+        // copies are made shallow first, then their pointers fixed up
+        while let Some((src, dst, chain)) = copier.todo.pop() {
+            copier.fill(src, dst, chain);
+        }
+
+        (out, copier)
+    }
+
+    /// Gets the copy of an object, making a shallow one if needed
+    ///
+    /// When `chain` is set, the rest of the list after the object is
+    /// copied along with it.
+    fn object(&mut self, src: *mut SlHead, chain: bool) -> *mut SlHead {
+        if nil_p(src) || core_type(src).is_none() {
+            return src;
+        }
+
+        if let Some(&dst) = self.done.get(&(src as usize, chain)) {
+            return dst;
+        }
+
+        let dst = core_copy_val(self.reg, src);
+        self.done.insert((src as usize, chain), dst);
+        self.todo.push((src, dst, chain));
+
+        dst
+    }
+
+    /// Points a shallow copy's references at copies of their targets
+    fn fill(&mut self, src: *mut SlHead, dst: *mut SlHead, chain: bool) {
+        match core_type(src).unwrap() {
+            CoreType::Ref => {
+                let target = self.object(ref_get(src), true);
+                core_write_field(dst, 0, target);
+            }
+            CoreType::VecStd => {
+                for i in 0..stdvec_get_len(src) as usize {
+                    let elt = self.object(stdvec_idx(src, i as u32), false);
+                    core_write_field(dst, 4 + 4 + (i * 8), elt);
+                }
+            }
+            CoreType::VecHash => {
+                // each bucket is a list of entries
+                for i in 0..hashvec_get_size(src) as usize {
+                    let bucket = self.object(core_read_field(src, 4 + 4 + (i * 8)), true);
+                    core_write_field(dst, 4 + 4 + (i * 8), bucket);
+                }
+            }
+            CoreType::ProcLambda => {
+                let body = self.object(proc_lambda_get_body(src), true);
+                proc_lambda_set_body(dst, body);
+            }
+            _ => {}
+        }
+
+        if chain {
+            let next = get_next_list_elt(src);
+            if !nil_p(next) {
+                let next = self.object(next, true);
+                set_next_list_elt(dst, next);
+            }
+        }
+    }
+}
+
 /// Copies the values from a pair of Sail objects of core types into a
 /// two element list structure
 #[inline(always)]
@@ -1281,7 +1405,7 @@ pub fn env_layer_mut_entry(env: *mut SlHead, sym: *mut SlHead, val: *mut SlHead)
 ///
 /// Returns false if no mutation was performed, or true if it was
 #[inline(always)]
-fn env_layer_mut_by_id(env: *mut SlHead, sym_id: u32, val: *mut SlHead) -> bool {
+pub fn env_layer_mut_by_id(env: *mut SlHead, sym_id: u32, val: *mut SlHead) -> bool {
     let entry = env_lookup_entry(env, sym_id);
     if nil_p(entry) {
         false
@@ -1336,6 +1460,42 @@ pub fn env_arg_layer_ins(reg: *mut Region, layer: *mut SlHead, key: *mut SlHead,
     }
 }
 
+/// Copies every binding visible in an environment into a single new
+/// layer in the given region
+///
+/// Inner bindings hide outer ones just as in lookup. Values are copied
+/// as by `core_deep_copy`, so handles like atoms and queues are shared.
+pub fn env_deep_copy(reg: *mut Region, mut env: *mut SlHead) -> *mut SlHead {
+    let out = env_create(reg, 255);
+
+    while !nil_p(env) {
+        let buckets = if coretypp!(env ; VecHash) {
+            (0..hashvec_get_size(env) as usize)
+                .map(|i| core_read_field(env, 4 + 4 + (i * PTR_LEN as usize)))
+                .collect()
+        } else if coretypp!(env ; Ref) {
+            vec![core_read_field(env, 0)]
+        } else {
+            panic!("incorrect layer in env")
+        };
+
+        for mut pos in buckets {
+            while !nil_p(pos) {
+                let sym_id = sym_get_id(ref_get(pos));
+                if nil_p(env_lookup_entry(out, sym_id)) {
+                    let val = get_next_list_elt(ref_get(pos));
+                    env_layer_ins_by_id(reg, out, sym_id, core_deep_copy(reg, val));
+                }
+                pos = get_next_list_elt(pos);
+            }
+        }
+
+        env = get_next_list_elt(env);
+    }
+
+    out
+}
+
 // pub fn env_push_layer(env: *mut SlHead, layer: *mut SlHead) {
 //     let next = ref_get(env);
 //     set_next_list_elt(layer, next);
@@ -0,0 +1,493 @@
+// STARK, a system for computer augmented design.
+// Copyright (C) 2021 Matthew Rothlisberger
+
+// STARK is licensed under the terms of the GNU Affero General Public
+// License. See the top level LICENSE file for the license text.
+
+// Find full copyright information in the top level COPYRIGHT file.
+
+// <>
+
+// src/sail/debug.rs
+
+// Interactive debugger for Sail code, driven from the REPL. Because
+// evaluation happens one frame at a time on an explicit stack, pausing
+// is just a matter of not stepping the stack until told to.
+
+// <>
+
+use super::{context, core::*, eval, memmgt, parser, profile, SlHead};
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Where a list was parsed from
+#[derive(Clone)]
+struct Line {
+    source: Arc<str>,
+    line: u32,
+}
+
+/// Source lines of parsed lists, keyed by the address of their first element
+static LINES: Mutex<BTreeMap<usize, Line>> = Mutex::new(BTreeMap::new());
+
+/// Number of debuggers in existence; lines are forgotten when none are
+static DEBUGGERS: AtomicUsize = AtomicUsize::new(0);
+
+/// Checks whether anything wants the source lines of code being parsed
+///
+/// That is a debugger, for line breakpoints, or the profiler on this
+/// thread, which names procedures by where they were written.
+pub fn lines_wanted() -> bool {
+    DEBUGGERS.load(Ordering::SeqCst) > 0 || profile::enabled()
+}
+
+/// Forgets the lines of an earlier parse of the named source
+pub fn lines_forget(source: &str) {
+    LINES.lock().unwrap().retain(|_, l| &*l.source != source);
+}
+
+/// Remembers the source line of the list beginning with `elt`
+pub fn line_record(elt: *mut SlHead, source: &Arc<str>, line: u32) {
+    if nil_p(elt) {
+        return;
+    }
+
+    LINES.lock().unwrap().insert(
+        elt as usize,
+        Line {
+            source: source.clone(),
+            line,
+        },
+    );
+}
+
+/// Gets the source line of the list beginning with `elt`, if known
+fn line_of(elt: *mut SlHead) -> Option<Line> {
+    LINES.lock().unwrap().get(&(elt as usize)).cloned()
+}
+
+/// Gives the `source:line` of the list beginning with `elt`, if known
+pub fn location(elt: *mut SlHead) -> Option<String> {
+    line_of(elt).map(|l| format!("{}:{}", l.source, l.line))
+}
+
+/// Place at which evaluation should stop
+enum Breakpoint {
+    /// Any call whose operator is the named symbol
+    Call(String),
+    /// Any list starting on this line, of the named source if given
+    Line(Option<String>, u32),
+}
+
+impl Breakpoint {
+    /// Reads a breakpoint as written after `,break`
+    fn parse(spec: &str) -> Option<Self> {
+        let (source, line) = match spec.rfind(':') {
+            Some(i) => (Some(&spec[..i]), &spec[i + 1..]),
+            None => (None, spec),
+        };
+
+        match line.parse::<u32>() {
+            Ok(n) => Some(Self::Line(source.map(String::from), n)),
+            Err(_) if source.is_none() && !spec.is_empty() => Some(Self::Call(spec.to_string())),
+            Err(_) => None,
+        }
+    }
+
+    /// Checks whether evaluating the list starting with `elt` hits this breakpoint
+    fn hit(&self, tbl: *mut SlHead, elt: *mut SlHead) -> bool {
+        match self {
+            Self::Call(name) => basic_sym_p(elt) && context(tbl, elt).to_string() == *name,
+            Self::Line(source, line) => match line_of(elt) {
+                Some(l) => {
+                    l.line == *line
+                        && source
+                            .as_ref()
+                            .map_or(true, |s| l.source.ends_with(s.as_str()))
+                }
+                None => false,
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Call(name) => write!(f, "call to {}", name),
+            Self::Line(Some(source), line) => write!(f, "line {} of {}", line, source),
+            Self::Line(None, line) => write!(f, "line {}", line),
+        }
+    }
+}
+
+/// How far to run before stopping again, apart from breakpoints
+#[derive(Clone, Copy)]
+enum Step {
+    /// Only stop at breakpoints
+    Run,
+    /// Stop at the next list evaluated
+    Into,
+    /// Stop at the next list evaluated no deeper than this
+    Over(usize),
+    /// Stop at the next list evaluated shallower than this
+    Out(usize),
+}
+
+/// What the REPL should do after a debugger command
+pub enum Reply {
+    /// Carry on evaluating
+    Resume,
+    /// Show this text and read another command
+    Show(String),
+}
+
+/// Breakpoints and stepping state for one evaluation stack
+pub struct Debugger {
+    breaks: Vec<Breakpoint>,
+    step: Step,
+}
+
+const HELP: &str = "\
+,break <name>          stop at calls to the named procedure
+,break [file:]<line>   stop at lists starting on a source line
+,breaks                list breakpoints
+,delete [n]            delete breakpoint n, or all of them
+,load <file>           evaluate a file, keeping its line numbers
+while stopped:
+,step                  stop at the next list evaluated
+,next                  stop at the next list at this depth or above
+,finish                stop once the current list is finished
+,continue              run until the next breakpoint
+,bt                    show the stack, innermost frame first
+,locals [n]            show local bindings of frame n (default 0)
+,eval <n> <expr>       evaluate an expression in frame n
+,set <n> <sym> <expr>  change a binding visible from frame n";
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Debugger {
+    fn drop(&mut self) {
+        if DEBUGGERS.fetch_sub(1, Ordering::SeqCst) == 1 {
+            LINES.lock().unwrap().clear();
+        }
+    }
+}
+
+impl Debugger {
+    /// Creates a debugger; source lines are recorded while any exists
+    pub fn new() -> Self {
+        DEBUGGERS.fetch_add(1, Ordering::SeqCst);
+
+        Self {
+            breaks: Vec::new(),
+            step: Step::Run,
+        }
+    }
+
+    /// Forgets any step in progress, before starting a new evaluation
+    pub fn reset(&mut self) {
+        self.step = Step::Run;
+    }
+
+    /// Checks whether the stack should pause before running its top frame
+    ///
+    /// Evaluation only ever stops just before a list is evaluated.
+    pub fn should_stop(&mut self, tbl: *mut SlHead, stack: &eval::EvalStack) -> bool {
+        let top = match stack.top() {
+            Some(f) if f.opc == eval::Opcode::Eval => f,
+            _ => return false,
+        };
+
+        let stop = match self.step {
+            Step::Run => false,
+            Step::Into => true,
+            Step::Over(depth) => stack.depth() <= depth,
+            Step::Out(depth) => stack.depth() < depth,
+        } || self.breaks.iter().any(|b| b.hit(tbl, top.obj));
+
+        if stop {
+            self.step = Step::Run;
+        }
+
+        stop
+    }
+
+    /// Describes where a stopped stack is about to continue
+    pub fn stop_message(&self, tbl: *mut SlHead, stack: &eval::EvalStack) -> String {
+        match stack.top() {
+            Some(f) => format!("stopped at {}", describe_list(tbl, f.obj)),
+            None => "not stopped".to_string(),
+        }
+    }
+
+    /// Carries out a debugger command, given with its leading comma
+    ///
+    /// `stack` is the stopped stack, or None at the top level prompt.
+    pub fn command(
+        &mut self,
+        reg: *mut memmgt::Region,
+        tbl: *mut SlHead,
+        stack: Option<&eval::EvalStack>,
+        line: &str,
+    ) -> Reply {
+        let (cmd, rest) = split_word(line.trim());
+
+        match (cmd, stack) {
+            (",break", _) => match Breakpoint::parse(rest) {
+                Some(b) => {
+                    let msg = format!("breakpoint {}: {}", self.breaks.len(), b);
+                    self.breaks.push(b);
+                    Reply::Show(msg)
+                }
+                None => Reply::Show("usage: ,break <name> or ,break [file:]<line>".to_string()),
+            },
+            (",breaks", _) => {
+                let mut out = String::new();
+                for (i, b) in self.breaks.iter().enumerate() {
+                    writeln!(out, "{}: {}", i, b).unwrap();
+                }
+                Reply::Show(out.trim_end().to_string())
+            }
+            (",delete", _) if rest.is_empty() => {
+                self.breaks.clear();
+                Reply::Show("deleted all breakpoints".to_string())
+            }
+            (",delete", _) => match rest.parse::<usize>() {
+                Ok(i) if i < self.breaks.len() => {
+                    self.breaks.remove(i);
+                    Reply::Show(format!("deleted breakpoint {}", i))
+                }
+                _ => Reply::Show(format!("no breakpoint {}", rest)),
+            },
+            (",help", _) => Reply::Show(HELP.to_string()),
+            (",step", Some(_)) => {
+                self.step = Step::Into;
+                Reply::Resume
+            }
+            (",next", Some(s)) => {
+                self.step = Step::Over(s.depth());
+                Reply::Resume
+            }
+            (",finish", Some(s)) => {
+                self.step = Step::Out(s.depth());
+                Reply::Resume
+            }
+            (",continue", Some(_)) => {
+                self.step = Step::Run;
+                Reply::Resume
+            }
+            (",bt", Some(s)) => Reply::Show(backtrace(tbl, s)),
+            (",locals", Some(s)) => match frame_env(s, rest) {
+                Ok(env) => Reply::Show(locals(tbl, env)),
+                Err(msg) => Reply::Show(msg),
+            },
+            (",eval", Some(s)) => {
+                let (idx, expr) = split_word(rest);
+                let env = match frame_env(s, idx) {
+                    Ok(env) => env,
+                    Err(msg) => return Reply::Show(msg),
+                };
+
+                match parser::parse(reg, tbl, expr) {
+                    Ok(expr) => {
+                        Reply::Show(context(tbl, eval::eval(reg, tbl, env, expr)).to_string())
+                    }
+                    Err(err) => Reply::Show(format!("{:?}", err)),
+                }
+            }
+            (",set", Some(s)) => {
+                let (idx, rest) = split_word(rest);
+                let (name, expr) = split_word(rest);
+                let env = match frame_env(s, idx) {
+                    Ok(env) => env,
+                    Err(msg) => return Reply::Show(msg),
+                };
+
+                let sym = match parser::parse(reg, tbl, name) {
+                    Ok(sym) if basic_sym_p(sym) => sym,
+                    _ => return Reply::Show(format!("{} is not a symbol", name)),
+                };
+
+                match parser::parse(reg, tbl, expr) {
+                    Ok(expr) => {
+                        let val = eval::eval(reg, tbl, env, expr);
+                        if env_layer_mut_entry(env, sym, val) {
+                            Reply::Show(format!("{} = {}", name, context(tbl, val)))
+                        } else {
+                            Reply::Show(format!("{} is not bound", name))
+                        }
+                    }
+                    Err(err) => Reply::Show(format!("{:?}", err)),
+                }
+            }
+            (",step", None)
+            | (",next", None)
+            | (",finish", None)
+            | (",continue", None)
+            | (",bt", None)
+            | (",locals", None)
+            | (",eval", None)
+            | (",set", None) => Reply::Show(format!("{} only works while stopped", cmd)),
+            _ => Reply::Show(format!("unknown command {}; try ,help", cmd)),
+        }
+    }
+}
+
+/// Splits off the first whitespace separated word of a string
+fn split_word(text: &str) -> (&str, &str) {
+    match text.find(char::is_whitespace) {
+        Some(i) => (&text[..i], text[i..].trim()),
+        None => (text, ""),
+    }
+}
+
+/// Gets the environment of the frame with the given index (0 if empty)
+fn frame_env(stack: &eval::EvalStack, idx: &str) -> Result<*mut SlHead, String> {
+    let idx = if idx.is_empty() {
+        0
+    } else {
+        idx.parse::<usize>()
+            .map_err(|_| format!("{} is not a frame number", idx))?
+    };
+
+    match stack.frames().get(idx) {
+        Some(f) => Ok(f.env),
+        None => Err(format!("no frame {}", idx)),
+    }
+}
+
+/// Shows a list being evaluated, from its first element, with its source line
+fn describe_list(tbl: *mut SlHead, elt: *mut SlHead) -> String {
+    let mut out = String::from("(");
+
+    let mut pos = elt;
+    while !nil_p(pos) {
+        out.push_str(&context(tbl, pos).to_string());
+        pos = get_next_list_elt(pos);
+        if !nil_p(pos) {
+            out.push(' ');
+        }
+    }
+    out.push(')');
+
+    // long expressions are cut off to keep to one line
+    if out.len() > 60 {
+        let mut end = 57;
+        while !out.is_char_boundary(end) {
+            end -= 1;
+        }
+        out.truncate(end);
+        out.push_str("...");
+    }
+
+    if let Some(l) = line_of(elt) {
+        write!(out, " at {}:{}", l.source, l.line).unwrap();
+    }
+
+    out
+}
+
+/// Lists every frame on a stack with its opcode, innermost first
+fn backtrace(tbl: *mut SlHead, stack: &eval::EvalStack) -> String {
+    let mut out = String::new();
+
+    for (i, f) in stack.frames().iter().enumerate() {
+        let what = match f.opc {
+            eval::Opcode::Eval => describe_list(tbl, f.obj),
+            eval::Opcode::Apply => context(tbl, f.obj).to_string(),
+            _ => String::new(),
+        };
+
+        let opc = format!("{:?}", f.opc);
+        let entry = format!("#{:<3} {:<8} {}", i, opc, what);
+        writeln!(out, "{}", entry.trim_end()).unwrap();
+    }
+
+    out.trim_end().to_string()
+}
+
+/// Shows the bindings in each layer of an environment, stopping short
+/// of the global layer with its many procedures
+fn locals(tbl: *mut SlHead, mut env: *mut SlHead) -> String {
+    let mut out = String::new();
+
+    while !nil_p(env) && !nil_p(get_next_list_elt(env)) {
+        if coretypp!(env ; Ref) {
+            let mut pos = ref_get(env);
+            while !nil_p(pos) {
+                binding(&mut out, tbl, ref_get(pos));
+                pos = get_next_list_elt(pos);
+            }
+        } else {
+            for i in 0..hashvec_get_size(env) as usize {
+                let mut pos = core_read_field(env, 4 + 4 + (i * PTR_LEN as usize));
+                while !nil_p(pos) {
+                    binding(&mut out, tbl, ref_get(pos));
+                    pos = get_next_list_elt(pos);
+                }
+            }
+        }
+
+        env = get_next_list_elt(env);
+    }
+
+    if out.is_empty() {
+        "no local bindings".to_string()
+    } else {
+        out.trim_end().to_string()
+    }
+}
+
+/// Writes out one environment entry: a symbol followed by its value
+fn binding(out: &mut String, tbl: *mut SlHead, entry: *mut SlHead) {
+    writeln!(
+        out,
+        "{} = {}",
+        context(tbl, entry),
+        context(tbl, get_next_list_elt(entry))
+    )
+    .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{environment_setup, memmgt, prep_environment, DEFAULT_ZONE_SIZE};
+    use super::*;
+
+    #[test]
+    fn stops_at_lines() {
+        let reg = unsafe { memmgt::acquire_mem_region(DEFAULT_ZONE_SIZE) };
+        let (tbl, env) = prep_environment(reg);
+        environment_setup(reg, tbl, env);
+
+        // lines are only recorded while there is a debugger to use them
+        let code = "(do (+ 1 2)\n    (+ 3 4))";
+        let expr = parser::parse_source(reg, tbl, code, "test.sl").unwrap();
+        assert!(location(expr).is_none());
+
+        let mut debugger = Debugger::new();
+        let expr = parser::parse_source(reg, tbl, code, "test.sl").unwrap();
+        debugger.command(reg, tbl, None, ",break test.sl:2");
+
+        let mut result = 1 as *mut SlHead;
+        let mut stack = eval::EvalStack::new(1000);
+        stack.start(&mut result, env, expr);
+
+        while !debugger.should_stop(tbl, &stack) {
+            stack.iter_once(reg, tbl);
+        }
+
+        assert_eq!(
+            "stopped at (+ 3 4) at test.sl:2",
+            debugger.stop_message(tbl, &stack)
+        );
+    }
+}
@@ -17,12 +17,35 @@
 // <>
 
 use super::core::*;
-use super::memmgt;
-use super::{SP_DEF, SP_DO, SP_EVAL, SP_FN, SP_IF, SP_QUOTE, SP_SET, SP_WHILE};
+use super::{context, errcode_init, exit_requested, memmgt, profile, sched, SlErrCode};
+use super::{
+    SP_CATCH, SP_DEF, SP_DO, SP_EVAL, SP_FN, SP_IF, SP_PROFILE, SP_QUOTE, SP_SET, SP_WHILE,
+};
 
 use std::alloc;
+use std::cell::RefCell;
 use std::convert::TryInto;
 use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Flag which another thread may set to interrupt evaluation
+pub type Interrupt = Arc<AtomicBool>;
+
+thread_local! {
+    /// Interrupt flag given to each stack created on this thread
+    static INTERRUPT: RefCell<Interrupt> = RefCell::new(Arc::new(AtomicBool::new(false)));
+}
+
+/// Gets the interrupt flag for stacks on this thread
+pub fn interrupt_handle() -> Interrupt {
+    INTERRUPT.with(|i| i.borrow().clone())
+}
+
+/// Replaces the interrupt flag for stacks created on this thread from now on
+pub fn interrupt_handle_set(flag: Interrupt) {
+    INTERRUPT.with(|i| *i.borrow_mut() = flag);
+}
 
 /// Sail evaluation stack
 pub struct EvalStack {
@@ -36,6 +59,13 @@ pub struct EvalStack {
     frame_start: *mut usize,
     /// Location to write discarded return values
     pub null_loc: *mut usize,
+    /// Set from outside to abort the innermost `catch` expression
+    interrupt: Interrupt,
+    /// Number of `catch` frames on the stack
+    catches: usize,
+    /// Lambda calls made while profiling, outermost first: the stack
+    /// offset and environment of each body's frame, and the procedure
+    calls: Vec<(usize, *mut SlHead, *mut SlHead)>,
 }
 
 impl EvalStack {
@@ -52,6 +82,9 @@ impl EvalStack {
                 stack_top: stack as *mut usize,
                 frame_start: stack as *mut usize,
                 null_loc: Box::into_raw(Box::from(0)),
+                interrupt: interrupt_handle(),
+                catches: 0,
+                calls: Vec::new(),
             }
         }
     }
@@ -222,6 +255,24 @@ impl EvalStack {
         }
     }
 
+    /// Starts evaluating a Sail expression as if it were wrapped in `catch`
+    ///
+    /// An interrupt then ends the evaluation with an error code.
+    /// Returns false and does nothing if the stack is already in use
+    pub fn start_catching(
+        &mut self,
+        ret: *mut *mut SlHead,
+        env: *mut SlHead,
+        expr: *mut SlHead,
+    ) -> bool {
+        if !self.is_empty() {
+            false
+        } else {
+            self.catch(ret, env, expr);
+            true
+        }
+    }
+
     /// Starts evaluating a Sail expression that will not return outside the stack
     ///
     /// Works even when other expressions are evaluating on the stack
@@ -237,18 +288,167 @@ impl EvalStack {
         }
     }
 
+    /// Pushes a frame which evaluates `expr`, giving an error code
+    /// instead if the evaluation is interrupted
+    fn catch(&mut self, ret: *mut *mut SlHead, env: *mut SlHead, expr: *mut SlHead) {
+        // interrupts sent while nothing could catch them are stale
+        if self.catches == 0 {
+            self.interrupt.store(false, Ordering::Relaxed);
+        }
+        self.catches += 1;
+
+        self.push_frame_head(ret, Opcode::Catch, env);
+        self.push(nil());
+
+        let return_to = self.frame_addr(0);
+
+        self.eval_expr(return_to, env, expr);
+    }
+
     // TODO: use more of a condition system than an exception system eventually
+    /// Destroys stack frames down to the innermost `catch`, which then
+    /// returns the error
+    fn raise(&mut self, error: *mut SlHead) {
+        assert!(self.catches > 0);
+
+        while self.frame_opc() != Opcode::Catch {
+            self.profile_drop();
+            self.pop_frame();
+        }
+
+        unsafe { ptr::write(self.frame_addr(0), error) };
+    }
+
+    /// Destroys all stack frames, returning a value from the bottom one
     fn unwind(&mut self, error: *mut SlHead) {
-        // destroy stack frames until reaching an error catch or the bottom
+        let mut ret = self.null_loc as *mut *mut SlHead;
         while self.frame_start > self.stack_start {
+            ret = self.frame_ret();
+            self.profile_drop();
             self.pop_frame();
         }
+        self.catches = 0;
 
+        // the bottom frame's return address leads outside the stack
         unsafe {
-            ptr::write(self.frame_ret(), error);
+            ptr::write(ret, error);
+        }
+    }
+
+    /// Pushes a frame which evaluates `expr` while profiling this
+    /// thread, printing a report once it returns
+    ///
+    /// Within an expression already being profiled, just evaluates it.
+    fn profile(&mut self, ret: *mut *mut SlHead, env: *mut SlHead, expr: *mut SlHead) {
+        let started = profile::start(profile::SAMPLE_INTERVAL);
+
+        self.push_frame_head(ret, Opcode::Profile, env);
+        self.push(nil());
+        self.push(started as usize as *mut SlHead);
+
+        let return_to = self.frame_addr(0);
+
+        self.eval_expr(return_to, env, expr);
+    }
+
+    /// Stops profiling, without a report, if the top frame is a
+    /// `profile` frame which started it
+    fn profile_drop(&mut self) {
+        if self.frame_opc() == Opcode::Profile && self.frame_obj(1) as usize != 0 {
+            profile::stop();
+        }
+    }
+
+    /// Notes that a lambda's body is now running, for the profiler
+    fn profile_call(&mut self, env: *mut SlHead, proc: *mut SlHead) {
+        let offset = self.frame_start as usize - self.stack_start as usize;
+
+        // calls at or above this frame have returned, or were tail calls
+        while matches!(self.calls.last(), Some(c) if c.0 >= offset) {
+            self.calls.pop();
+        }
+
+        self.calls.push((offset, env, proc));
+    }
+
+    /// Records the procedures running on this stack as a profiler sample
+    fn profile_sample(&mut self) {
+        let mut heads = Vec::new();
+        let mut start = self.frame_start;
+        while start > self.stack_start {
+            unsafe {
+                heads.push((
+                    start as usize - self.stack_start as usize,
+                    FrameView::read(start).env,
+                ));
+                start = ptr::read(start.add(FrameOffset::LastTop as usize)) as *mut usize;
+            }
+        }
+        heads.reverse();
+
+        // a call is still running while its body's frame, or whatever
+        // replaced it in tail position, keeps the same environment
+        let mut live = 0;
+        let mut i = 0;
+        for &(offset, env, _) in &self.calls {
+            while i < heads.len() && heads[i].0 < offset {
+                i += 1;
+            }
+            if i < heads.len() && heads[i] == (offset, env) {
+                live += 1;
+            } else {
+                break;
+            }
+        }
+        self.calls.truncate(live);
+
+        let mut path: Vec<*mut SlHead> = self.calls.iter().map(|c| c.2).collect();
+        if let Some(f) = self.top() {
+            if f.opc == Opcode::Apply && core_type(f.obj) == Some(CoreType::ProcNative) {
+                path.push(f.obj);
+            }
+        }
+
+        profile::record(path);
+    }
+
+    /// Describes every frame on the stack, starting from the top
+    pub fn frames(&self) -> Vec<FrameView> {
+        let mut out = Vec::new();
+
+        let mut start = self.frame_start;
+        while start > self.stack_start {
+            unsafe {
+                out.push(FrameView::read(start));
+                start = ptr::read(start.add(FrameOffset::LastTop as usize)) as *mut usize;
+            }
+        }
+
+        out
+    }
+
+    /// Describes the frame at the top of the stack, which will run next
+    pub fn top(&self) -> Option<FrameView> {
+        if self.frame_start > self.stack_start {
+            Some(unsafe { FrameView::read(self.frame_start) })
+        } else {
+            None
         }
     }
 
+    /// Counts the frames on the stack
+    pub fn depth(&self) -> usize {
+        let mut count = 0;
+
+        let mut start = self.frame_start;
+        while start > self.stack_start {
+            count += 1;
+            start = unsafe { ptr::read(start.add(FrameOffset::LastTop as usize)) as *mut usize };
+        }
+
+        count
+    }
+
     /// Determines whether the stack is currently empty
     #[inline(always)]
     pub fn is_empty(&mut self) -> bool {
@@ -334,6 +534,17 @@ impl EvalStack {
             return;
         }
 
+        // interrupts are only acted upon where they can be caught
+        if self.catches > 0 && self.interrupt.load(Ordering::Relaxed) {
+            self.interrupt.store(false, Ordering::Relaxed);
+            self.raise(errcode_init(reg, SlErrCode::EvalInterrupted));
+            return;
+        }
+
+        if profile::tick() {
+            self.profile_sample();
+        }
+
         let (ret, env, opc) = self.frame_top();
 
         if cfg!(feature = "stkdbg") {
@@ -355,6 +566,11 @@ impl EvalStack {
 
                 if basic_sym_p(raw_op) {
                     match sym_get_id(raw_op) {
+                        id if id == SP_CATCH.0 => {
+                            // needs: expression to evaluate
+                            self.catch(ret, env, raw_args);
+                            return;
+                        }
                         id if id == SP_DEF.0 => {
                             // needs: symbol to bind, object to bind to it
                             self.push_frame_head(ret, Opcode::Bind, env);
@@ -411,6 +627,11 @@ impl EvalStack {
                             self.eval_expr(return_to, env, raw_args);
                             return;
                         }
+                        id if id == SP_PROFILE.0 => {
+                            // needs: expression to evaluate
+                            self.profile(ret, env, raw_args);
+                            return;
+                        }
                         id if id == SP_QUOTE.0 => {
                             // needs: nothing else evaluated
                             unsafe { ptr::write(ret, raw_args) };
@@ -460,6 +681,10 @@ impl EvalStack {
                     };
                     assert!(proc_p(proc));
 
+                    if basic_sym_p(raw_op) && profile::enabled() {
+                        profile::name(proc, || context(tbl, raw_op).to_string());
+                    }
+
                     self.push_frame_head(ret, Opcode::Apply, env);
 
                     self.push(proc);
@@ -486,6 +711,10 @@ impl EvalStack {
                 assert!(basic_sym_p(symbol));
                 let value = self.frame_obj(1);
 
+                if proc_p(value) && profile::enabled() {
+                    profile::name(value, || context(tbl, symbol).to_string());
+                }
+
                 env_layer_ins_entry(reg, env, symbol, value);
                 self.pop_frame();
 
@@ -524,8 +753,11 @@ impl EvalStack {
                 let body = self.frame_obj(2);
 
                 if truthy(result) {
+                    // pred must be evaluated after the body, even when it
+                    // is an atom which eval_expr would resolve at once
                     let return_to = self.frame_addr(1);
-                    self.eval_expr(return_to, env, pred);
+                    self.push_frame_head(return_to, Opcode::PreEval, env);
+                    self.push(pred);
 
                     self.push_frame_head(self.null_loc as *mut *mut SlHead, Opcode::DoSeq, env);
                     self.push(body);
@@ -534,6 +766,26 @@ impl EvalStack {
                     unsafe { ptr::write(ret, nil()) };
                 }
             }
+            Opcode::Catch => {
+                let result = self.frame_obj(0);
+                self.pop_frame();
+                self.catches -= 1;
+
+                unsafe { ptr::write(ret, result) };
+            }
+            Opcode::Profile => {
+                let result = self.frame_obj(0);
+                let started = self.frame_obj(1) as usize != 0;
+                self.pop_frame();
+
+                if started {
+                    if let Some(prof) = profile::stop() {
+                        println!("{}", prof.report());
+                    }
+                }
+
+                unsafe { ptr::write(ret, result) };
+            }
             Opcode::Branch => {
                 let pred_res = self.frame_obj(0);
                 let true_body = self.frame_obj(1);
@@ -599,11 +851,26 @@ impl EvalStack {
 
                     self.push_frame_head(ret, Opcode::DoSeq, proc_env);
                     self.push(proc_lambda_get_body(proc));
+
+                    if profile::enabled() {
+                        self.profile_call(proc_env, proc);
+                    }
                 } else {
                     let args: &[*mut SlHead] =
                         unsafe { std::slice::from_raw_parts(self.frame_addr(1), argct as usize) };
 
                     let fn_rslt = proc_native_get_body(proc)(reg, tbl, env, args);
+
+                    if exit_requested() {
+                        self.unwind(fn_rslt);
+                        return;
+                    }
+
+                    // the native will run again when this coroutine resumes
+                    if sched::retry_requested() {
+                        return;
+                    }
+
                     unsafe { ptr::write(ret, fn_rslt) };
 
                     self.pop_frame();
@@ -636,9 +903,40 @@ pub fn eval(
     result
 }
 
+/// Applies a Sail procedure to arguments in a freshly created stack
+pub fn apply(
+    reg: *mut memmgt::Region,
+    tbl: *mut SlHead,
+    env: *mut SlHead,
+    proc: *mut SlHead,
+    args: &[*mut SlHead],
+) -> *mut SlHead {
+    assert!(proc_p(proc));
+    assert_eq!(proc_get_argct(proc) as usize, args.len());
+
+    let sigil = 1 as *mut SlHead;
+
+    let mut result = sigil;
+    let ret_addr: *mut *mut SlHead = &mut result;
+
+    let mut stack = EvalStack::new(10000);
+
+    stack.push_frame_head(ret_addr, Opcode::Apply, env);
+    stack.push(proc);
+    for arg in args {
+        stack.push(*arg);
+    }
+
+    while result == sigil {
+        stack.iter_once(reg, tbl);
+    }
+
+    result
+}
+
 enum_and_tryfrom! {
     /// Operation code for the frame; stored as a tag at the frame start
-    #[derive(Debug, PartialEq, Eq)]
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
     #[repr(u8)]
     pub enum Opcode {
         /// Expression to be evaluated
@@ -668,11 +966,37 @@ enum_and_tryfrom! {
         /// Procedure, all arguments
         Apply,
 
-        // /// Function to run with error
-        // Catch,
+        /// Expression result, or error if interrupted
+        Catch,
 
-        // /// Function to run, error caught
-        // Caught,
+        /// Expression result, whether this frame started profiling
+        Profile,
+    }
+}
+
+/// A look at one frame of an evaluation stack, for debugging
+#[derive(Clone, Copy)]
+pub struct FrameView {
+    /// What the frame does when it runs
+    pub opc: Opcode,
+    /// Environment in which the frame runs
+    pub env: *mut SlHead,
+    /// First object in the frame body; for `Eval` frames, the head of
+    /// the list being evaluated, and for `Apply` frames, the procedure
+    pub obj: *mut SlHead,
+}
+
+impl FrameView {
+    /// Reads the frame starting at the given stack address
+    unsafe fn read(start: *mut usize) -> Self {
+        let env_and_opc = ptr::read(start.add(FrameOffset::EnvOpc as usize));
+        Self {
+            opc: ((env_and_opc & 0x000000000000FFFF) as u8)
+                .try_into()
+                .unwrap(),
+            env: (env_and_opc >> 16) as *mut SlHead,
+            obj: ptr::read(start.add(FrameOffset::ArgZero as usize)) as *mut SlHead,
+        }
     }
 }
 
@@ -0,0 +1,362 @@
+// STARK, a system for computer augmented design.
+// Copyright (C) 2021 Matthew Rothlisberger
+
+// STARK is licensed under the terms of the GNU Affero General Public
+// License. See the top level LICENSE file for the license text.
+
+// Find full copyright information in the top level COPYRIGHT file.
+
+// <>
+
+// src/sail/heap.rs
+
+// Introspection of Sail memory regions. Objects are never freed and
+// fill their zones without gaps, so a region can be walked one object
+// at a time to count what it holds or to dump it for offline study.
+
+// <>
+
+use super::{core::*, memmgt, SlHead, HEAD_LEN, T_ATOM, T_REF};
+
+use std::collections::{BTreeMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Write};
+use std::ptr;
+use std::sync::Mutex;
+
+/// What to report on a program's main region once it finishes
+struct ExitReport {
+    stats: bool,
+    dump: Option<String>,
+}
+
+static EXIT_REPORT: Mutex<ExitReport> = Mutex::new(ExitReport {
+    stats: false,
+    dump: None,
+});
+
+/// Asks for statistics on stderr, a heap dump written to a file, or
+/// both, whenever `exit_report` is called
+pub fn report_on_exit(stats: bool, dump: Option<String>) {
+    *EXIT_REPORT.lock().unwrap() = ExitReport { stats, dump };
+}
+
+/// Reports on a program's main region as asked by `report_on_exit`
+pub fn exit_report(reg: *mut memmgt::Region, tbl: *mut SlHead, env: *mut SlHead) {
+    let report = EXIT_REPORT.lock().unwrap();
+
+    if report.stats {
+        eprintln!("{}", region_stats(reg, tbl, env));
+    }
+
+    if let Some(path) = &report.dump {
+        let written =
+            std::fs::File::create(path).and_then(|mut f| heap_dump(reg, tbl, env, &mut f));
+        if let Err(err) = written {
+            log::error!("could not write heap dump to {}: {}", path, err);
+        }
+    }
+}
+
+/// Counts of what a region holds
+pub struct RegionStats {
+    /// Number of zones in the region
+    pub zones: usize,
+    /// Size of each zone in bytes
+    pub zone_size: usize,
+    /// Bytes taken by objects
+    pub used: usize,
+    /// Bytes not yet taken, counting the unusable ends of full zones
+    pub free: usize,
+    /// Object count and bytes for each configuration
+    pub kinds: BTreeMap<String, (usize, usize)>,
+    /// Object count and bytes reachable from the environment
+    pub reachable: (usize, usize),
+    /// Bytes which could not be read as objects
+    pub unparsed: usize,
+}
+
+/// Gathers statistics on a region, finding which objects the
+/// environment (or the symbol table) can still reach
+pub fn region_stats(reg: *mut memmgt::Region, tbl: *mut SlHead, env: *mut SlHead) -> RegionStats {
+    let zone_size = unsafe { memmgt::region_zone_size(reg) };
+    let zones = unsafe { memmgt::region_zones(reg) };
+    let used: usize = zones.iter().map(|z| z.1).sum();
+
+    let mut objects = Vec::new();
+    let unparsed = walk(reg, |obj, len| objects.push((obj, len)));
+
+    let mut kinds: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+    for &(obj, len) in &objects {
+        let kind = kinds.entry(kind_name(tbl, obj)).or_insert((0, 0));
+        kind.0 += 1;
+        kind.1 += len;
+    }
+
+    let reached = reachable(&objects, &[env, tbl]);
+    let reachable = objects
+        .iter()
+        .filter(|o| reached.contains(&(o.0 as usize)))
+        .fold((0, 0), |acc, o| (acc.0 + 1, acc.1 + o.1));
+
+    RegionStats {
+        zones: zones.len(),
+        zone_size,
+        used,
+        free: zones.len() * zone_size - used,
+        kinds,
+        reachable,
+        unparsed,
+    }
+}
+
+impl fmt::Display for RegionStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (count, bytes) = self
+            .kinds
+            .values()
+            .fold((0, 0), |acc, k| (acc.0 + k.0, acc.1 + k.1));
+
+        writeln!(
+            f,
+            "region: {} zones of {} bytes, {} used, {} free",
+            self.zones, self.zone_size, self.used, self.free
+        )?;
+        writeln!(
+            f,
+            "objects: {} ({} bytes), {} reachable ({} bytes)",
+            count, bytes, self.reachable.0, self.reachable.1
+        )?;
+        if self.unparsed > 0 {
+            writeln!(f, "unreadable: {} bytes", self.unparsed)?;
+        }
+
+        write!(f, "\n  {:<20} {:>9} {:>11}", "kind", "count", "bytes")?;
+        for (name, (count, bytes)) in &self.kinds {
+            write!(f, "\n  {:<20} {:>9} {:>11}", name, count, bytes)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes every object in a region, one per line, for offline analysis
+///
+/// Lines starting with `#` describe the region and name the roots; each
+/// other line holds an object's address, kind, size in bytes, and the
+/// addresses of the objects it refers to, separated by spaces. Nothing
+/// is ever freed, so objects no root reaches are garbage, not leaks.
+pub fn heap_dump(
+    reg: *mut memmgt::Region,
+    tbl: *mut SlHead,
+    env: *mut SlHead,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let zones = unsafe { memmgt::region_zones(reg) };
+    let zone_size = unsafe { memmgt::region_zone_size(reg) };
+
+    writeln!(out, "# stark heap dump")?;
+    writeln!(out, "# region {} zones of {} bytes", zones.len(), zone_size)?;
+    writeln!(out, "# root env {:p}", env)?;
+    writeln!(out, "# root tbl {:p}", tbl)?;
+
+    let mut objects = Vec::new();
+    let unparsed = walk(reg, |obj, len| objects.push((obj, len)));
+
+    for (obj, len) in objects {
+        write!(out, "{:p} {} {}", obj, kind_name(tbl, obj), len)?;
+        for r in references(obj) {
+            write!(out, " {:p}", r)?;
+        }
+        writeln!(out)?;
+    }
+
+    if unparsed > 0 {
+        writeln!(out, "# unreadable {} bytes", unparsed)?;
+    }
+
+    Ok(())
+}
+
+/// Calls `f` with every object in a region and its size in bytes
+///
+/// Returns the number of bytes which could not be read as objects; a
+/// zone is abandoned at the first object of unknown size. Another
+/// thread allocating in the region meanwhile may make this happen.
+fn walk(reg: *mut memmgt::Region, mut f: impl FnMut(*mut SlHead, usize)) -> usize {
+    let mut unparsed = 0;
+
+    for (start, used) in unsafe { memmgt::region_zones(reg) } {
+        let mut offset = 0;
+        while offset < used {
+            let obj = unsafe { start.add(offset) } as *mut SlHead;
+            match object_len(obj) {
+                Some(len) if offset + len <= used => {
+                    f(obj, len);
+                    offset += len;
+                }
+                _ => {
+                    unparsed += used - offset;
+                    break;
+                }
+            }
+        }
+    }
+
+    unparsed
+}
+
+/// Reads the configuration of an object, if it is a valid one
+fn cfg_of(obj: *mut SlHead) -> Option<Cfg> {
+    Cfg::try_from(unsafe { ptr::read_unaligned(obj as *const u8) } & 0b11111100).ok()
+}
+
+/// Gives the full size of an object, head and type specifiers included
+pub fn object_len(obj: *mut SlHead) -> Option<usize> {
+    use Cfg::*;
+    let body = match cfg_of(obj)? {
+        B0Other => 0,
+        B1Other => 1,
+        B2Other => 2,
+        B4Other => 4,
+        B8Other => 8,
+        B16Other => 16,
+        VecOther | Other => return None,
+        _ => core_size(obj),
+    };
+
+    let head = value_ptr(obj) as usize - obj as usize;
+    debug_assert!(head >= HEAD_LEN as usize);
+
+    Some(head + body)
+}
+
+/// Names the kind of an object: its configuration, and for objects
+/// which are not of a core type, the type they declare
+fn kind_name(tbl: *mut SlHead, obj: *mut SlHead) -> String {
+    let cfg = match cfg_of(obj) {
+        Some(cfg) => cfg,
+        None => return "invalid".to_string(),
+    };
+
+    if core_type(obj).is_some() {
+        return format!("{:?}", cfg);
+    }
+
+    let name = sym_tab_lookup_id_num(tbl, super::get_self_type(obj));
+    if coretypp!(name ; VecStr) {
+        format!("{:?}:{}", cfg, string_get(name))
+    } else {
+        format!("{:?}", cfg)
+    }
+}
+
+/// Place in an object which may hold a pointer to another object
+#[derive(Clone, Copy)]
+pub enum Slot {
+    /// The next list element pointer kept in the head
+    Next,
+    /// A pointer stored this many bytes from the start of the object
+    Field(usize),
+}
+
+/// Lists the places in an object which may point to other objects
+pub fn pointer_slots(obj: *mut SlHead) -> Vec<Slot> {
+    let mut out = vec![Slot::Next];
+    let base = value_ptr(obj) as usize - obj as usize;
+    let ptr_field = |i: usize| Slot::Field(base + 8 + i * PTR_LEN as usize);
+
+    match core_type(obj) {
+        Some(CoreType::Ref) => out.push(Slot::Field(base)),
+        Some(CoreType::VecStd) => out.extend((0..stdvec_get_len(obj) as usize).map(ptr_field)),
+        Some(CoreType::VecHash) => out.extend((0..hashvec_get_size(obj) as usize).map(ptr_field)),
+        Some(CoreType::VecArr) if unsafe { read_field_unchecked::<u32>(obj, 0) } == T_REF.0 => {
+            let len = unsafe { read_field_unchecked::<u32>(obj, 4) };
+            out.extend((0..len as usize).map(ptr_field));
+        }
+        Some(CoreType::ProcLambda) => out.push(Slot::Field(base + NUM_16_LEN as usize)),
+        None if super::get_self_type(obj) == T_ATOM.0 => out.push(Slot::Field(base)),
+        _ => {}
+    }
+
+    out
+}
+
+/// Reads the pointer in one of an object's slots
+pub fn slot_get(obj: *mut SlHead, slot: Slot) -> *mut SlHead {
+    match slot {
+        Slot::Next => get_next_list_elt(obj),
+        Slot::Field(offset) => unsafe {
+            ptr::read_unaligned((obj as *mut u8).add(offset) as *const *mut SlHead)
+        },
+    }
+}
+
+/// Writes a pointer to one of an object's slots
+pub fn slot_set(obj: *mut SlHead, slot: Slot, val: *mut SlHead) {
+    match slot {
+        Slot::Next => set_next_list_elt(obj, val),
+        Slot::Field(offset) => unsafe {
+            ptr::write_unaligned((obj as *mut u8).add(offset) as *mut *mut SlHead, val)
+        },
+    }
+}
+
+/// Lists the Sail objects an object points to
+pub fn references(obj: *mut SlHead) -> Vec<*mut SlHead> {
+    pointer_slots(obj)
+        .into_iter()
+        .map(|slot| slot_get(obj, slot))
+        .filter(|r| !nil_p(*r))
+        .collect()
+}
+
+/// Finds which of the given objects the roots lead to, keyed by address
+fn reachable(objects: &[(*mut SlHead, usize)], roots: &[*mut SlHead]) -> HashSet<usize> {
+    let known: HashSet<usize> = objects.iter().map(|o| o.0 as usize).collect();
+
+    let mut seen = HashSet::new();
+    let mut todo: Vec<*mut SlHead> = roots.to_vec();
+
+    while let Some(obj) = todo.pop() {
+        if !known.contains(&(obj as usize)) || !seen.insert(obj as usize) {
+            continue;
+        }
+        todo.extend(references(obj));
+    }
+
+    seen
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parser;
+    use super::*;
+
+    #[test]
+    fn walks() {
+        let reg = unsafe { memmgt::acquire_mem_region(4096) };
+        let (tbl, env) = prep_environment(reg);
+        let before = region_stats(reg, tbl, env).kinds["VecStd"].0;
+
+        // enough to fill several zones
+        for _ in 0..100 {
+            let s = string_init(reg, "garbage");
+            stdvec_init(reg, &[s, s]);
+        }
+        parser::parse(reg, tbl, "(a b [c d] \"e\")").unwrap();
+
+        let stats = region_stats(reg, tbl, env);
+        assert_eq!(stats.unparsed, 0);
+        assert!(stats.zones > 1);
+        assert!(stats.kinds["VecStd"].0 >= before + 100);
+        assert!(stats.reachable.1 < stats.used);
+
+        let mut dump = Vec::new();
+        heap_dump(reg, tbl, env, &mut dump).unwrap();
+        let lines = String::from_utf8(dump).unwrap().lines().count();
+        let count: usize = stats.kinds.values().map(|k| k.0).sum();
+        assert_eq!(lines, count + 4);
+    }
+}
@@ -0,0 +1,353 @@
+// STARK, a system for computer augmented design.
+// Copyright (C) 2021 Matthew Rothlisberger
+
+// STARK is licensed under the terms of the GNU Affero General Public
+// License. See the top level LICENSE file for the license text.
+
+// Find full copyright information in the top level COPYRIGHT file.
+
+// <>
+
+// src/sail/image.rs
+
+// Images: snapshots of a Sail environment and symbol table, written
+// to a file and booted from later in place of a fresh environment.
+// Every pointer is stored as an offset into the image and relocated
+// when it is loaded; native procedures are linked again by name.
+
+// <>
+
+use super::heap::{object_len, pointer_slots, slot_get, slot_set};
+use super::{core::*, errcode_init, memmgt, SlErrCode, SlHead, SYM_ARRAY, T_ATOM};
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::ptr;
+use std::sync::Mutex;
+
+/// First bytes of every image file
+const MAGIC: &[u8; 8] = b"STARKIMG";
+/// Version of the image format; images of other versions are refused
+const VERSION: u32 = 1;
+/// Most bytes read from the start of an object to find its length: the
+/// longest head, and the two length fields at the start of a vector
+const HEAD_SLACK: usize = (HEAD_LEN + SYMBOL_LEN + SYMBOL_LEN) as usize + 8;
+
+/// Image to boot programs from instead of a fresh environment
+static BOOT_IMAGE: Mutex<Option<String>> = Mutex::new(None);
+
+/// Makes programs started from now on boot from the given image
+pub fn boot_from(path: Option<String>) {
+    *BOOT_IMAGE.lock().unwrap() = path;
+}
+
+/// Gets the image programs should boot from, if any
+pub fn boot_image() -> Option<String> {
+    BOOT_IMAGE.lock().unwrap().clone()
+}
+
+/// Checks whether an object can be kept in an image
+///
+/// Objects tied to the running process, like threads and queues, are
+/// left out, and references to them become nil.
+fn storable_p(obj: *mut SlHead) -> bool {
+    match core_type(obj) {
+        Some(_) => true,
+        None => super::get_self_type(obj) == T_ATOM.0,
+    }
+}
+
+/// Finds the names bound to native procedures in the outermost layer
+/// of an environment, which is the only one saved
+pub fn native_names(tbl: *mut SlHead, global: *mut SlHead) -> HashMap<usize, String> {
+    let mut out = HashMap::new();
+
+    for i in 0..hashvec_get_size(global) as usize {
+        let mut entry = core_read_field(global, 4 + 4 + (i * PTR_LEN as usize));
+        while !nil_p(entry) {
+            let sym = ref_get(entry);
+            let val = get_next_list_elt(sym);
+            let name = sym_tab_lookup_id_num(tbl, sym_get_id(sym));
+
+            if core_type(val) == Some(CoreType::ProcNative) && coretypp!(name ; VecStr) {
+                out.entry(val as usize)
+                    .or_insert_with(|| string_get(name).to_string());
+            }
+
+            entry = get_next_list_elt(entry);
+        }
+    }
+
+    out
+}
+
+/// Saves the symbol table and the global layer of an environment, with
+/// everything they refer to, as an image file
+///
+/// Only the global layer is saved, since an image boots into a fresh
+/// global environment, so saving from within a procedure or any other
+/// inner layer is refused rather than losing its bindings. Returns the
+/// number of objects left out because they cannot be saved.
+pub fn image_save(tbl: *mut SlHead, global: *mut SlHead, path: &str) -> Result<usize, SlErrCode> {
+    if !nil_p(get_next_list_elt(global)) {
+        log::error!("images can only be saved from the global environment");
+        return Err(SlErrCode::ErrorUnknown);
+    }
+
+    let natives = native_names(tbl, global);
+    let keep = |obj: *mut SlHead| {
+        storable_p(obj)
+            && (core_type(obj) != Some(CoreType::ProcNative)
+                || natives.contains_key(&(obj as usize)))
+    };
+
+    // lay out every object reachable from the roots, one after another
+    let mut offsets: HashMap<usize, usize> = HashMap::new();
+    let mut objects: Vec<(*mut SlHead, usize)> = Vec::new();
+    let mut left_out = 0;
+    let mut size = 0;
+
+    let mut todo = vec![global, tbl];
+    while let Some(obj) = todo.pop() {
+        if nil_p(obj) || offsets.contains_key(&(obj as usize)) {
+            continue;
+        }
+
+        if !keep(obj) {
+            left_out += 1;
+            offsets.insert(obj as usize, usize::MAX);
+            continue;
+        }
+
+        let len = object_len(obj).ok_or(SlErrCode::ImageInvalid)?;
+        offsets.insert(obj as usize, size);
+        objects.push((obj, size));
+        size += len;
+
+        todo.extend(super::heap::references(obj));
+    }
+
+    // pointers become offsets plus one, so that zero stays nil
+    let relocate = |obj: *mut SlHead| match offsets.get(&(obj as usize)) {
+        Some(&o) if o != usize::MAX => (o + 1) as *mut SlHead,
+        _ => nil(),
+    };
+
+    let mut blob = vec![0u8; size];
+    let mut linked: Vec<(usize, &str)> = Vec::new();
+    for &(obj, offset) in &objects {
+        let len = object_len(obj).unwrap();
+        let copy = unsafe {
+            ptr::copy_nonoverlapping(obj as *const u8, blob.as_mut_ptr().add(offset), len);
+            blob.as_mut_ptr().add(offset) as *mut SlHead
+        };
+
+        for slot in pointer_slots(obj) {
+            slot_set(copy, slot, relocate(slot_get(obj, slot)));
+        }
+
+        if core_type(obj) == Some(CoreType::ProcNative) {
+            unsafe { write_field_unchecked::<u64>(copy, NUM_16_LEN as usize, 0) };
+            linked.push((offset, &natives[&(obj as usize)]));
+        }
+    }
+
+    let mut out = Vec::with_capacity(size + 64);
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    out.extend_from_slice(&(SYM_ARRAY.len() as u32).to_le_bytes());
+    out.extend_from_slice(&(size as u64).to_le_bytes());
+    out.extend_from_slice(&(relocate(tbl) as u64).to_le_bytes());
+    out.extend_from_slice(&(relocate(global) as u64).to_le_bytes());
+    out.extend_from_slice(&blob);
+    out.extend_from_slice(&(linked.len() as u32).to_le_bytes());
+    for (offset, name) in linked {
+        out.extend_from_slice(&(offset as u64).to_le_bytes());
+        out.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        out.extend_from_slice(name.as_bytes());
+    }
+
+    std::fs::write(path, out).map_err(|_| SlErrCode::FileCouldNotWrite)?;
+
+    if left_out > 0 {
+        log::warn!("{} objects could not be saved in {}", left_out, path);
+    }
+
+    Ok(left_out)
+}
+
+/// Reads an image file into a region, giving its symbol table and
+/// environment
+///
+/// Native procedures are linked by name to those in `fns`, as given to
+/// `insert_native_procs`. A native missing from it gives an error code
+/// whenever it is called.
+pub fn image_load(
+    reg: *mut memmgt::Region,
+    fns: &[(&str, NativeFn, u16)],
+    path: &str,
+) -> Result<(*mut SlHead, *mut SlHead), SlErrCode> {
+    let data = std::fs::read(path).map_err(|_| SlErrCode::FileCouldNotRead)?;
+    let mut input = Reader {
+        data: &data,
+        pos: 0,
+    };
+
+    if input.take(8)? != MAGIC
+        || input.u32()? != VERSION
+        || input.u32()? as usize != SYM_ARRAY.len()
+    {
+        return Err(SlErrCode::ImageInvalid);
+    }
+
+    let size = input.u64()? as usize;
+    let root_tbl = input.u64()? as usize;
+    let root_env = input.u64()? as usize;
+    // an object's length is read from its head and its first few bytes
+    // of value, so a cut off last object must not let that read run
+    // past the blob; it is caught by the length check below instead
+    let mut blob = input.take(size)?.to_vec();
+    blob.resize(size + HEAD_SLACK, 0);
+
+    // copy each object into the region, noting where it went
+    let mut placed: HashMap<usize, *mut SlHead> = HashMap::new();
+    let mut objects = Vec::new();
+    let mut offset = 0;
+    while offset < size {
+        let src = blob[offset..].as_ptr() as *mut SlHead;
+        let len = match object_len(src) {
+            Some(len) if offset + len <= size => len,
+            _ => return Err(SlErrCode::ImageInvalid),
+        };
+        let head = value_ptr(src) as usize - src as usize;
+
+        let obj = unsafe {
+            let obj = memmgt::alloc(reg, len - head, blob[offset]);
+            ptr::copy_nonoverlapping(src as *const u8, obj as *mut u8, len);
+            obj
+        };
+
+        placed.insert(offset + 1, obj);
+        objects.push(obj);
+        offset += len;
+    }
+
+    let locate = |stored: *mut SlHead| match stored as usize {
+        0 => Ok(nil()),
+        o => placed.get(&o).copied().ok_or(SlErrCode::ImageInvalid),
+    };
+
+    for &obj in &objects {
+        for slot in pointer_slots(obj) {
+            slot_set(obj, slot, locate(slot_get(obj, slot))?);
+        }
+    }
+
+    for _ in 0..input.u32()? {
+        let offset = input.u64()? as usize;
+        let len = input.u32()? as usize;
+        let name = std::str::from_utf8(input.take(len)?).map_err(|_| SlErrCode::ImageInvalid)?;
+
+        let obj = locate((offset + 1) as *mut SlHead)?;
+        if core_type(obj) != Some(CoreType::ProcNative) {
+            return Err(SlErrCode::ImageInvalid);
+        }
+
+        match fns.iter().find(|entry| entry.0 == name) {
+            Some(entry) => proc_native_set_body(obj, entry.1),
+            None => {
+                log::warn!("native procedure {} from {} is not available", name, path);
+                proc_native_set_body(obj, missing_native);
+            }
+        }
+    }
+
+    Ok((
+        locate(root_tbl as *mut SlHead)?,
+        locate(root_env as *mut SlHead)?,
+    ))
+}
+
+/// Stands in for a native procedure from an image which this program
+/// does not have
+fn missing_native(
+    reg: *mut memmgt::Region,
+    _tbl: *mut SlHead,
+    _env: *mut SlHead,
+    _args: &[*mut SlHead],
+) -> *mut SlHead {
+    errcode_init(reg, SlErrCode::ImageInvalid)
+}
+
+/// Reads the parts of an image file in order
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SlErrCode> {
+        let out = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or(SlErrCode::ImageInvalid)?;
+        self.pos += len;
+        Ok(out)
+    }
+
+    fn u32(&mut self) -> Result<u32, SlErrCode> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, SlErrCode> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{environment_setup, eval, memmgt, parser, stdenv, DEFAULT_ZONE_SIZE};
+    use super::*;
+
+    #[test]
+    fn boots() {
+        let path = std::env::temp_dir().join(format!("stark-image-{}.img", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let run = |reg, tbl, env, code: &str| {
+            let expr = parser::parse(reg, tbl, code).unwrap();
+            super::super::context(tbl, eval::eval(reg, tbl, env, expr)).to_string()
+        };
+
+        let reg = unsafe { memmgt::acquire_mem_region(DEFAULT_ZONE_SIZE) };
+        let (tbl, env) = prep_environment(reg);
+        environment_setup(reg, tbl, env);
+        run(
+            reg,
+            tbl,
+            env,
+            "(do (def sq (fn [x] (* x x))) (def f sq) (def a (atom [1 \"two\"])) (def w (spawn (fn [i o] ()))))",
+        );
+        // the worker's thread and queues cannot be saved
+        assert!(image_save(tbl, env, path).unwrap() > 0);
+        // nor can a procedure's own bindings, so saving from one is refused
+        let inner = run(reg, tbl, env, "((fn [p] (image-save p)) \"unsaved.img\")");
+        assert_eq!(inner, "<err: ErrorUnknown>");
+
+        let reg = unsafe { memmgt::acquire_mem_region(DEFAULT_ZONE_SIZE) };
+        let (tbl, env) = image_load(reg, stdenv::ENVFNS, path).unwrap();
+
+        // a cut off image is refused rather than read past its end
+        let data = std::fs::read(path).unwrap();
+        std::fs::write(path, &data[..data.len() / 2]).unwrap();
+        assert!(matches!(
+            image_load(reg, stdenv::ENVFNS, path),
+            Err(SlErrCode::ImageInvalid)
+        ));
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(run(reg, tbl, env, "(sq 7)"), "49");
+        assert_eq!(run(reg, tbl, env, "(vec-get (deref a) 1)"), "\"two\"");
+        assert_eq!(run(reg, tbl, env, "(do (def b 3) (+ b (f 2)))"), "7");
+    }
+}
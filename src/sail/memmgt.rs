@@ -265,6 +265,53 @@ pub unsafe fn acquire_mem_region(zone_size: usize) -> *mut Region {
     out
 }
 
+/// Empties every zone of a region so its memory may be used again
+///
+/// All objects in the region become invalid; the caller must be sure
+/// that nothing still refers to any of them.
+pub unsafe fn region_reset(region: *mut Region) {
+    assert_ne!(region, ptr::null_mut());
+
+    let mut zone = (*region).head;
+    while !zone.is_null() {
+        let lock: *mut u8 = &mut (*zone).lock;
+        while !std::intrinsics::atomic_cxchg_acq(lock, false as u8, true as u8).1 {
+            std::hint::spin_loop();
+        }
+
+        (*zone).used = 0;
+        (*zone).top = (zone as *mut u8).add(MEM_ZONE_HEAD_SIZE);
+
+        std::intrinsics::atomic_store_rel(lock, false as u8);
+
+        zone = (*zone).next;
+    }
+}
+
+/// Gives the size of each of a region's zones
+pub unsafe fn region_zone_size(region: *mut Region) -> usize {
+    assert_ne!(region, ptr::null_mut());
+    (*region).zone_size
+}
+
+/// Lists the start and used length of each of a region's zones, newest first
+///
+/// Objects fill each zone from its start without gaps, so these spans
+/// may be walked one object at a time.
+pub unsafe fn region_zones(region: *mut Region) -> Vec<(*mut u8, usize)> {
+    assert_ne!(region, ptr::null_mut());
+
+    let mut out = Vec::new();
+    let mut zone = (*region).head;
+    while !zone.is_null() {
+        let start = (zone as *mut u8).add(MEM_ZONE_HEAD_SIZE);
+        out.push((start, (*zone).used));
+        zone = (*zone).next;
+    }
+
+    out
+}
+
 /// Returns the region and zone in which a given Sail object is stored
 unsafe fn which_mem_area(ptr: *mut SlHead) -> (*mut Region, *mut Zone) {
     assert_ne!(ptr, ptr::null_mut());
@@ -298,12 +345,16 @@ unsafe fn which_mem_area(ptr: *mut SlHead) -> (*mut Region, *mut Zone) {
 unsafe fn new_mem_zone(region: *mut Region) {
     assert_ne!(region, ptr::null_mut());
 
-    if cfg!(feature = "memdbg") {
-        log::debug!("Creating mem zone");
-    }
-
     let region_head = region.as_mut().unwrap();
     let size = region_head.zone_size;
+
+    if cfg!(feature = "memdbg") {
+        log::debug!(
+            "Creating mem zone {} of {} bytes",
+            region_zones(region).len() + 1,
+            size
+        );
+    }
     let cur_head = region_head.head;
 
     let ptr = {
@@ -35,16 +35,25 @@ use std::convert::TryFrom;
 use std::fmt;
 use std::mem;
 use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
 
 #[macro_use]
 pub mod core;
 pub use self::core::*;
 
+pub mod atom;
+pub mod debug;
 pub mod eval;
+pub mod heap;
+pub mod image;
 pub mod memmgt;
 pub mod parser;
+pub mod profile;
 pub mod queue;
+pub mod sched;
+pub mod serial;
 pub mod stdenv;
+pub mod worker;
 
 /// Basic error codes for Sail faults
 #[derive(Debug)]
@@ -57,6 +66,11 @@ pub enum SlErrCode {
     ParseInvalidString,
     ParseInvalidNum,
     FileCouldNotRead,
+    EvalInterrupted,
+    FileCouldNotWrite,
+    ImageInvalid,
+    SerialUnsupported,
+    SerialInvalid,
 }
 
 impl TryFrom<u16> for SlErrCode {
@@ -73,11 +87,42 @@ impl TryFrom<u16> for SlErrCode {
             x if x == ParseInvalidString as u16 => Ok(ParseInvalidString),
             x if x == ParseInvalidNum as u16 => Ok(ParseInvalidNum),
             x if x == FileCouldNotRead as u16 => Ok(FileCouldNotRead),
+            x if x == EvalInterrupted as u16 => Ok(EvalInterrupted),
+            x if x == FileCouldNotWrite as u16 => Ok(FileCouldNotWrite),
+            x if x == ImageInvalid as u16 => Ok(ImageInvalid),
+            x if x == SerialUnsupported as u16 => Ok(SerialUnsupported),
+            x if x == SerialInvalid as u16 => Ok(SerialInvalid),
             _ => Err(()),
         }
     }
 }
 
+/// Set once any Sail program has called `exit`
+static EXIT_REQUESTED: AtomicBool = AtomicBool::new(false);
+/// Status code given to the most recent `exit` call
+static EXIT_STATUS: AtomicI32 = AtomicI32::new(0);
+
+/// Records that the process should exit with the given status
+fn request_exit(status: i32) {
+    EXIT_STATUS.store(status, Ordering::SeqCst);
+    EXIT_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Checks whether a Sail program has asked to exit
+#[inline(always)]
+pub fn exit_requested() -> bool {
+    EXIT_REQUESTED.load(Ordering::Relaxed)
+}
+
+/// Returns the status a Sail program asked to exit with, if any
+pub fn exit_status() -> Option<i32> {
+    if exit_requested() {
+        Some(EXIT_STATUS.load(Ordering::SeqCst))
+    } else {
+        None
+    }
+}
+
 #[inline(always)]
 fn errcode_make(reg: *mut memmgt::Region) -> *mut SlHead {
     unsafe {
@@ -88,7 +133,7 @@ fn errcode_make(reg: *mut memmgt::Region) -> *mut SlHead {
 }
 
 #[inline(always)]
-fn errcode_init(reg: *mut memmgt::Region, err: SlErrCode) -> *mut SlHead {
+pub fn errcode_init(reg: *mut memmgt::Region, err: SlErrCode) -> *mut SlHead {
     unsafe {
         let ptr = memmgt::alloc(reg, 2, Cfg::B2Err as u8);
         write_field_unchecked::<u16>(ptr, 0, err as u16);
@@ -154,12 +199,12 @@ pub fn arrvec_init<T: SizedBase + Copy>(
     }
 }
 
-fn arrvec_get_typ(loc: *mut SlHead) -> u32 {
+pub fn arrvec_get_typ(loc: *mut SlHead) -> u32 {
     coretypck!(loc ; VecArr);
     core_read_field(loc, 0)
 }
 
-fn arrvec_get_len(loc: *mut SlHead) -> u32 {
+pub fn arrvec_get_len(loc: *mut SlHead) -> u32 {
     coretypck!(loc ; VecArr);
     core_read_field(loc, 4)
 }
@@ -167,6 +212,7 @@ fn arrvec_get_len(loc: *mut SlHead) -> u32 {
 pub fn arrvec_rplc<T: SizedBase + Copy>(loc: *mut SlHead, val: &[T]) {
     let (len, typ) = (arrvec_get_len(loc), arrvec_get_typ(loc));
 
+    assert!(!shared_p(loc), "cannot modify a shared object");
     assert_eq!(len, val.len() as u32);
     assert_eq!(temp_get_size(typ), mem::size_of::<T>());
 
@@ -256,8 +302,22 @@ incl_symbols! {
     62 K_CX_KEY_S    "cx-kb-s" Keyword;
     63 K_CX_KEY_E    "cx-kb-e" Keyword;
     64 K_CX_KEY_K    "cx-kb-k" Keyword;
-    65 K_CX_KEY_M    "cx-kb-m" Keyword
-    66
+    65 K_CX_KEY_M    "cx-kb-m" Keyword;
+    66 S_ARGV        "argv"    Basic;
+    67 T_THREAD      "thread"  Type;
+    68 T_COROUTINE   "coroutine" Type;
+    69 T_ATOM        "atom"    Type;
+    70 SP_CATCH      "catch"   Basic;
+    71 SP_PROFILE    "profile" Basic;
+    72 S_VIEW        "view"    Basic;
+    73 K_CX_VIEW     "cx-view" Keyword;
+    74 K_CX_ZFIT     "cx-zfit" Keyword;
+    75 S_SELECTED    "selected" Basic;
+    76 K_CX_RLSE     "cx-rlse" Keyword;
+    77 K_CX_KEY_V    "cx-kb-v" Keyword;
+    78 K_CX_KEY_X    "cx-kb-x" Keyword;
+    79 S_CR_DRAW     "cr-draw" Basic
+    80
 }
 
 // TODO: MINIMIZE the use of *pub* and *unsafe* functions
@@ -513,18 +573,25 @@ impl fmt::Display for SlContextVal {
     }
 }
 
+/// Default size of memory zones in regions created for Sail programs
+pub const DEFAULT_ZONE_SIZE: usize = 1000000;
+
 /// Accepts an input stream and runs a read - evaluate - print loop perpetually
-pub fn repl(stream_in: std::io::Stdin) {
+pub fn repl(stream_in: std::io::Stdin, zone_size: usize) {
     // TODO: Consider stack-like environment per function
     // TODO: Start to think about namespaces etc
 
-    let region = unsafe { memmgt::acquire_mem_region(100000) };
-
-    // Create persistent environment and symbol table
-    let (tbl, env) = prep_environment(region);
+    let region = unsafe { memmgt::acquire_mem_region(zone_size) };
 
-    // Load standard / base definitions into environment and symbol table
-    environment_setup(region, tbl, env);
+    // Create persistent environment and symbol table, with standard /
+    // base definitions or those of the boot image
+    let (tbl, env) = match environment_boot(region) {
+        Ok(out) => out,
+        Err(err) => {
+            println!("{:?}", err);
+            return;
+        }
+    };
 
     let mut stack = eval::EvalStack::new(10000);
 
@@ -533,11 +600,32 @@ pub fn repl(stream_in: std::io::Stdin) {
     let mut ret_slot = sigil;
     let ret_addr: *mut *mut SlHead = &mut ret_slot;
 
+    let mut debugger = debug::Debugger::new();
+
     loop {
         let mut input = String::new();
-        stream_in.read_line(&mut input).expect("Failure");
+        if stream_in.read_line(&mut input).expect("Failure") == 0 {
+            // end of input
+            return;
+        }
+
+        // lines starting with a comma, which cannot begin any Sail
+        // expression, are commands to the REPL or debugger
+        let parsed = if let Some(path) = input.trim().strip_prefix(",load ") {
+            match std::fs::read_to_string(path.trim()) {
+                Ok(code) => parser::parse_source(region, tbl, &code, path.trim()),
+                Err(_) => Err(SlErrCode::FileCouldNotRead),
+            }
+        } else if input.trim_start().starts_with(',') {
+            if let debug::Reply::Show(msg) = debugger.command(region, tbl, None, &input) {
+                println!("{}\n", msg);
+            }
+            continue;
+        } else {
+            parser::parse(region, tbl, &input)
+        };
 
-        let expr = match parser::parse(region, tbl, &input) {
+        let expr = match parsed {
             Ok(out) => out,
             Err(err) => {
                 println!("{:?}\n", err);
@@ -545,12 +633,34 @@ pub fn repl(stream_in: std::io::Stdin) {
             }
         };
 
-        stack.start(ret_addr, env, expr);
+        // an interrupt (see `eval::interrupt_handle`) abandons this line
+        stack.start_catching(ret_addr, env, expr);
+        debugger.reset();
 
         while ret_slot == sigil {
+            if debugger.should_stop(tbl, &stack) {
+                println!("{}", debugger.stop_message(tbl, &stack));
+
+                loop {
+                    input.clear();
+                    if stream_in.read_line(&mut input).expect("Failure") == 0 {
+                        return;
+                    }
+
+                    match debugger.command(region, tbl, Some(&stack), &input) {
+                        debug::Reply::Resume => break,
+                        debug::Reply::Show(msg) => println!("{}", msg),
+                    }
+                }
+            }
+
             stack.iter_once(region, tbl);
         }
 
+        if exit_requested() {
+            return;
+        }
+
         println!("{}\n", context(tbl, ret_slot).to_string());
 
         ret_slot = sigil;
@@ -558,25 +668,64 @@ pub fn repl(stream_in: std::io::Stdin) {
 }
 
 /// Runs a Sail file in its own context
-pub fn run_file(filename: &str) -> Result<String, SlErrCode> {
+///
+/// The file name followed by `args` is bound to `argv` as a vector of strings
+pub fn run_file(filename: &str, args: &[String], zone_size: usize) -> Result<String, SlErrCode> {
     let file = match std::fs::read_to_string(filename) {
         Ok(s) => s,
         Err(_) => return Err(SlErrCode::FileCouldNotRead),
     };
-    interpret(&file)
+
+    let mut argv = vec![filename.to_string()];
+    argv.extend_from_slice(args);
+
+    run_code(&file, filename, &argv, zone_size)
 }
 
-/// Interprets a Sail expression, returning the formatted result
-pub fn interpret(code: &str) -> Result<String, SlErrCode> {
-    let region = unsafe { memmgt::acquire_mem_region(1000000) };
+/// Parses a Sail file without evaluating it, reporting any syntax error
+pub fn check_file(filename: &str) -> Result<(), SlErrCode> {
+    let file = match std::fs::read_to_string(filename) {
+        Ok(s) => s,
+        Err(_) => return Err(SlErrCode::FileCouldNotRead),
+    };
 
-    let (tbl, env) = prep_environment(region);
+    let region = unsafe { memmgt::acquire_mem_region(file.len() * 4 + 1000) };
+    let (tbl, _) = prep_environment(region);
 
-    environment_setup(region, tbl, env);
+    parser::parse(region, tbl, &file)?;
+    Ok(())
+}
 
-    let expr = parser::parse(region, tbl, code)?;
+/// Interprets a Sail expression, returning the formatted result
+///
+/// An error code produced as the final value counts as a failure
+pub fn interpret(code: &str, zone_size: usize) -> Result<String, SlErrCode> {
+    run_code(code, "<input>", &[], zone_size)
+}
+
+/// Evaluates Sail code in a fresh environment with `argv` bound
+///
+/// `source` names the code's origin for the debugger and profiler
+fn run_code(
+    code: &str,
+    source: &str,
+    argv: &[String],
+    zone_size: usize,
+) -> Result<String, SlErrCode> {
+    let region = unsafe { memmgt::acquire_mem_region(zone_size) };
+
+    let (tbl, env) = environment_boot(region)?;
+    argv_set(region, env, argv);
+
+    let expr = parser::parse_source(region, tbl, code, source)?;
     let result = eval::eval(region, tbl, env, expr);
 
+    heap::exit_report(region, tbl, env);
+
+    if coretypp!(result ; ErrCode) {
+        return Err(errcode_get(result));
+    }
+
     Ok(context(tbl, result).to_string())
 }
 
@@ -589,9 +738,37 @@ pub fn environment_setup(reg: *mut memmgt::Region, tbl: *mut SlHead, env: *mut S
     let true_intern = bool_init(reg, true);
     env_layer_ins_by_id(reg, env, S_T_INTERN.0, true_intern);
 
+    env_layer_ins_by_id(reg, env, S_ARGV.0, stdvec_make(reg, 0));
+
     insert_native_procs(reg, tbl, env, stdenv::ENVFNS);
 }
 
+/// Creates a symbol table and environment with the standard definitions,
+/// or boots them from the image chosen with `image::boot_from`
+///
+/// An image holds its own symbol table and environment, so these are
+/// restored from it directly rather than set up first.
+pub fn environment_boot(reg: *mut memmgt::Region) -> Result<(*mut SlHead, *mut SlHead), SlErrCode> {
+    if let Some(path) = image::boot_image() {
+        return image::image_load(reg, stdenv::ENVFNS, &path);
+    }
+
+    let (tbl, env) = prep_environment(reg);
+    environment_setup(reg, tbl, env);
+
+    Ok((tbl, env))
+}
+
+/// Replaces the `argv` vector in an environment made by `environment_setup`
+pub fn argv_set(reg: *mut memmgt::Region, env: *mut SlHead, args: &[String]) {
+    let argv = stdvec_make(reg, args.len() as u32);
+    for arg in args {
+        stdvec_push(argv, string_init(reg, arg));
+    }
+
+    env_layer_mut_by_id(env, S_ARGV.0, argv);
+}
+
 /// Insert a slice of native procedures into the symbol table and environment
 pub fn insert_native_procs(
     reg: *mut memmgt::Region,
@@ -616,13 +793,88 @@ mod tests {
     #[test]
     fn returns() {
         let exp = String::from("42");
-        assert_eq!(exp, interpret(&exp).unwrap());
+        assert_eq!(exp, interpret(&exp, DEFAULT_ZONE_SIZE).unwrap());
     }
 
     #[test]
     fn adds() {
         let exp = String::from("(+ 2 2)");
-        assert_eq!("4", interpret(&exp).unwrap());
+        assert_eq!("4", interpret(&exp, DEFAULT_ZONE_SIZE).unwrap());
+    }
+
+    #[test]
+    fn binds_argv() {
+        let args = [String::from("script.sl"), String::from("7")];
+        let exp = "(+ (vec-len argv) (parse (vec-get argv 1)))";
+        assert_eq!("9", run_code(exp, "<input>", &args, DEFAULT_ZONE_SIZE).unwrap());
+    }
+
+    #[test]
+    fn loops() {
+        let exp = "(do (def i 0) (def go #T) (while go (set i (+ i 1)) (set go #F)) i)";
+        assert_eq!("1", interpret(exp, DEFAULT_ZONE_SIZE).unwrap());
+    }
+
+    #[test]
+    fn interrupts() {
+        let flag = eval::interrupt_handle();
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            flag.store(true, Ordering::SeqCst);
+        });
+
+        let exp = "(do (def r (catch (while #T ()))) (if r 5 ()))";
+        assert_eq!("5", interpret(exp, DEFAULT_ZONE_SIZE).unwrap());
+    }
+
+    #[test]
+    fn spawns() {
+        let exp = "(do (def w (spawn (fn [inbox outbox]
+                                   (qtx outbox (+ (qrx-wait inbox) 1))
+                                   42)))
+                       (qtx (vec-get w 0) 9)
+                       (+ (qrx-wait (vec-get w 1)) (join (vec-get w 2))))";
+        assert_eq!("52", interpret(exp, DEFAULT_ZONE_SIZE).unwrap());
+
+        // workers get copies of the caller's bindings
+        let exp = "(do (def n 1)
+                       (def w (spawn (fn [inbox outbox] (set n 5) (qtx outbox n))))
+                       (+ (qrx-wait (vec-get w 1)) n))";
+        assert_eq!("6", interpret(exp, DEFAULT_ZONE_SIZE).unwrap());
+
+        assert!(interpret("(spawn (fn [inbox] inbox))", DEFAULT_ZONE_SIZE).is_err());
+    }
+
+    #[test]
+    fn makes_queues() {
+        let exp = "(do (def q (queue-make-bounded 2 :drop-oldest))
+                       (qtx (vec-get q 0) 1)
+                       (qtx (vec-get q 0) 2)
+                       (qtx (vec-get q 0) 3)
+                       (def u (queue-make))
+                       (qtx (vec-get u 0) 4)
+                       (+ (qdropped (vec-get q 1))
+                          (+ (qrx (vec-get q 1)) (qrx (vec-get u 1)))))";
+        assert_eq!("7", interpret(exp, DEFAULT_ZONE_SIZE).unwrap());
+    }
+
+    #[test]
+    fn selects_queues() {
+        let exp = "(do (def a (queue-make))
+                       (def b (queue-make))
+                       (def rxs (vec-make 2))
+                       (vec-push rxs (vec-get a 1))
+                       (vec-push rxs (vec-get b 1))
+                       (qtx (vec-get b 0) 3)
+                       (def ready (qselect rxs))
+                       (+ ready (qrx (vec-get b 1))))";
+        assert_eq!("4", interpret(exp, DEFAULT_ZONE_SIZE).unwrap());
+
+        // anything but a receiver is refused before blocking
+        let exp = "(do (def rxs (vec-make 1)) (vec-push rxs 5) (qselect rxs))";
+        assert!(interpret(exp, DEFAULT_ZONE_SIZE).is_err());
+        assert!(interpret("(qrx-wait 5)", DEFAULT_ZONE_SIZE).is_err());
+        assert!(interpret("(qrx-timeout 5 10)", DEFAULT_ZONE_SIZE).is_err());
     }
 
     #[test]
@@ -15,10 +15,25 @@
 
 // <>
 
-use super::{core::*, memmgt, SlErrCode, SlHead};
+use super::{core::*, debug, memmgt, SlErrCode, SlHead};
 
+use std::cell::RefCell;
 use std::iter;
 use std::str;
+use std::sync::Arc;
+
+/// Text being parsed, kept so that lists can be given line numbers
+struct Source {
+    name: Arc<str>,
+    len: usize,
+    /// Offset of every newline in the text
+    newlines: Vec<usize>,
+}
+
+thread_local! {
+    /// Source being parsed on this thread, if any
+    static SOURCE: RefCell<Option<Source>> = RefCell::new(None);
+}
 
 // struct Parser {
 //     chars: iter::Peekable<str::Bytes<'static>>,
@@ -35,9 +50,53 @@ pub fn parse(
     let mut acc: Vec<u8> = Vec::new();
     let mut chars = code.bytes().peekable();
 
-    let val = read_value(&mut chars, &mut acc, reg, tbl)?;
+    read_value(&mut chars, &mut acc, reg, tbl)
+}
 
-    Ok(val)
+/// Parses Sail code from a named source, such as a file
+///
+/// While a debugger or the profiler wants them (see
+/// `debug::lines_wanted`), the line on which each list starts is
+/// recorded, replacing those of any earlier parse of the same source.
+pub fn parse_source(
+    reg: *mut memmgt::Region,
+    tbl: *mut SlHead,
+    code: &str,
+    name: &str,
+) -> Result<*mut SlHead, SlErrCode> {
+    if !debug::lines_wanted() {
+        return parse(reg, tbl, code);
+    }
+
+    debug::lines_forget(name);
+    SOURCE.with(|s| {
+        *s.borrow_mut() = Some(Source {
+            name: Arc::from(name),
+            len: code.len(),
+            newlines: code.match_indices('\n').map(|(i, _)| i).collect(),
+        })
+    });
+
+    let val = parse(reg, tbl, code);
+
+    SOURCE.with(|s| *s.borrow_mut() = None);
+
+    val
+}
+
+/// Records the source line of a list, given the characters left
+/// after its first element and that element
+fn note_line(left: usize, elt: *mut SlHead) {
+    SOURCE.with(|s| {
+        if let Some(src) = s.borrow().as_ref() {
+            let offset = src.len - left;
+            let line = match src.newlines.binary_search(&offset) {
+                Ok(i) | Err(i) => i as u32 + 1,
+            };
+
+            debug::line_record(elt, &src.name, line);
+        }
+    });
 }
 
 // pub fn parse_bytes(tbl: *mut SlHead, code: &[u8]) -> Result<*mut SlHead, SlErrCode> {
@@ -185,8 +244,10 @@ fn read_list(
             _ => {
                 // append to the list tail
                 tail = {
+                    let left = chars.len();
                     let next = read_value(chars, acc, reg, tbl)?;
                     if count < 1 {
+                        note_line(left, next);
                         ref_set(tail, next)
                     } else {
                         set_next_list_elt(tail, next)
@@ -0,0 +1,331 @@
+// STARK, a system for computer augmented design.
+// Copyright (C) 2021 Matthew Rothlisberger
+
+// STARK is licensed under the terms of the GNU Affero General Public
+// License. See the top level LICENSE file for the license text.
+
+// Find full copyright information in the top level COPYRIGHT file.
+
+// <>
+
+// src/sail/profile.rs
+
+// Sampling profiler for Sail code. While a thread is profiling, each
+// of its evaluation stacks notes which procedures are running once
+// every so many steps; the samples are then summed into a flat
+// profile, a call tree, and folded stacks for flamegraph tools.
+
+// <>
+
+use super::{core::*, debug, SlHead};
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Evaluation steps between samples, unless asked otherwise
+pub const SAMPLE_INTERVAL: u32 = 100;
+
+/// Name given to samples taken outside any procedure
+const TOP: &str = "<top>";
+
+/// Share of samples below which call tree entries are left out
+const TREE_CUTOFF: f64 = 0.005;
+
+/// Number of threads currently profiling, checked before anything else
+/// so that evaluation is barely slowed when none are
+static PROFILING: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// Profile being collected on this thread, if any
+    static PROFILER: RefCell<Option<Profile>> = RefCell::new(None);
+}
+
+/// Samples collected while profiling one thread
+pub struct Profile {
+    /// Average evaluation steps between samples
+    interval: u32,
+    /// Steps left until the next sample
+    countdown: u32,
+    /// State of the generator which varies the steps between samples
+    jitter: u32,
+    /// Names under which procedures were bound or called
+    names: HashMap<usize, String>,
+    /// Sample counts for each path of running procedures, outermost first
+    stacks: HashMap<Vec<usize>, u64>,
+}
+
+/// Starts profiling this thread, sampling every `interval` steps
+///
+/// Returns false and does nothing if the thread is already profiling
+pub fn start(interval: u32) -> bool {
+    PROFILER.with(|p| {
+        let mut p = p.borrow_mut();
+        if p.is_some() {
+            return false;
+        }
+
+        *p = Some(Profile {
+            interval: interval.max(1),
+            countdown: interval.max(1),
+            jitter: 0x9E37_79B9,
+            names: HashMap::new(),
+            stacks: HashMap::new(),
+        });
+        PROFILING.fetch_add(1, Ordering::Relaxed);
+        true
+    })
+}
+
+/// Stops profiling this thread, giving the samples collected
+pub fn stop() -> Option<Profile> {
+    let out = PROFILER.with(|p| p.borrow_mut().take());
+    if out.is_some() {
+        PROFILING.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    out
+}
+
+/// Checks whether this thread is profiling
+#[inline(always)]
+pub fn enabled() -> bool {
+    PROFILING.load(Ordering::Relaxed) > 0 && PROFILER.with(|p| p.borrow().is_some())
+}
+
+/// Counts one evaluation step, returning true when a sample is due
+#[inline(always)]
+pub fn tick() -> bool {
+    if PROFILING.load(Ordering::Relaxed) == 0 {
+        return false;
+    }
+
+    PROFILER.with(|p| match p.borrow_mut().as_mut() {
+        Some(prof) => {
+            prof.countdown -= 1;
+            if prof.countdown == 0 {
+                prof.countdown = prof.next_countdown();
+                true
+            } else {
+                false
+            }
+        }
+        None => false,
+    })
+}
+
+/// Records one sample: the procedures running, outermost first
+pub fn record(path: Vec<*mut SlHead>) {
+    let path = path.into_iter().map(|p| p as usize).collect();
+    PROFILER.with(|p| {
+        if let Some(prof) = p.borrow_mut().as_mut() {
+            *prof.stacks.entry(path).or_insert(0) += 1;
+        }
+    })
+}
+
+/// Gives a procedure the name it was bound or called by, unless it
+/// already has one
+pub fn name(proc: *mut SlHead, name: impl FnOnce() -> String) {
+    PROFILER.with(|p| {
+        if let Some(prof) = p.borrow_mut().as_mut() {
+            prof.names.entry(proc as usize).or_insert_with(name);
+        }
+    })
+}
+
+impl Profile {
+    /// Picks the steps until the next sample, anywhere from half to one
+    /// and a half times the interval, so that samples do not keep
+    /// landing on the same point of a loop whose length divides it
+    fn next_countdown(&mut self) -> u32 {
+        // xorshift
+        self.jitter ^= self.jitter << 13;
+        self.jitter ^= self.jitter >> 17;
+        self.jitter ^= self.jitter << 5;
+
+        let spread = self.interval.max(2);
+        (self.interval - spread / 2 + self.jitter % spread).max(1)
+    }
+
+    /// Total number of samples taken
+    pub fn samples(&self) -> u64 {
+        self.stacks.values().sum()
+    }
+
+    /// Names a procedure; anonymous lambdas are named after the
+    /// source line of their body, where it is known
+    fn proc_name(&self, proc: usize) -> String {
+        if let Some(name) = self.names.get(&proc) {
+            return name.clone();
+        }
+
+        let proc = proc as *mut SlHead;
+        if core_type(proc) == Some(CoreType::ProcLambda) {
+            let body = proc_lambda_get_body(proc);
+            if nnil_ref_p(body) {
+                if let Some(loc) = debug::location(ref_get(body)) {
+                    return format!("fn@{}", loc);
+                }
+            }
+            "fn".to_string()
+        } else {
+            "native".to_string()
+        }
+    }
+
+    /// Every sampled path with names in place of procedures
+    fn named_stacks(&self) -> Vec<(Vec<String>, u64)> {
+        let mut out: BTreeMap<Vec<String>, u64> = BTreeMap::new();
+
+        for (path, count) in &self.stacks {
+            let names = if path.is_empty() {
+                vec![TOP.to_string()]
+            } else {
+                path.iter().map(|&p| self.proc_name(p)).collect()
+            };
+            *out.entry(names).or_insert(0) += count;
+        }
+
+        out.into_iter().collect()
+    }
+
+    /// Writes the samples in the folded stack format read by
+    /// flamegraph tools: one path per line, then its sample count
+    pub fn folded(&self) -> String {
+        let mut out = String::new();
+
+        for (names, count) in self.named_stacks() {
+            writeln!(out, "{} {}", names.join(";"), count).unwrap();
+        }
+
+        out
+    }
+
+    /// Summarises the samples as a flat profile followed by a call tree
+    pub fn report(&self) -> String {
+        let stacks = self.named_stacks();
+        let total = self.samples();
+        let mut out = String::new();
+
+        writeln!(
+            out,
+            "profile: {} samples, about one every {} steps",
+            total, self.interval
+        )
+        .unwrap();
+
+        if total == 0 {
+            return out.trim_end().to_string();
+        }
+
+        let pct = |n: u64| 100.0 * n as f64 / total as f64;
+
+        // self time goes to the innermost procedure of each sample;
+        // total time counts a procedure once per sample it appears in
+        let mut flat: HashMap<&str, (u64, u64)> = HashMap::new();
+        for (names, count) in &stacks {
+            flat.entry(names.last().unwrap()).or_insert((0, 0)).0 += count;
+
+            let seen: HashSet<&String> = names.iter().collect();
+            for name in seen {
+                flat.entry(name).or_insert((0, 0)).1 += count;
+            }
+        }
+
+        let mut flat: Vec<(&str, u64, u64)> =
+            flat.into_iter().map(|(n, (o, a))| (n, o, a)).collect();
+        flat.sort_by(|a, b| b.1.cmp(&a.1).then(b.2.cmp(&a.2)).then(a.0.cmp(b.0)));
+
+        writeln!(out, "\n  self%  total%  samples  procedure").unwrap();
+        for (name, own, all) in flat {
+            writeln!(
+                out,
+                "{:>7.1} {:>7.1} {:>8}  {}",
+                pct(own),
+                pct(all),
+                own,
+                name
+            )
+            .unwrap();
+        }
+
+        let mut tree = Node::default();
+        for (names, count) in &stacks {
+            tree.count += count;
+            let mut node = &mut tree;
+            // direct recursion is folded into a single entry
+            let mut names = names.clone();
+            names.dedup();
+            for name in &names {
+                node = node.children.entry(name.clone()).or_default();
+                node.count += count;
+            }
+        }
+
+        writeln!(out, "\ncall tree:").unwrap();
+        tree.write_children(&mut out, total, 0);
+
+        out.trim_end().to_string()
+    }
+}
+
+/// Procedure in the call tree, with the samples taken within it
+#[derive(Default)]
+struct Node {
+    count: u64,
+    children: BTreeMap<String, Node>,
+}
+
+impl Node {
+    /// Writes the busiest children first, each indented under its caller
+    fn write_children(&self, out: &mut String, total: u64, depth: usize) {
+        let mut children: Vec<(&String, &Node)> = self.children.iter().collect();
+        children.sort_by(|a, b| b.1.count.cmp(&a.1.count).then(a.0.cmp(b.0)));
+
+        for (name, child) in children {
+            let share = child.count as f64 / total as f64;
+            if share < TREE_CUTOFF {
+                continue;
+            }
+
+            writeln!(
+                out,
+                "{:>7.1}%  {}{}",
+                100.0 * share,
+                "  ".repeat(depth),
+                name
+            )
+            .unwrap();
+            child.write_children(out, total, depth + 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{interpret, DEFAULT_ZONE_SIZE};
+
+    #[test]
+    fn attributes() {
+        // profile prints its report and gives the value of its expression
+        let exp = "(do (def sq (fn [x] (* x x)))
+                       (def loop (fn [n acc]
+                                   (if (= n 0)
+                                       acc
+                                       (loop (- n 1) (+ acc (sq n))))))
+                       (profile (loop 200 0)))";
+        assert_eq!("2686700", interpret(exp, DEFAULT_ZONE_SIZE).unwrap());
+
+        assert!(super::start(1));
+        assert!(!super::start(1));
+        super::super::interpret("(do (def sq (fn [x] (* x x))) (sq 3))", DEFAULT_ZONE_SIZE)
+            .unwrap();
+        let prof = super::stop().unwrap();
+
+        let folded = prof.folded();
+        assert!(folded.contains("sq;* "));
+        assert!(prof.report().contains("call tree:"));
+    }
+}
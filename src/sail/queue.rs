@@ -16,6 +16,12 @@
 
 use super::{core::*, memmgt, SlHead};
 
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
 // TODO: global identifiers for queues?
 // TODO: take action to avoid the "ABA problem"
 
@@ -30,10 +36,302 @@ use super::{core::*, memmgt, SlHead};
 //     ((qptr & 0x0000FFFFFFFFFFFF) as *mut SlHead, (qptr >> 48) as u16)
 // }
 
-/// Creates a queue sender and receiver as a linked pair
+/// What a bounded queue does with an item sent while it is full
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    /// Sender waits until the receiver makes room
+    Block,
+    /// Oldest waiting item is discarded to make room
+    DropOldest,
+    /// New item is discarded
+    DropNewest,
+    /// Like `DropNewest`, but also discards any item equal to the last
+    /// one still waiting (e.g. repeated `:cx-rdrw`) even when not full
+    Coalesce,
+}
+
+/// Shared state of a queue, referenced by the sender's second field
+///
+/// Never freed, as with every object in a region.
+struct QueueCtl {
+    /// Region in which items are copied for the receiver
+    rx_region: *mut memmgt::Region,
+    /// Receiver of this queue, used to wake blocked senders
+    receiver: *mut SlHead,
+    /// Maximum number of waiting items; zero for an unbounded queue
+    capacity: usize,
+    policy: Overflow,
+    /// Items waiting to be received
+    len: AtomicUsize,
+    /// Waiting items of a bounded `DropOldest` or any `Coalesce`
+    /// queue, which bypass the list
+    staged: Option<Mutex<Staging>>,
+    sent: AtomicU64,
+    recvd: AtomicU64,
+    dropped: AtomicU64,
+}
+
+/// Holds the items of a staged queue until they are received
+///
+/// Items wait as copies in a staging region, and only received items
+/// are copied into the receiver's region, so those displaced or
+/// coalesced take up no memory there. Once the staging region has grown
+/// to twice what the waiting items took up when last moved, they are
+/// moved to a spare region and the old one is emptied to be the next
+/// spare, which gives back the memory of items no longer waiting.
+struct Staging {
+    /// Waiting items, oldest first
+    items: VecDeque<*mut SlHead>,
+    /// Region holding the waiting items
+    region: *mut memmgt::Region,
+    /// Empty region the waiting items are next moved to
+    spare: *mut memmgt::Region,
+    /// Bytes the waiting items took up when last moved
+    live: usize,
+}
+
+impl Staging {
+    fn new(zone_size: usize, capacity: usize) -> Self {
+        unsafe {
+            Self {
+                items: VecDeque::with_capacity(capacity),
+                region: memmgt::acquire_mem_region(zone_size),
+                spare: memmgt::acquire_mem_region(zone_size),
+                live: 0,
+            }
+        }
+    }
+
+    /// Copies an item in behind those waiting
+    fn push(&mut self, item: *mut SlHead) {
+        let copy = core_deep_copy(self.region, item);
+        self.items.push_back(copy);
+
+        let used = region_used(self.region);
+        let zone_size = unsafe { memmgt::region_zone_size(self.region) };
+        if used < 2 * self.live.max(zone_size) {
+            return;
+        }
+
+        for item in self.items.iter_mut() {
+            *item = core_deep_copy(self.spare, *item);
+        }
+
+        std::mem::swap(&mut self.region, &mut self.spare);
+        unsafe { memmgt::region_reset(self.spare) };
+
+        self.live = region_used(self.region);
+    }
+}
+
+/// Bytes in use across a region's zones
+fn region_used(region: *mut memmgt::Region) -> usize {
+    unsafe { memmgt::region_zones(region).iter().map(|z| z.1).sum() }
+}
+
+impl QueueCtl {
+    /// Claims a place in the queue for a new item, applying the overflow policy
+    ///
+    /// Returns false if the item should be dropped instead.
+    fn reserve(&self) -> bool {
+        if self.capacity == 0 {
+            self.len.fetch_add(1, Ordering::SeqCst);
+            return true;
+        }
+
+        loop {
+            let len = self.len.load(Ordering::SeqCst);
+            if len < self.capacity {
+                if self
+                    .len
+                    .compare_exchange(len, len + 1, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+                {
+                    return true;
+                }
+                continue;
+            }
+
+            match self.policy {
+                Overflow::Block => {
+                    block_on(&[self.receiver as usize], None, || {
+                        if self.len.load(Ordering::SeqCst) < self.capacity {
+                            Some(())
+                        } else {
+                            None
+                        }
+                    });
+                }
+                Overflow::DropOldest | Overflow::Coalesce => unreachable!("queue is staged"),
+                Overflow::DropNewest => return false,
+            }
+        }
+    }
+
+    /// Copies an item into staging, applying the overflow policy
+    ///
+    /// Returns false if the item was dropped instead.
+    fn stage(&self, staged: &Mutex<Staging>, item: *mut SlHead) -> bool {
+        let mut staged = staged.lock().unwrap();
+
+        if self.policy == Overflow::Coalesce {
+            if let Some(&last) = staged.items.back() {
+                if core_eq(last, item) {
+                    self.dropped.fetch_add(1, Ordering::SeqCst);
+                    return false;
+                }
+            }
+        }
+
+        if self.capacity != 0 && staged.items.len() >= self.capacity {
+            self.dropped.fetch_add(1, Ordering::SeqCst);
+            if self.policy == Overflow::Coalesce {
+                return false;
+            }
+            staged.items.pop_front();
+        }
+
+        staged.push(item);
+        self.len.store(staged.items.len(), Ordering::SeqCst);
+
+        true
+    }
+
+    /// Copies the oldest staged item into the receiver's region, if any
+    fn unstage(&self, staged: &Mutex<Staging>) -> Option<*mut SlHead> {
+        let mut staged = staged.lock().unwrap();
+
+        let item = staged.items.pop_front()?;
+
+        let out = core_deep_copy(self.rx_region, item);
+        self.len.store(staged.items.len(), Ordering::SeqCst);
+
+        Some(out)
+    }
+}
+
+/// Counts describing a queue's traffic
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueueStats {
+    /// Items waiting to be received
+    pub len: usize,
+    /// Items accepted into the queue over its lifetime
+    pub sent: u64,
+    /// Items returned to the receiver over its lifetime
+    pub recvd: u64,
+    /// Items discarded by the overflow policy
+    pub dropped: u64,
+}
+
+/// Gets the control block of a queue from either of its ends
+fn queue_ctl(loc: *mut SlHead) -> &'static QueueCtl {
+    assert_eq!(get_base_size(loc), BaseSize::B16);
+
+    let sender = match super::get_self_type(loc) {
+        t if t == super::T_QUEUE_TX.0 => loc,
+        t if t == super::T_QUEUE_RX.0 => unsafe { read_field_unchecked(loc, 8) },
+        _ => panic!("not a queue"),
+    };
+
+    unsafe { &*(read_field_unchecked::<u64>(sender, 8) as *const QueueCtl) }
+}
+
+// Threads blocked on a receiver are listed here, keyed by the address
+// of the receiver's paired sender (the only thing a sender knows about
+// itself). A receiving thread registers before its final check of the
+// queue, so a transmission can never slip between the check and the
+// park without unparking it. Senders blocked on a full queue wait the
+// same way, keyed by the receiver's address.
+
+/// Threads parked waiting for items, with the sender they wait on
+static WAITERS: Mutex<Vec<(usize, Thread)>> = Mutex::new(Vec::new());
+
+/// Number of entries in `WAITERS`; lets senders skip the lock when nobody waits
+static WAITER_CT: AtomicUsize = AtomicUsize::new(0);
+
+/// Registers the current thread as waiting on each of the given senders
+fn waiters_add(senders: &[usize]) {
+    let mut waiters = WAITERS.lock().unwrap();
+    for &sender in senders {
+        waiters.push((sender, thread::current()));
+    }
+    WAITER_CT.fetch_add(senders.len(), Ordering::SeqCst);
+}
+
+/// Removes the current thread's registrations
+fn waiters_remove() {
+    let id = thread::current().id();
+    let mut waiters = WAITERS.lock().unwrap();
+    let before = waiters.len();
+    waiters.retain(|(_, t)| t.id() != id);
+    WAITER_CT.fetch_sub(before - waiters.len(), Ordering::SeqCst);
+}
+
+/// Unparks every thread waiting on the given sender
+fn waiters_wake(sender: usize) {
+    if WAITER_CT.load(Ordering::SeqCst) == 0 {
+        return;
+    }
+
+    for (_, t) in WAITERS.lock().unwrap().iter().filter(|(s, _)| *s == sender) {
+        t.unpark();
+    }
+}
+
+/// Repeats `poll` until it produces a value, parking the thread between attempts
+///
+/// Gives up and returns `None` once the deadline, if any, has passed.
+fn block_on<T>(
+    senders: &[usize],
+    deadline: Option<Instant>,
+    mut poll: impl FnMut() -> Option<T>,
+) -> Option<T> {
+    loop {
+        if let Some(out) = poll() {
+            return Some(out);
+        }
+
+        waiters_add(senders);
+
+        // check again now that a sender is certain to see us
+        if let Some(out) = poll() {
+            waiters_remove();
+            return Some(out);
+        }
+
+        match deadline {
+            Some(d) => {
+                let now = Instant::now();
+                if now >= d {
+                    waiters_remove();
+                    return None;
+                }
+                thread::park_timeout(d - now);
+            }
+            None => thread::park(),
+        }
+
+        waiters_remove();
+    }
+}
+
+/// Creates an unbounded queue sender and receiver as a linked pair
 pub fn queue_create(
     tx_region: *mut memmgt::Region,
     rx_region: *mut memmgt::Region,
+) -> (*mut SlHead, *mut SlHead) {
+    queue_create_bounded(tx_region, rx_region, 0, Overflow::Block)
+}
+
+/// Creates a queue sender and receiver holding at most `capacity` items
+///
+/// A capacity of zero makes the queue unbounded. With `Overflow::Block`,
+/// a thread must never send on a full queue that only it receives from.
+pub fn queue_create_bounded(
+    tx_region: *mut memmgt::Region,
+    rx_region: *mut memmgt::Region,
+    capacity: usize,
+    policy: Overflow,
 ) -> (*mut SlHead, *mut SlHead) {
     unsafe {
         let sender = memmgt::alloc(tx_region, 16, Cfg::B16Other as u8);
@@ -42,8 +340,29 @@ pub fn queue_create(
         super::set_self_type(sender, super::T_QUEUE_TX.0);
         super::set_self_type(receiver, super::T_QUEUE_RX.0);
 
+        let staged = match policy {
+            Overflow::DropOldest if capacity == 0 => None,
+            Overflow::DropOldest | Overflow::Coalesce => {
+                let zone_size = memmgt::region_zone_size(rx_region);
+                Some(Mutex::new(Staging::new(zone_size, capacity)))
+            }
+            _ => None,
+        };
+
+        let ctl = Box::new(QueueCtl {
+            rx_region,
+            receiver,
+            capacity,
+            policy,
+            len: AtomicUsize::new(0),
+            staged,
+            sent: AtomicU64::new(0),
+            recvd: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        });
+
         write_field_unchecked(sender, 0, receiver);
-        write_field_unchecked(sender, 8, rx_region as u64);
+        write_field_unchecked(sender, 8, Box::into_raw(ctl) as u64);
 
         write_field_unchecked(receiver, 0, nil());
         write_field_unchecked(receiver, 8, sender);
@@ -53,17 +372,33 @@ pub fn queue_create(
 }
 
 /// Transmits a copy of the given Sail object along the queue
-pub fn queue_tx(loc: *mut SlHead, item: *mut SlHead) {
+///
+/// Returns false if the queue's overflow policy discarded the item
+pub fn queue_tx(loc: *mut SlHead, item: *mut SlHead) -> bool {
     assert_eq!(super::get_self_type(loc), super::T_QUEUE_TX.0);
     assert_eq!(get_base_size(loc), BaseSize::B16);
 
+    let ctl = queue_ctl(loc);
+
+    if let Some(staged) = &ctl.staged {
+        if !ctl.stage(staged, item) {
+            return false;
+        }
+        ctl.sent.fetch_add(1, Ordering::SeqCst);
+        waiters_wake(loc as usize);
+        return true;
+    }
+
+    if !ctl.reserve() {
+        ctl.dropped.fetch_add(1, Ordering::SeqCst);
+        return false;
+    }
+
     unsafe {
-        // create new list element containing the item
-        // TODO: must change to permit copying arbitrary values
-        let elt = core_copy_val(
-            read_field_unchecked::<u64>(loc, 8) as *mut memmgt::Region,
-            item,
-        );
+        // create new list element containing a copy of the item; handles
+        // like atoms and queues are shared rather than copied, so the
+        // element is always a cell of its own and never the item itself
+        let elt = ref_init(ctl.rx_region, core_deep_copy(ctl.rx_region, item));
 
         let mut tail;
         loop {
@@ -71,7 +406,7 @@ pub fn queue_tx(loc: *mut SlHead, item: *mut SlHead) {
             tail = read_field_atomic_unchecked(loc, 0);
 
             // get pointer to the tail's next element
-            let (is_head, next) = if super::get_self_type(tail) == super::T_QUEUE_RX.0 {
+            let (is_head, next) = if tail == ctl.receiver {
                 (true, read_field_atomic_unchecked(tail, 0))
             } else {
                 (false, get_next_list_elt(tail))
@@ -97,13 +432,78 @@ pub fn queue_tx(loc: *mut SlHead, item: *mut SlHead) {
         // attempt to change the tail pointer to the new node
         write_field_cmpxcg_unchecked(loc, 0, tail, elt);
     }
+
+    ctl.sent.fetch_add(1, Ordering::SeqCst);
+    waiters_wake(loc as usize);
+
+    true
+}
+
+/// Checks whether the queue has an item ready, without receiving it
+///
+/// Each receiver has only one consumer, so a true result stays true
+/// until that consumer receives
+pub fn queue_ready_p(loc: *mut SlHead) -> bool {
+    assert_eq!(super::get_self_type(loc), super::T_QUEUE_RX.0);
+    assert_eq!(get_base_size(loc), BaseSize::B16);
+
+    let ctl = queue_ctl(loc);
+    if ctl.staged.is_some() {
+        return ctl.len.load(Ordering::SeqCst) > 0;
+    }
+
+    unsafe { !nil_p(read_field_atomic_unchecked(loc, 0)) }
 }
 
 /// Receives and returns the object at the head of the queue
+///
+/// Returns nil if the queue is empty
 pub fn queue_rx(loc: *mut SlHead) -> *mut SlHead {
     assert_eq!(super::get_self_type(loc), super::T_QUEUE_RX.0);
     assert_eq!(get_base_size(loc), BaseSize::B16);
 
+    let ctl = queue_ctl(loc);
+
+    let out = match &ctl.staged {
+        Some(staged) => match ctl.unstage(staged) {
+            Some(item) => item,
+            None => return nil(),
+        },
+        None => {
+            let elt = queue_unlink(loc);
+            if nil_p(elt) {
+                return nil();
+            }
+            ctl.len.fetch_sub(1, Ordering::SeqCst);
+
+            // each item arrives in a cell of its own
+            ref_get(elt)
+        }
+    };
+
+    ctl.recvd.fetch_add(1, Ordering::SeqCst);
+
+    if ctl.policy == Overflow::Block && ctl.capacity != 0 {
+        waiters_wake(loc as usize);
+    }
+
+    out
+}
+
+/// Gets a snapshot of the queue's length and traffic counts from either end
+pub fn queue_stats(loc: *mut SlHead) -> QueueStats {
+    let ctl = queue_ctl(loc);
+
+    QueueStats {
+        len: ctl.len.load(Ordering::SeqCst),
+        sent: ctl.sent.load(Ordering::SeqCst),
+        recvd: ctl.recvd.load(Ordering::SeqCst),
+        dropped: ctl.dropped.load(Ordering::SeqCst),
+    }
+}
+
+/// Removes the cell at the head of the queue list; nil when empty
+fn queue_unlink(loc: *mut SlHead) -> *mut SlHead {
     unsafe {
         loop {
             // get the head of the queue list
@@ -150,6 +550,48 @@ pub fn queue_rx(loc: *mut SlHead) -> *mut SlHead {
     }
 }
 
+/// Receives the object at the head of the queue, blocking until one arrives
+pub fn queue_rx_wait(loc: *mut SlHead) -> *mut SlHead {
+    let sender = unsafe { read_field_unchecked::<u64>(loc, 8) } as usize;
+    block_on(&[sender], None, || nnil(queue_rx(loc))).unwrap()
+}
+
+/// Receives the object at the head of the queue, waiting at most `timeout`
+///
+/// Returns nil if nothing arrived in time
+pub fn queue_rx_timeout(loc: *mut SlHead, timeout: Duration) -> *mut SlHead {
+    let sender = unsafe { read_field_unchecked::<u64>(loc, 8) } as usize;
+    block_on(&[sender], Some(Instant::now() + timeout), || {
+        nnil(queue_rx(loc))
+    })
+    .unwrap_or_else(nil)
+}
+
+/// Waits until one of the given queues has an item ready
+///
+/// Returns the index of the first ready receiver, or `None` if the
+/// timeout passes first; the item itself is left in the queue
+pub fn queue_select(locs: &[*mut SlHead], timeout: Option<Duration>) -> Option<usize> {
+    let senders: Vec<usize> = locs
+        .iter()
+        .map(|&loc| unsafe { read_field_unchecked::<u64>(loc, 8) } as usize)
+        .collect();
+
+    block_on(&senders, timeout.map(|t| Instant::now() + t), || {
+        locs.iter().position(|&loc| queue_ready_p(loc))
+    })
+}
+
+/// Wraps a possibly nil object as an option
+#[inline(always)]
+fn nnil(obj: *mut SlHead) -> Option<*mut SlHead> {
+    if nil_p(obj) {
+        None
+    } else {
+        Some(obj)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -199,4 +641,154 @@ mod tests {
             assert_eq!(i64_get(queue_rx(recv)), 42);
         }
     }
+
+    #[test]
+    fn q_bounded() {
+        let tx_reg = unsafe { memmgt::acquire_mem_region(100) };
+        let rx_reg = unsafe { memmgt::acquire_mem_region(100) };
+
+        let (send, recv) = queue_create_bounded(tx_reg, rx_reg, 2, Overflow::DropNewest);
+        assert!(queue_tx(send, i64_init(tx_reg, 1)));
+        assert!(queue_tx(send, i64_init(tx_reg, 2)));
+        assert!(!queue_tx(send, i64_init(tx_reg, 3)));
+        assert_eq!(i64_get(queue_rx(recv)), 1);
+        assert_eq!(i64_get(queue_rx(recv)), 2);
+        assert!(nil_p(queue_rx(recv)));
+
+        let (send, recv) = queue_create_bounded(tx_reg, rx_reg, 2, Overflow::DropOldest);
+        let used = || unsafe {
+            memmgt::region_zones(rx_reg)
+                .iter()
+                .map(|z| z.1)
+                .sum::<usize>()
+        };
+        let before = used();
+        for i in 1..=40 {
+            assert!(queue_tx(send, i64_init(tx_reg, i)));
+        }
+        // displaced items never reach the receiver's region
+        assert_eq!(used(), before);
+        assert_eq!(queue_stats(recv).len, 2);
+        assert_eq!(queue_stats(recv).dropped, 38);
+        assert!(queue_ready_p(recv));
+        assert_eq!(i64_get(queue_rx(recv)), 39);
+        assert_eq!(i64_get(queue_rx(recv)), 40);
+        assert!(nil_p(queue_rx(recv)));
+        assert!(!queue_ready_p(recv));
+
+        let (send, recv) = queue_create_bounded(tx_reg, rx_reg, 8, Overflow::Coalesce);
+        let redraw = sym_init(tx_reg, super::super::K_CX_REDRW.0);
+        assert!(queue_tx(send, redraw));
+        assert!(!queue_tx(send, redraw));
+        assert!(queue_tx(send, i64_init(tx_reg, 5)));
+        assert!(queue_tx(send, redraw));
+        assert_eq!(
+            queue_stats(send),
+            QueueStats {
+                len: 3,
+                sent: 3,
+                recvd: 0,
+                dropped: 1
+            }
+        );
+        assert_eq!(sym_get_id(queue_rx(recv)), super::super::K_CX_REDRW.0);
+        assert_eq!(i64_get(queue_rx(recv)), 5);
+        assert_eq!(sym_get_id(queue_rx(recv)), super::super::K_CX_REDRW.0);
+        assert_eq!(queue_stats(recv).recvd, 3);
+
+        // staging gives back the memory of items displaced long ago
+        let (send, _) = queue_create_bounded(tx_reg, rx_reg, 2, Overflow::DropOldest);
+        let item = i64_init(tx_reg, 9);
+        for _ in 0..10_000 {
+            queue_tx(send, item);
+        }
+        let staged = queue_ctl(send).staged.as_ref().unwrap().lock().unwrap();
+        assert!(region_used(staged.region) + region_used(staged.spare) < 1000);
+    }
+
+    #[test]
+    fn q_structures() {
+        let tx_reg = unsafe { memmgt::acquire_mem_region(10000) };
+        let rx_reg = unsafe { memmgt::acquire_mem_region(1000) };
+        let (tbl, _) = super::super::prep_environment(tx_reg);
+
+        let (send, recv) = queue_create(tx_reg, rx_reg);
+
+        let code = "(1 (2 3) [4 \"five\" (6)] :seven)";
+        let item = super::super::parser::parse(tx_reg, tbl, code).unwrap();
+        queue_tx(send, item);
+
+        let out = queue_rx(recv);
+        assert_ne!(out, item);
+        assert_eq!(super::super::context(tbl, out).to_string(), code);
+
+        // a vector containing itself
+        let cyclic = stdvec_make(tx_reg, 2);
+        stdvec_push(cyclic, i64_init(tx_reg, 8));
+        stdvec_push(cyclic, cyclic);
+        queue_tx(send, cyclic);
+
+        let out = queue_rx(recv);
+        assert_ne!(out, cyclic);
+        assert_eq!(i64_get(stdvec_idx(out, 0)), 8);
+        assert_eq!(stdvec_idx(out, 1), out);
+    }
+
+    #[test]
+    fn q_handles() {
+        let tx_reg = unsafe { memmgt::acquire_mem_region(1000) };
+        let rx_reg = unsafe { memmgt::acquire_mem_region(1000) };
+
+        let (send, recv) = queue_create(tx_reg, rx_reg);
+
+        // atoms and queues are shared, not copied, and are never linked
+        // into the queue themselves, so may be sent more than once
+        let atom = super::super::atom::atom_make(tx_reg, i64_init(tx_reg, 3));
+        let (inner_send, inner_recv) = queue_create(tx_reg, rx_reg);
+        queue_tx(send, atom);
+        queue_tx(send, atom);
+        queue_tx(send, inner_send);
+        assert!(nil_p(get_next_list_elt(atom)));
+        assert!(nil_p(get_next_list_elt(inner_send)));
+
+        assert_eq!(queue_rx(recv), atom);
+        assert_eq!(queue_rx(recv), atom);
+        queue_tx(queue_rx(recv), i64_init(tx_reg, 6));
+        assert_eq!(i64_get(queue_rx(inner_recv)), 6);
+        assert!(nil_p(queue_rx(recv)));
+    }
+
+    #[test]
+    fn q_wait() {
+        unsafe {
+            let tx_reg = memmgt::acquire_mem_region(100);
+            let rx_reg = memmgt::acquire_mem_region(100);
+
+            let (send, recv) = queue_create(tx_reg, rx_reg);
+            let (other_send, other_recv) = queue_create(tx_reg, rx_reg);
+
+            assert!(nil_p(queue_rx_timeout(recv, Duration::from_millis(10))));
+            assert_eq!(
+                queue_select(&[recv, other_recv], Some(Duration::from_millis(10))),
+                None
+            );
+
+            let (send, tx_reg) = (send as usize, tx_reg as usize);
+            let producer = thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                queue_tx(
+                    send as *mut SlHead,
+                    i64_init(tx_reg as *mut memmgt::Region, 7),
+                );
+            });
+
+            assert_eq!(queue_select(&[other_recv, recv], None), Some(1));
+            assert_eq!(i64_get(queue_rx_wait(recv)), 7);
+            producer.join().unwrap();
+
+            queue_tx(other_send, i64_init(tx_reg as *mut memmgt::Region, 14));
+            assert_eq!(queue_select(&[other_recv, recv], None), Some(0));
+            assert_eq!(i64_get(queue_rx_wait(other_recv)), 14);
+        }
+    }
 }
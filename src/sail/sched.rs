@@ -0,0 +1,283 @@
+// STARK, a system for computer augmented design.
+// Copyright (C) 2021 Matthew Rothlisberger
+
+// STARK is licensed under the terms of the GNU Affero General Public
+// License. See the top level LICENSE file for the license text.
+
+// Find full copyright information in the top level COPYRIGHT file.
+
+// <>
+
+// src/sail/sched.rs
+
+// Coroutines, generators, and a cooperative task scheduler. Every
+// coroutine owns an EvalStack which is stepped only while it runs, so
+// suspending one is just a matter of no longer calling `iter_once`.
+
+// <>
+
+use super::{core::*, eval, memmgt, queue, SlHead};
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+/// A suspendable Sail computation
+struct Coroutine {
+    stack: eval::EvalStack,
+    /// Return slot for the bottom frame; still `SIGIL` until finished
+    ret: Box<*mut SlHead>,
+    /// Whether the coroutine runs under the task scheduler
+    task: bool,
+}
+
+/// Placeholder return value of a coroutine that has not finished
+const SIGIL: *mut SlHead = 1 as *mut SlHead;
+
+/// Request made by a native procedure to the running coroutine
+#[derive(Clone, Copy)]
+enum Signal {
+    None,
+    /// Suspend, handing this value to whoever resumed the coroutine
+    Yield(*mut SlHead),
+    /// Suspend until this receiver has an item, then retry the call
+    Wait(*mut SlHead),
+}
+
+/// How a single run of a coroutine ended
+enum Outcome {
+    Yielded(*mut SlHead),
+    Blocked(*mut SlHead),
+    Done(*mut SlHead),
+}
+
+thread_local! {
+    /// Coroutines being run on this thread, innermost last
+    static RUNNING: RefCell<Vec<*mut Coroutine>> = RefCell::new(Vec::new());
+    /// Pending request from a native procedure to the innermost coroutine
+    static SIGNAL: Cell<Signal> = Cell::new(Signal::None);
+    /// Tasks waiting to be run by `run_tasks`
+    static TASKS: RefCell<VecDeque<*mut SlHead>> = RefCell::new(VecDeque::new());
+}
+
+/// Creates a coroutine which will apply `proc` (taking no arguments) in `env`
+///
+/// Nothing is evaluated until the coroutine is first resumed. Returns
+/// an error code if `proc` is not such a procedure.
+pub fn co_make(
+    reg: *mut memmgt::Region,
+    env: *mut SlHead,
+    proc: *mut SlHead,
+    task: bool,
+) -> *mut SlHead {
+    if !thunk_p(proc) {
+        log::error!("coroutines apply a procedure taking no arguments");
+        return super::errcode_init(reg, super::SlErrCode::ErrorUnknown);
+    }
+
+    let mut co = Box::new(Coroutine {
+        stack: eval::EvalStack::new(10000),
+        ret: Box::new(SIGIL),
+        task,
+    });
+
+    let ret_addr: *mut *mut SlHead = &mut *co.ret;
+    co.stack.push_frame_head(ret_addr, eval::Opcode::Apply, env);
+    co.stack.push(proc);
+
+    unsafe {
+        let obj = memmgt::alloc(reg, 8, Cfg::B8Other as u8);
+        super::set_self_type(obj, super::T_COROUTINE.0);
+        write_field_unchecked(obj, 0, Box::into_raw(co) as u64);
+        obj
+    }
+}
+
+/// Checks whether `proc` is a procedure taking no arguments
+fn thunk_p(proc: *mut SlHead) -> bool {
+    proc_p(proc) && proc_get_argct(proc) == 0
+}
+
+/// Gets the Rust-side state of a coroutine object
+fn co_get(obj: *mut SlHead) -> *mut Coroutine {
+    assert_eq!(super::get_self_type(obj), super::T_COROUTINE.0);
+    unsafe { read_field_unchecked::<u64>(obj, 0) as *mut Coroutine }
+}
+
+/// Checks whether a coroutine has returned
+pub fn co_done_p(obj: *mut SlHead) -> bool {
+    unsafe { *(*co_get(obj)).ret != SIGIL }
+}
+
+/// Runs a coroutine until it yields or returns, giving that value
+///
+/// A finished coroutine gives nil. A generator which waits on an empty
+/// queue blocks the thread, as it would outside a coroutine.
+pub fn co_resume(reg: *mut memmgt::Region, tbl: *mut SlHead, obj: *mut SlHead) -> *mut SlHead {
+    if co_done_p(obj) {
+        return nil();
+    }
+
+    match co_run(reg, tbl, co_get(obj)) {
+        Outcome::Yielded(val) | Outcome::Done(val) => val,
+        // only a task resumed by hand can get here; it waits where it is
+        Outcome::Blocked(_) => nil(),
+    }
+}
+
+/// Steps a coroutine's stack until it suspends or finishes
+fn co_run(reg: *mut memmgt::Region, tbl: *mut SlHead, co: *mut Coroutine) -> Outcome {
+    RUNNING.with(|r| r.borrow_mut().push(co));
+
+    let co = unsafe { &mut *co };
+    let out = loop {
+        co.stack.iter_once(reg, tbl);
+
+        if *co.ret != SIGIL {
+            break Outcome::Done(*co.ret);
+        }
+
+        match SIGNAL.with(|s| s.replace(Signal::None)) {
+            Signal::None => {}
+            Signal::Yield(val) => break Outcome::Yielded(val),
+            Signal::Wait(rx) => break Outcome::Blocked(rx),
+        }
+    };
+
+    RUNNING.with(|r| r.borrow_mut().pop());
+
+    out
+}
+
+/// Whether the innermost running coroutine is a scheduled task
+fn in_task() -> bool {
+    RUNNING.with(|r| match r.borrow().last() {
+        Some(&co) => unsafe { (*co).task },
+        None => false,
+    })
+}
+
+/// Suspends the running coroutine once the current native call returns
+///
+/// Called outside any coroutine, this does nothing.
+pub fn co_yield(val: *mut SlHead) -> *mut SlHead {
+    if RUNNING.with(|r| !r.borrow().is_empty()) {
+        SIGNAL.with(|s| s.set(Signal::Yield(val)));
+    }
+
+    val
+}
+
+/// Checks whether the native call just made must be repeated later
+///
+/// The evaluator then leaves the call's frame in place, so that it is
+/// applied again when the coroutine next runs.
+#[inline(always)]
+pub fn retry_requested() -> bool {
+    SIGNAL.with(|s| matches!(s.get(), Signal::Wait(_)))
+}
+
+/// Receives from a queue, waiting until an item arrives
+///
+/// Inside a task this suspends only the task, not the thread.
+pub fn sched_rx_wait(receiver: *mut SlHead) -> *mut SlHead {
+    if !in_task() {
+        return queue::queue_rx_wait(receiver);
+    }
+
+    let out = queue::queue_rx(receiver);
+    if nil_p(out) {
+        SIGNAL.with(|s| s.set(Signal::Wait(receiver)));
+    }
+
+    out
+}
+
+/// Adds a new task which will apply `proc` when the scheduler runs
+pub fn task_add(reg: *mut memmgt::Region, env: *mut SlHead, proc: *mut SlHead) -> *mut SlHead {
+    if !thunk_p(proc) {
+        log::error!("tasks apply a procedure taking no arguments");
+        return super::errcode_init(reg, super::SlErrCode::ErrorUnknown);
+    }
+
+    let co = co_make(reg, env, proc, true);
+    TASKS.with(|t| t.borrow_mut().push_back(co));
+    co
+}
+
+/// Runs this thread's tasks round robin until all of them are finished
+///
+/// A task gives up the thread when it yields or waits on an empty
+/// queue; when every task is waiting, the thread sleeps until one of
+/// their queues has an item.
+pub fn run_tasks(reg: *mut memmgt::Region, tbl: *mut SlHead) {
+    assert!(!in_task(), "tasks cannot run tasks");
+
+    let mut blocked: Vec<(*mut SlHead, *mut SlHead)> = Vec::new();
+
+    loop {
+        if super::exit_requested() {
+            return;
+        }
+
+        // wake up any tasks whose queues have something
+        let mut i = 0;
+        while i < blocked.len() {
+            if queue::queue_ready_p(blocked[i].1) {
+                let (co, _) = blocked.swap_remove(i);
+                TASKS.with(|t| t.borrow_mut().push_back(co));
+            } else {
+                i += 1;
+            }
+        }
+
+        let next = TASKS.with(|t| t.borrow_mut().pop_front());
+
+        match next {
+            Some(co) => match co_run(reg, tbl, co_get(co)) {
+                Outcome::Yielded(_) => TASKS.with(|t| t.borrow_mut().push_back(co)),
+                Outcome::Blocked(rx) => blocked.push((co, rx)),
+                Outcome::Done(_) => {}
+            },
+            None if blocked.is_empty() => return,
+            None => {
+                let receivers: Vec<*mut SlHead> = blocked.iter().map(|b| b.1).collect();
+                queue::queue_select(&receivers, None);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{interpret, DEFAULT_ZONE_SIZE};
+
+    #[test]
+    fn generates() {
+        let exp = "(do (def g (gen (fn [] (yield 1) (yield 2) 3)))
+                       (def a (resume g))
+                       (def b (resume g))
+                       (def c (resume g))
+                       (if (gen-done g) (+ a (+ b c)) ()))";
+        assert_eq!("6", interpret(exp, DEFAULT_ZONE_SIZE).unwrap());
+
+        assert!(interpret("(gen (fn [x] x))", DEFAULT_ZONE_SIZE).is_err());
+    }
+
+    #[test]
+    fn schedules() {
+        // the first task must not hold up the second while it waits
+        let exp = "(do (def w (spawn (fn [inbox outbox] (qtx outbox (* (qrx-wait inbox) 2)))))
+                       (def got 0)
+                       (def ticks 0)
+                       (task (fn [] (set got (qrx-wait (vec-get w 1)))))
+                       (task (fn [] (while (not (= ticks 3))
+                                      (set ticks (+ ticks 1))
+                                      (yield ()))
+                                    (qtx (vec-get w 0) 21)))
+                       (run-tasks)
+                       (+ got ticks))";
+        assert_eq!("45", interpret(exp, DEFAULT_ZONE_SIZE).unwrap());
+
+        assert!(interpret("(task 5)", DEFAULT_ZONE_SIZE).is_err());
+    }
+}
@@ -0,0 +1,770 @@
+// STARK, a system for computer augmented design.
+// Copyright (C) 2021 Matthew Rothlisberger
+
+// STARK is licensed under the terms of the GNU Affero General Public
+// License. See the top level LICENSE file for the license text.
+
+// Find full copyright information in the top level COPYRIGHT file.
+
+// <>
+
+// src/sail/serial.rs
+
+// Binary encoding of Sail values, for saving them to files and sending
+// them between processes without the losses of printing and parsing.
+// Numbers keep their exact types, symbols are written by name so they
+// survive a different symbol table, and native procedures are written
+// by the name they are bound to.
+
+// <>
+
+use super::{
+    arrvec_get_len, arrvec_get_typ, core::*, image::native_names, memmgt, SlErrCode, SlHead, T_REF,
+    T_SYMBOL,
+};
+
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::ptr;
+
+/// First bytes of every encoded value
+const MAGIC: &[u8; 3] = b"SLV";
+/// Version of the encoding; values of other versions are refused
+const VERSION: u8 = 2;
+
+/// Deepest nesting of containers accepted when decoding
+const MAX_DEPTH: usize = 1024;
+
+enum_and_tryfrom! {
+    /// Marks the kind of each encoded value
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    #[repr(u8)]
+    enum Tag {
+        Nil,
+        False,
+        True,
+        U8,
+        I8,
+        U16,
+        I16,
+        U32,
+        I32,
+        U64,
+        I64,
+        U128,
+        I128,
+        F32,
+        F64,
+        Symbol,
+        ErrCode,
+        List,
+        VecStd,
+        VecStr,
+        VecArr,
+        VecAny,
+        VecHash,
+        ProcLambda,
+        ProcNative,
+        /// Container already written, given by its position in the order
+        /// containers were first written
+        Seen,
+    }
+}
+
+impl Tag {
+    /// Gives the head configuration and size of a number tag
+    fn number(self) -> Option<(Cfg, usize)> {
+        Some(match self {
+            Tag::U8 => (Cfg::B1U8, 1),
+            Tag::I8 => (Cfg::B1I8, 1),
+            Tag::U16 => (Cfg::B2U16, 2),
+            Tag::I16 => (Cfg::B2I16, 2),
+            Tag::U32 => (Cfg::B4U32, 4),
+            Tag::I32 => (Cfg::B4I32, 4),
+            Tag::U64 => (Cfg::B8U64, 8),
+            Tag::I64 => (Cfg::B8I64, 8),
+            Tag::U128 => (Cfg::B16U128, 16),
+            Tag::I128 => (Cfg::B16I128, 16),
+            Tag::F32 => (Cfg::B4F32, 4),
+            Tag::F64 => (Cfg::B8F64, 8),
+            _ => return None,
+        })
+    }
+}
+
+/// Encodes a Sail value and everything it refers to
+///
+/// Containers reached more than once are written once and referred to
+/// afterwards, so sharing and cycles survive. Values which are not of
+/// a core type, like atoms and queues, cannot be encoded.
+pub fn serialize(
+    tbl: *mut SlHead,
+    env: *mut SlHead,
+    obj: *mut SlHead,
+) -> Result<Vec<u8>, SlErrCode> {
+    let mut enc = Encoder {
+        tbl,
+        env,
+        out: Vec::new(),
+        seen: HashMap::new(),
+        natives: None,
+    };
+
+    enc.out.extend_from_slice(MAGIC);
+    enc.out.push(VERSION);
+    enc.value(obj)?;
+
+    Ok(enc.out)
+}
+
+/// Decodes a value encoded by `serialize` into a region
+///
+/// Symbols are interned in `tbl`, and native procedures are found by
+/// name in `env`.
+pub fn deserialize(
+    reg: *mut memmgt::Region,
+    tbl: *mut SlHead,
+    env: *mut SlHead,
+    data: &[u8],
+) -> Result<*mut SlHead, SlErrCode> {
+    let mut dec = Decoder {
+        reg,
+        tbl,
+        env,
+        data,
+        pos: 0,
+        seen: Vec::new(),
+        depth: 0,
+    };
+
+    if dec.take(3)? != MAGIC || dec.u8()? != VERSION {
+        return Err(SlErrCode::SerialInvalid);
+    }
+
+    let out = dec.value()?;
+    if dec.pos != data.len() {
+        return Err(SlErrCode::SerialInvalid);
+    }
+
+    Ok(out)
+}
+
+/// Writes the raw bytes of numbers little endian first
+fn little_endian(bytes: &mut [u8], size: usize) {
+    if cfg!(target_endian = "big") {
+        for num in bytes.chunks_mut(size) {
+            num.reverse();
+        }
+    }
+}
+
+/// State of a `serialize` in progress
+struct Encoder {
+    tbl: *mut SlHead,
+    env: *mut SlHead,
+    out: Vec<u8>,
+    /// Position of each container written so far
+    seen: HashMap<usize, u32>,
+    /// Names of native procedures, found when first needed
+    natives: Option<HashMap<usize, String>>,
+}
+
+impl Encoder {
+    fn tag(&mut self, tag: Tag) {
+        self.out.push(tag as u8);
+    }
+
+    fn u32(&mut self, val: u32) {
+        self.out.extend_from_slice(&val.to_le_bytes());
+    }
+
+    fn str(&mut self, val: &str) {
+        self.u32(val.len() as u32);
+        self.out.extend_from_slice(val.as_bytes());
+    }
+
+    /// Copies `len` numbers of `size` bytes each from `src`
+    fn raw(&mut self, src: *const u8, size: usize, len: usize) {
+        let start = self.out.len();
+        self.out
+            .extend_from_slice(unsafe { std::slice::from_raw_parts(src, size * len) });
+        little_endian(&mut self.out[start..], size);
+    }
+
+    /// Writes a symbol ID as its mode and name
+    fn symbol(&mut self, id: u32) -> Result<(), SlErrCode> {
+        let name = sym_tab_lookup_id_num(self.tbl, demodes_sym(id));
+        if nil_p(name) {
+            return Err(SlErrCode::SerialUnsupported);
+        }
+
+        self.out.push(mode_of_sym(id) as u8);
+        self.str(string_get(name));
+        Ok(())
+    }
+
+    /// Notes a container, or refers back to it if already written
+    fn container(&mut self, obj: *mut SlHead) -> bool {
+        let next = self.seen.len() as u32;
+        match self.seen.get(&(obj as usize)) {
+            Some(&pos) => {
+                self.tag(Tag::Seen);
+                self.u32(pos);
+                false
+            }
+            None => {
+                self.seen.insert(obj as usize, next);
+                true
+            }
+        }
+    }
+
+    /// Writes the elements of a typed array vector, from the first
+    /// element at `elts`
+    fn elements(&mut self, typ: u32, elts: *const u8, len: usize) -> Result<(), SlErrCode> {
+        self.symbol(typ)?;
+        self.u32(len as u32);
+
+        if typ == T_REF.0 {
+            for i in 0..len {
+                let elt = unsafe { ptr::read_unaligned((elts as *const *mut SlHead).add(i)) };
+                self.value(elt)?;
+            }
+        } else if typ == T_SYMBOL.0 {
+            for i in 0..len {
+                self.symbol(unsafe { ptr::read_unaligned((elts as *const u32).add(i)) })?;
+            }
+        } else {
+            self.raw(elts, temp_get_size(typ), len);
+        }
+
+        Ok(())
+    }
+
+    fn value(&mut self, obj: *mut SlHead) -> Result<(), SlErrCode> {
+        use CoreType::*;
+
+        let typ = core_type(obj).ok_or(SlErrCode::SerialUnsupported)?;
+        match typ {
+            Nil => self.tag(Tag::Nil),
+            Bool => self.tag(if bool_get(obj) { Tag::True } else { Tag::False }),
+            U8 | I8 | U16 | I16 | U32 | I32 | U64 | I64 | U128 | I128 | F32 | F64 => {
+                let tag = match typ {
+                    U8 => Tag::U8,
+                    I8 => Tag::I8,
+                    U16 => Tag::U16,
+                    I16 => Tag::I16,
+                    U32 => Tag::U32,
+                    I32 => Tag::I32,
+                    U64 => Tag::U64,
+                    I64 => Tag::I64,
+                    U128 => Tag::U128,
+                    I128 => Tag::I128,
+                    F32 => Tag::F32,
+                    _ => Tag::F64,
+                };
+                self.tag(tag);
+                self.raw(value_ptr(obj), core_size(obj), 1);
+            }
+            Symbol => {
+                self.tag(Tag::Symbol);
+                self.symbol(sym_get_id(obj))?;
+            }
+            ErrCode => {
+                self.tag(Tag::ErrCode);
+                self.raw(value_ptr(obj), 2, 1);
+            }
+            Ref => {
+                if self.container(obj) {
+                    self.tag(Tag::List);
+
+                    let mut elts = vec![];
+                    let mut elt = ref_get(obj);
+                    while !nil_p(elt) {
+                        elts.push(elt);
+                        elt = get_next_list_elt(elt);
+                    }
+
+                    self.u32(elts.len() as u32);
+                    for elt in elts {
+                        self.value(elt)?;
+                    }
+                }
+            }
+            VecStd => {
+                if self.container(obj) {
+                    self.tag(Tag::VecStd);
+                    self.u32(core_read_field(obj, 0));
+                    self.u32(stdvec_get_len(obj));
+                    for i in 0..stdvec_get_len(obj) {
+                        self.value(stdvec_idx(obj, i))?;
+                    }
+                }
+            }
+            VecStr => {
+                if self.container(obj) {
+                    self.tag(Tag::VecStr);
+                    self.u32(core_read_field(obj, 0));
+                    self.str(string_get(obj));
+                }
+            }
+            VecArr => {
+                if self.container(obj) {
+                    self.tag(Tag::VecArr);
+                    let elts = unsafe { value_ptr(obj).add(8) };
+                    self.elements(arrvec_get_typ(obj), elts, arrvec_get_len(obj) as usize)?;
+                }
+            }
+            VecAny => {
+                if self.container(obj) {
+                    // type, capacity, length
+                    self.tag(Tag::VecAny);
+                    self.u32(core_read_field(obj, 4));
+                    let elts = unsafe { value_ptr(obj).add(12) };
+                    let len = core_read_field::<u32>(obj, 8) as usize;
+                    self.elements(core_read_field(obj, 0), elts, len)?;
+                }
+            }
+            VecHash => {
+                if self.container(obj) {
+                    self.tag(Tag::VecHash);
+                    self.u32(hashvec_get_size(obj));
+
+                    // entries keep their order within each bucket, so
+                    // that newer entries still shadow older ones
+                    let mut entries = vec![];
+                    for i in 0..hashvec_get_size(obj) as usize {
+                        let mut entry = core_read_field(obj, 4 + 4 + (i * PTR_LEN as usize));
+                        while !nil_p(entry) {
+                            entries.push(ref_get(entry));
+                            entry = get_next_list_elt(entry);
+                        }
+                    }
+
+                    self.u32(entries.len() as u32);
+                    for key in entries {
+                        self.value(key)?;
+                        self.value(get_next_list_elt(key))?;
+                    }
+                }
+            }
+            ProcLambda => {
+                if self.container(obj) {
+                    self.tag(Tag::ProcLambda);
+
+                    let argct = proc_get_argct(obj);
+                    self.u32(argct as u32);
+                    for i in 0..argct as usize {
+                        self.symbol(core_read_field(
+                            obj,
+                            (NUM_16_LEN + PTR_LEN) as usize + (i * SYMBOL_LEN as usize),
+                        ))?;
+                    }
+
+                    // the body is a chain of expressions, evaluated in turn
+                    let mut body = vec![];
+                    let mut expr = proc_lambda_get_body(obj);
+                    while !nil_p(expr) {
+                        body.push(expr);
+                        expr = get_next_list_elt(expr);
+                    }
+
+                    self.u32(body.len() as u32);
+                    for expr in body {
+                        self.value(expr)?;
+                    }
+                }
+            }
+            ProcNative => {
+                if self.natives.is_none() {
+                    let mut global = self.env;
+                    while !nil_p(get_next_list_elt(global)) {
+                        global = get_next_list_elt(global);
+                    }
+                    self.natives = Some(native_names(self.tbl, global));
+                }
+
+                let name = self.natives.as_ref().unwrap().get(&(obj as usize)).cloned();
+                let name = name.ok_or(SlErrCode::SerialUnsupported)?;
+                self.tag(Tag::ProcNative);
+                self.str(&name);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// State of a `deserialize` in progress
+struct Decoder<'a> {
+    reg: *mut memmgt::Region,
+    tbl: *mut SlHead,
+    env: *mut SlHead,
+    data: &'a [u8],
+    pos: usize,
+    /// Containers made so far, in the order they were written
+    seen: Vec<*mut SlHead>,
+    /// Containers currently being filled
+    depth: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SlErrCode> {
+        let out = self
+            .data
+            .get(self.pos..self.pos.saturating_add(len))
+            .ok_or(SlErrCode::SerialInvalid)?;
+        self.pos += len;
+        Ok(out)
+    }
+
+    fn u8(&mut self) -> Result<u8, SlErrCode> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, SlErrCode> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn str(&mut self) -> Result<&'a str, SlErrCode> {
+        let len = self.u32()? as usize;
+        std::str::from_utf8(self.take(len)?).map_err(|_| SlErrCode::SerialInvalid)
+    }
+
+    /// Reads a count of items, each taking at least `size` bytes, so
+    /// that a corrupt count cannot ask for more memory than the input
+    /// could ever fill
+    fn count(&mut self, size: usize) -> Result<u32, SlErrCode> {
+        let count = self.u32()?;
+        if (count as usize).saturating_mul(size) > self.data.len() - self.pos {
+            return Err(SlErrCode::SerialInvalid);
+        }
+        Ok(count)
+    }
+
+    /// Copies `len` numbers of `size` bytes each to `dst`
+    fn raw(&mut self, dst: *mut u8, size: usize, len: usize) -> Result<(), SlErrCode> {
+        let mut bytes = self.take(size * len)?.to_vec();
+        little_endian(&mut bytes, size);
+        unsafe { ptr::copy_nonoverlapping(bytes.as_ptr(), dst, bytes.len()) };
+        Ok(())
+    }
+
+    /// Reads a symbol's mode and name, giving its ID in this table
+    fn symbol(&mut self) -> Result<u32, SlErrCode> {
+        let mode = SymbolMode::try_from(self.u8()?).map_err(|_| SlErrCode::SerialInvalid)?;
+        let name = self.str()?;
+        Ok(modeize_sym(sym_tab_get_id(self.reg, self.tbl, name), mode))
+    }
+
+    /// Notes a new container, so that later values can refer to it
+    fn container(&mut self, obj: *mut SlHead) -> *mut SlHead {
+        self.seen.push(obj);
+        obj
+    }
+
+    /// Reads the elements of a typed array vector, allocated with a
+    /// head of `head` bytes and room for `cap` elements
+    fn elements(
+        &mut self,
+        cfg: Cfg,
+        head: usize,
+        cap: Option<u32>,
+    ) -> Result<*mut SlHead, SlErrCode> {
+        let typ = self.symbol()?;
+        if !temp_base_sized_p(typ) {
+            return Err(SlErrCode::SerialInvalid);
+        }
+
+        let size = temp_get_size(typ);
+        let len = self.count(1)?;
+        let cap = cap.unwrap_or(len);
+        if len > cap {
+            return Err(SlErrCode::SerialInvalid);
+        }
+
+        let obj = unsafe {
+            let obj = memmgt::alloc(self.reg, vec_size(head, size, cap as usize), cfg as u8);
+            ptr::write_bytes(value_ptr(obj), 0, vec_size(head, size, cap as usize));
+            write_field_unchecked::<u32>(obj, 0, typ);
+            write_field_unchecked::<u32>(obj, head - 4, len);
+            obj
+        };
+        self.container(obj);
+
+        let elts = unsafe { value_ptr(obj).add(head) };
+        if typ == T_REF.0 {
+            for i in 0..len as usize {
+                let elt = self.value()?;
+                unsafe { ptr::write_unaligned((elts as *mut *mut SlHead).add(i), elt) };
+            }
+        } else if typ == T_SYMBOL.0 {
+            for i in 0..len as usize {
+                let id = self.symbol()?;
+                unsafe { ptr::write_unaligned((elts as *mut u32).add(i), id) };
+            }
+        } else {
+            self.raw(elts, size, len as usize)?;
+        }
+
+        Ok(obj)
+    }
+
+    fn value(&mut self) -> Result<*mut SlHead, SlErrCode> {
+        let tag = Tag::try_from(self.u8()?).map_err(|_| SlErrCode::SerialInvalid)?;
+
+        if let Some((cfg, size)) = tag.number() {
+            let obj = unsafe { memmgt::alloc(self.reg, size, cfg as u8) };
+            self.raw(value_ptr(obj), size, 1)?;
+            return Ok(obj);
+        }
+
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(SlErrCode::SerialInvalid);
+        }
+
+        let out = match tag {
+            Tag::Nil => nil(),
+            Tag::False => bool_init(self.reg, false),
+            Tag::True => bool_init(self.reg, true),
+            Tag::Symbol => {
+                let id = self.symbol()?;
+                sym_init(self.reg, id)
+            }
+            Tag::ErrCode => {
+                let code = u16::from_le_bytes(self.take(2)?.try_into().unwrap());
+                let code = SlErrCode::try_from(code).map_err(|_| SlErrCode::SerialInvalid)?;
+                super::errcode_init(self.reg, code)
+            }
+            Tag::Seen => {
+                let pos = self.u32()? as usize;
+                *self.seen.get(pos).ok_or(SlErrCode::SerialInvalid)?
+            }
+            Tag::List => {
+                let list = self.container(ref_make(self.reg));
+
+                let mut last = nil();
+                for _ in 0..self.count(1)? {
+                    let elt = self.value()?;
+                    // nil cannot be a list element, and an element
+                    // already in a list cannot join another
+                    if nil_p(elt) || !nil_p(get_next_list_elt(elt)) || elt == list {
+                        return Err(SlErrCode::SerialInvalid);
+                    }
+
+                    if nil_p(last) {
+                        ref_set(list, elt);
+                    } else {
+                        set_next_list_elt(last, elt);
+                    }
+                    last = elt;
+                }
+
+                list
+            }
+            Tag::VecStd => {
+                let cap = self.u32()?;
+                let len = self.count(1)?;
+                if len > cap || cap as usize > self.data.len() {
+                    return Err(SlErrCode::SerialInvalid);
+                }
+
+                let vec = self.container(stdvec_make(self.reg, cap));
+                for _ in 0..len {
+                    let elt = self.value()?;
+                    stdvec_push(vec, elt);
+                }
+
+                vec
+            }
+            Tag::VecStr => {
+                let cap = self.u32()?;
+                let val = self.str()?;
+                if val.len() > cap as usize || cap as usize > self.data.len() {
+                    return Err(SlErrCode::SerialInvalid);
+                }
+
+                let string = string_make(self.reg, cap);
+                string_set(string, val);
+                self.container(string)
+            }
+            Tag::VecArr => self.elements(Cfg::VecArr, 8, None)?,
+            Tag::VecAny => {
+                let cap = self.u32()?;
+                if cap as usize > self.data.len() {
+                    return Err(SlErrCode::SerialInvalid);
+                }
+
+                let vec = self.elements(Cfg::VecAny, 12, Some(cap))?;
+                unsafe { write_field_unchecked::<u32>(vec, 4, cap) };
+                vec
+            }
+            Tag::VecHash => {
+                let size = self.u32()?;
+                if size == 0 || size as usize > self.data.len() {
+                    return Err(SlErrCode::SerialInvalid);
+                }
+
+                let map = self.container(hashvec_make(self.reg, size));
+                let mut entries = vec![];
+                for _ in 0..self.count(2)? {
+                    let key = self.value()?;
+                    let val = self.value()?;
+                    entries.push((key, val));
+                }
+
+                // the first entry written is the newest, so goes in last
+                for (key, val) in entries.into_iter().rev() {
+                    hash_map_insert(self.reg, map, key, val);
+                }
+
+                map
+            }
+            Tag::ProcLambda => {
+                let argct = self.count(1)?;
+                if argct > u16::MAX as u32 {
+                    return Err(SlErrCode::SerialInvalid);
+                }
+
+                let proc = self.container(proc_lambda_make(self.reg, argct as u16));
+                for i in 0..argct as u16 {
+                    let arg = self.symbol()?;
+                    proc_lambda_set_arg(proc, i, arg);
+                }
+
+                let mut last = nil();
+                for _ in 0..self.count(1)? {
+                    let expr = self.value()?;
+                    // as with list elements, each expression must be
+                    // free to join the chain
+                    if nil_p(expr) || !nil_p(get_next_list_elt(expr)) || expr == proc {
+                        return Err(SlErrCode::SerialInvalid);
+                    }
+
+                    if nil_p(last) {
+                        proc_lambda_set_body(proc, expr);
+                    } else {
+                        set_next_list_elt(last, expr);
+                    }
+                    last = expr;
+                }
+
+                proc
+            }
+            Tag::ProcNative => {
+                let id = sym_tab_get_id(self.reg, self.tbl, self.str()?);
+                let proc = env_lookup_by_id(self.env, id);
+                if core_type(proc) != Some(CoreType::ProcNative) {
+                    return Err(SlErrCode::SerialInvalid);
+                }
+
+                proc
+            }
+            _ => unreachable!(),
+        };
+
+        self.depth -= 1;
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{context, environment_setup, errcode_init, eval, parser};
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let reg = unsafe { memmgt::acquire_mem_region(1000000) };
+        let (tbl, env) = prep_environment(reg);
+        environment_setup(reg, tbl, env);
+
+        let run = |reg, tbl, env, code: &str| {
+            let expr = parser::parse(reg, tbl, code).unwrap();
+            eval::eval(reg, tbl, env, expr)
+        };
+
+        let parsed = parser::parse(reg, tbl, "[:key \"str\" (a (b) c) {:k [#T ()]}]").unwrap();
+        let list = stdvec_idx(parsed, 2);
+        let val = stdvec_init(
+            reg,
+            &[
+                i64_init(reg, 1),
+                f32_init(reg, 2.5),
+                f64_init(reg, 2.5),
+                stdvec_idx(parsed, 0),
+                stdvec_idx(parsed, 1),
+                list,
+                stdvec_idx(parsed, 3),
+                run(reg, tbl, env, "(do (def sq (fn [x] (* x x))) sq)"),
+                run(reg, tbl, env, "(do +)"),
+                errcode_init(reg, SlErrCode::ParseInvalidNum),
+                list,
+            ],
+        );
+        let data = serialize(tbl, env, val).unwrap();
+
+        // decode into a different symbol table, where IDs differ
+        let reg = unsafe { memmgt::acquire_mem_region(1000000) };
+        let (tbl2, env2) = prep_environment(reg);
+        sym_tab_get_id(reg, tbl2, "shifts-every-id");
+        environment_setup(reg, tbl2, env2);
+
+        let out = deserialize(reg, tbl2, env2, &data).unwrap();
+        assert_eq!(
+            context(tbl, val).to_string(),
+            context(tbl2, out).to_string()
+        );
+        assert_eq!(core_type(stdvec_idx(out, 1)), Some(CoreType::F32));
+        assert_eq!(stdvec_idx(out, 5), stdvec_idx(out, 10));
+
+        // procedures still work, natives included
+        env_layer_ins_by_id(
+            reg,
+            env2,
+            sym_tab_get_id(reg, tbl2, "sq2"),
+            stdvec_idx(out, 7),
+        );
+        env_layer_ins_by_id(
+            reg,
+            env2,
+            sym_tab_get_id(reg, tbl2, "plus2"),
+            stdvec_idx(out, 8),
+        );
+        let res = run(reg, tbl2, env2, "(plus2 (sq2 3) 1)");
+        assert_eq!(context(tbl2, res).to_string(), "10");
+
+        assert!(deserialize(reg, tbl2, env2, &data[..data.len() - 1]).is_err());
+        assert!(deserialize(reg, tbl2, env2, b"SLV\x03\x00").is_err());
+    }
+
+    #[test]
+    fn lambda_bodies() {
+        let reg = unsafe { memmgt::acquire_mem_region(1000000) };
+        let (tbl, env) = prep_environment(reg);
+        environment_setup(reg, tbl, env);
+
+        let run = |reg, tbl, env, code: &str| {
+            let expr = parser::parse(reg, tbl, code).unwrap();
+            eval::eval(reg, tbl, env, expr)
+        };
+
+        let code = "(do (def add5 (fn [x] (def y 5) (+ x y))) add5)";
+        let add5 = run(reg, tbl, env, code);
+        let data = serialize(tbl, env, add5).unwrap();
+
+        // special forms are known by fixed IDs, so the table here must
+        // not be shifted for the body to be evaluated
+        let reg = unsafe { memmgt::acquire_mem_region(1000000) };
+        let (tbl2, env2) = prep_environment(reg);
+        environment_setup(reg, tbl2, env2);
+
+        // every expression of the body is kept, in order
+        let out = deserialize(reg, tbl2, env2, &data).unwrap();
+        let id = sym_tab_get_id(reg, tbl2, "add5");
+        env_layer_ins_by_id(reg, env2, id, out);
+        let res = run(reg, tbl2, env2, "(add5 1)");
+        assert_eq!(context(tbl2, res).to_string(), "6");
+    }
+}
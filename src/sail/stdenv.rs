@@ -238,9 +238,154 @@ sail_fn! {
     }
 
     "qrx" 2 [receiver] {
+        if !receiver_p(receiver) {
+            log::error!("qrx takes a queue receiver");
+            return super::errcode_init(_reg, super::SlErrCode::ErrorUnknown);
+        }
         return super::queue::queue_rx(receiver);
     }
 
+    "qrx-wait" 1 [receiver] {
+        if !receiver_p(receiver) {
+            log::error!("qrx-wait takes a queue receiver");
+            return super::errcode_init(_reg, super::SlErrCode::ErrorUnknown);
+        }
+        return super::sched::sched_rx_wait(receiver);
+    }
+
+    "qrx-timeout" 2 [receiver, ms] {
+        if !receiver_p(receiver) {
+            log::error!("qrx-timeout takes a queue receiver");
+            return super::errcode_init(_reg, super::SlErrCode::ErrorUnknown);
+        }
+        coretypck!(ms ; I64);
+        let timeout = std::time::Duration::from_millis(i64_get(ms).max(0) as u64);
+        return super::queue::queue_rx_timeout(receiver, timeout);
+    }
+
+    "qselect" 2 [receivers, ms] {
+        coretypck!(receivers ; VecStd);
+
+        let receivers: Vec<*mut SlHead> = (0..stdvec_get_len(receivers))
+            .map(|i| stdvec_idx(receivers, i))
+            .collect();
+
+        if let Some(i) = receivers.iter().position(|&rx| !receiver_p(rx)) {
+            log::error!("qselect item {} is not a queue receiver", i);
+            return super::errcode_init(_reg, super::SlErrCode::ErrorUnknown);
+        }
+
+        let timeout = if nil_p(ms) {
+            None
+        } else {
+            coretypck!(ms ; I64);
+            Some(std::time::Duration::from_millis(i64_get(ms).max(0) as u64))
+        };
+
+        return match super::queue::queue_select(&receivers, timeout) {
+            Some(idx) => i64_init(_reg, idx as i64),
+            None => nil(),
+        };
+    }
+
+    "qlen" 1 [queue] {
+        return i64_init(_reg, super::queue::queue_stats(queue).len as i64);
+    }
+
+    "qsent" 1 [queue] {
+        return i64_init(_reg, super::queue::queue_stats(queue).sent as i64);
+    }
+
+    "qrecvd" 1 [queue] {
+        return i64_init(_reg, super::queue::queue_stats(queue).recvd as i64);
+    }
+
+    "qdropped" 1 [queue] {
+        return i64_init(_reg, super::queue::queue_stats(queue).dropped as i64);
+    }
+
+    "queue-make" 0 [] {
+        let (sender, receiver) = super::queue::queue_create(_reg, _reg);
+        return stdvec_init(_reg, &[sender, receiver]);
+    }
+
+    "queue-make-bounded" 2 [capacity, policy] {
+        coretypck!(capacity ; I64);
+        coretypck!(policy ; Symbol);
+
+        let capacity = i64_get(capacity).max(1) as usize;
+
+        let id = sym_get_id(policy);
+        assert_eq!(mode_of_sym(id), SymbolMode::Keyword);
+
+        let policy = match string_get(sym_tab_lookup_id_num(_tbl, demodes_sym(id))) {
+            "block" => super::queue::Overflow::Block,
+            "drop-oldest" => super::queue::Overflow::DropOldest,
+            "drop-newest" => super::queue::Overflow::DropNewest,
+            "coalesce" => super::queue::Overflow::Coalesce,
+            other => {
+                log::error!("unknown queue overflow policy :{}", other);
+                return super::errcode_init(_reg, super::SlErrCode::ErrorUnknown);
+            }
+        };
+
+        let (sender, receiver) = super::queue::queue_create_bounded(_reg, _reg, capacity, policy);
+        return stdvec_init(_reg, &[sender, receiver]);
+    }
+
+    "gen" 1 [proc] {
+        return super::sched::co_make(_reg, _env, proc, false);
+    }
+
+    "resume" 1 [co] {
+        return super::sched::co_resume(_reg, _tbl, co);
+    }
+
+    "yield" 1 [val] {
+        return super::sched::co_yield(val);
+    }
+
+    "gen-done" 1 [co] {
+        if super::sched::co_done_p(co) {
+            env_lookup_by_id(_env, super::S_T_INTERN.0)
+        } else {
+            nil()
+        }
+    }
+
+    "task" 1 [proc] {
+        return super::sched::task_add(_reg, _env, proc);
+    }
+
+    "run-tasks" 0 [] {
+        super::sched::run_tasks(_reg, _tbl);
+        return nil();
+    }
+
+    "spawn" 1 [proc] {
+        return super::worker::worker_spawn(_reg, _tbl, _env, proc);
+    }
+
+    "join" 1 [thrd] {
+        return super::worker::worker_join(_reg, thrd);
+    }
+
+    "atom" 1 [val] {
+        return super::atom::atom_make(_reg, val);
+    }
+
+    "deref" 1 [atom] {
+        return super::atom::atom_deref(atom);
+    }
+
+    "reset!" 2 [atom, val] {
+        return super::atom::atom_store(_reg, atom, val);
+    }
+
+    "swap!" 2 [atom, proc] {
+        return super::atom::atom_swap(_reg, _tbl, _env, atom, proc);
+    }
+
     "as-f32" 1 [val] {
         return f32_init(_reg, f64_get(val) as f32);
     }
@@ -291,6 +436,7 @@ sail_fn! {
 
     "arr-vec-set" 3 [target, idx, val] {
         coretypck!(target ; VecArr);
+        assert!(!shared_p(target), "cannot modify a shared object");
         coretypck!(idx ; I64);
         let typ = super::arrvec_get_typ(target);
         assert!(temp_base_sized_p(typ));
@@ -310,6 +456,53 @@ sail_fn! {
         return target;
     }
 
+    "vec-get" 2 [target, idx] {
+        coretypck!(target ; VecStd);
+        coretypck!(idx ; I64);
+
+        let idx = i64_get(idx) as u32;
+        assert!(idx < stdvec_get_len(target));
+
+        return stdvec_idx(target, idx);
+    }
+
+    "vec-len" 1 [target] {
+        coretypck!(target ; VecStd);
+        return i64_init(_reg, stdvec_get_len(target) as i64);
+    }
+
+    "vec-make" 1 [cap] {
+        coretypck!(cap ; I64);
+        return stdvec_make(_reg, i64_get(cap) as u32);
+    }
+
+    "vec-push" 2 [target, item] {
+        coretypck!(target ; VecStd);
+
+        if stdvec_get_len(target) == stdvec_get_cap(target) {
+            log::error!("vec-push onto a full vector");
+            return super::errcode_init(_reg, super::SlErrCode::ErrorUnknown);
+        }
+
+        stdvec_push(target, item);
+        return target;
+    }
+
+    "env-var" 1 [name] {
+        coretypck!(name ; VecStr);
+
+        return match std::env::var(string_get(name)) {
+            Ok(val) => string_init(_reg, &val),
+            Err(_) => nil(),
+        };
+    }
+
+    "exit" 1 [status] {
+        coretypck!(status ; I64);
+        super::request_exit(i64_get(status) as i32);
+        return status;
+    }
+
     "print" 1 [arg] {
         println!("{}", super::context(_tbl, arg).to_string());
         return nil();
@@ -320,6 +513,56 @@ sail_fn! {
         return arg;
     }
 
+    "mem-stats" 0 [] {
+        let stats = super::heap::region_stats(_reg, _tbl, _env);
+        return string_init(_reg, &stats.to_string());
+    }
+
+    "heap-dump" 1 [path] {
+        coretypck!(path ; VecStr);
+
+        let written = std::fs::File::create(string_get(path))
+            .and_then(|mut f| super::heap::heap_dump(_reg, _tbl, _env, &mut f));
+
+        return match written {
+            Ok(()) => nil(),
+            Err(_) => super::errcode_init(_reg, super::SlErrCode::FileCouldNotWrite),
+        };
+    }
+
+    "image-save" 1 [path] {
+        coretypck!(path ; VecStr);
+
+        return match super::image::image_save(_tbl, _env, string_get(path)) {
+            Ok(_) => nil(),
+            Err(err) => super::errcode_init(_reg, err),
+        };
+    }
+
+    "serialize" 1 [val] {
+        return match super::serial::serialize(_tbl, _env, val) {
+            Ok(data) => super::arrvec_init(_reg, super::T_U8.0, data.len() as u32, &data),
+            Err(err) => super::errcode_init(_reg, err),
+        };
+    }
+
+    "deserialize" 1 [data] {
+        coretypck!(data ; VecArr);
+        assert_eq!(super::arrvec_get_typ(data), super::T_U8.0);
+
+        let data = unsafe {
+            std::slice::from_raw_parts(
+                value_ptr(data).add(8),
+                super::arrvec_get_len(data) as usize,
+            )
+        };
+
+        return match super::serial::deserialize(_reg, _tbl, _env, data) {
+            Ok(val) => val,
+            Err(err) => super::errcode_init(_reg, err),
+        };
+    }
+
     "printenv" 0 [] {
         println!("{}", super::context(_tbl, _env).to_string());
         return nil();
@@ -335,3 +578,9 @@ sail_fn! {
         };
     }
 }
+
+/// Checks that a receive native was given a queue receiver, since
+/// anything else would be read as one (and perhaps blocked on)
+fn receiver_p(loc: *mut SlHead) -> bool {
+    super::get_self_type(loc) == super::T_QUEUE_RX.0
+}
@@ -0,0 +1,125 @@
+// STARK, a system for computer augmented design.
+// Copyright (C) 2021 Matthew Rothlisberger
+
+// STARK is licensed under the terms of the GNU Affero General Public
+// License. See the top level LICENSE file for the license text.
+
+// Find full copyright information in the top level COPYRIGHT file.
+
+// <>
+
+// src/sail/worker.rs
+
+// Worker threads started from Sail code. Each runs a Sail function in
+// its own region and stack, talking to its parent through a queue pair.
+
+// <>
+
+use super::{core::*, eval, memmgt, queue, SlHead};
+
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+
+/// Handle kept by a thread object; empty once the thread is joined
+type WorkerHandle = Mutex<Option<JoinHandle<usize>>>;
+
+/// Starts a worker thread running `proc`, which takes an inbox and an outbox
+///
+/// The worker gets a fresh region and stack, and a copy of every binding
+/// visible in `env` as it stands, so it sees the caller's definitions
+/// without sharing them. Returns a vector of a sender to the inbox, a
+/// receiver from the outbox, and a thread object for `worker_join`, or
+/// an error code if `proc` does not take two arguments.
+pub fn worker_spawn(
+    reg: *mut memmgt::Region,
+    tbl: *mut SlHead,
+    env: *mut SlHead,
+    proc: *mut SlHead,
+) -> *mut SlHead {
+    if !proc_p(proc) || proc_get_argct(proc) != 2 {
+        log::error!("workers apply a procedure taking an inbox and an outbox");
+        return super::errcode_init(reg, super::SlErrCode::ErrorUnknown);
+    }
+
+    // zones as large as the caller's, which were sized by the user
+    let wrk_reg = unsafe { memmgt::acquire_mem_region(memmgt::region_zone_size(reg)) };
+
+    let (in_tx, in_rx) = queue::queue_create(reg, wrk_reg);
+    let (out_tx, out_rx) = queue::queue_create(wrk_reg, reg);
+
+    // the caller goes on changing its environment, so the worker must
+    // not read it once running
+    let wrk_env = env_deep_copy(wrk_reg, env);
+
+    let wrk_proc = core_deep_copy(wrk_reg, proc);
+
+    let (wrk_reg, tbl, wrk_env, wrk_proc, in_rx, out_tx) = (
+        wrk_reg as usize,
+        tbl as usize,
+        wrk_env as usize,
+        wrk_proc as usize,
+        in_rx as usize,
+        out_tx as usize,
+    );
+
+    let handle = thread::Builder::new()
+        .name("sail worker".to_string())
+        .spawn(move || {
+            let wrk_reg = wrk_reg as *mut memmgt::Region;
+            let tbl = tbl as *mut SlHead;
+
+            let mut stack = eval::EvalStack::new(10000);
+
+            let sigil = 1 as *mut SlHead;
+
+            let mut ret_slot = sigil;
+            let ret_addr: *mut *mut SlHead = &mut ret_slot;
+
+            stack.push_frame_head(ret_addr, eval::Opcode::Apply, wrk_env as *mut SlHead);
+            stack.push(wrk_proc as *mut SlHead);
+            stack.push(in_rx as *mut SlHead);
+            stack.push(out_tx as *mut SlHead);
+
+            while ret_slot == sigil {
+                stack.iter_once(wrk_reg, tbl);
+            }
+
+            ret_slot as usize
+        })
+        .unwrap();
+
+    let thrd = unsafe {
+        let thrd = memmgt::alloc(reg, 8, Cfg::B8Other as u8);
+        super::set_self_type(thrd, super::T_THREAD.0);
+
+        let handle: Box<WorkerHandle> = Box::new(Mutex::new(Some(handle)));
+        write_field_unchecked(thrd, 0, Box::into_raw(handle) as u64);
+
+        thrd
+    };
+
+    stdvec_init(reg, &[in_tx, out_rx, thrd])
+}
+
+/// Waits for a worker thread to finish and returns a copy of its result
+///
+/// Returns nil if the thread was already joined or ended in a panic
+pub fn worker_join(reg: *mut memmgt::Region, thrd: *mut SlHead) -> *mut SlHead {
+    assert_eq!(super::get_self_type(thrd), super::T_THREAD.0);
+
+    let handle = unsafe { &*(read_field_unchecked::<u64>(thrd, 0) as *const WorkerHandle) };
+
+    let handle = match handle.lock().unwrap().take() {
+        Some(h) => h,
+        None => return nil(),
+    };
+
+    match handle.join() {
+        // the worker's region lives on, so its result is still readable
+        Ok(result) => core_deep_copy(reg, result as *mut SlHead),
+        Err(_) => {
+            log::error!("a sail worker thread panicked");
+            nil()
+        }
+    }
+}
@@ -0,0 +1,153 @@
+// STARK, a system for computer augmented design.
+// Copyright (C) 2021 Matthew Rothlisberger
+
+// STARK is licensed under the terms of the GNU Affero General Public
+// License. See the top level LICENSE file for the license text.
+
+// Find full copyright information in the top level COPYRIGHT file.
+
+// <>
+
+// src/scripts.rs
+
+// Locates the Sail scripts and other resources STARK loads at
+// startup. Files are looked up through a search path; copies of the
+// defaults are built into the binary so it runs from any directory.
+
+// <>
+
+use crate::sail::{self, memmgt::Region, SlHead};
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Built-in copy of the manager thread's script
+const MAIN_SL: &str = include_str!("../scripts/main.sl");
+
+/// Built-in copy of the render thread's script
+const RNDR_SL: &str = include_str!("../scripts/rndr.sl");
+
+/// Built-in copy of the frame icon
+const ICON_PNG: &[u8] = include_bytes!("../icons/icon.png");
+
+/// Name of the user's init file within the config directory
+const INIT_SL: &str = "init.sl";
+
+/// Ordered list of directories to search for scripts and the icon
+///
+/// In order of precedence:
+/// - the directory given on the command line
+/// - `$STARK_HOME/scripts` for scripts, or `$STARK_HOME/icons` for the icon
+/// - the user config directory (`$XDG_CONFIG_HOME/stark`, usually `~/.config/stark`)
+///
+/// When no directory has a file, the built-in default is used.
+#[derive(Clone, Debug)]
+pub struct SearchPath {
+    cli_dir: Option<PathBuf>,
+    home: Option<PathBuf>,
+    config: Option<PathBuf>,
+}
+
+impl SearchPath {
+    /// Builds the search path from an optional command line directory and the environment
+    pub fn new(cli_dir: Option<&str>) -> Self {
+        let home = env::var_os("STARK_HOME")
+            .filter(|h| !h.is_empty())
+            .map(PathBuf::from);
+
+        Self {
+            cli_dir: cli_dir.map(PathBuf::from),
+            home,
+            config: config_dir(),
+        }
+    }
+
+    /// Finds the first script with the given name along the search path
+    pub fn find(&self, name: &str) -> Option<PathBuf> {
+        self.find_in("scripts", name)
+    }
+
+    /// Finds the first file with the given name along the search path,
+    /// looking in `home_sub` within `$STARK_HOME`
+    fn find_in(&self, home_sub: &str, name: &str) -> Option<PathBuf> {
+        let home = self.home.as_ref().map(|h| h.join(home_sub));
+
+        self.cli_dir
+            .iter()
+            .chain(home.iter())
+            .chain(self.config.iter())
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+    }
+
+    /// Parsed code of the manager thread's script
+    pub fn main_script(&self, reg: *mut Region, tbl: *mut SlHead) -> *mut SlHead {
+        self.parse(reg, tbl, "main.sl", MAIN_SL)
+    }
+
+    /// Parsed code of the render thread's script
+    pub fn rndr_script(&self, reg: *mut Region, tbl: *mut SlHead) -> *mut SlHead {
+        self.parse(reg, tbl, "rndr.sl", RNDR_SL)
+    }
+
+    /// PNG data for the frame icon, `icon.png` along the search path
+    pub fn icon(&self) -> Vec<u8> {
+        if let Some(path) = self.find_in("icons", "icon.png") {
+            match fs::read(&path) {
+                Ok(data) => return data,
+                Err(err) => log::warn!("could not read {}: {}", path.display(), err),
+            }
+        }
+
+        ICON_PNG.to_vec()
+    }
+
+    /// Parses a script from the search path, falling back to its
+    /// built-in text if the file found cannot be parsed
+    fn parse(&self, reg: *mut Region, tbl: *mut SlHead, name: &str, default: &str) -> *mut SlHead {
+        let text = self.load(name, default);
+
+        match sail::parser::parse_source(reg, tbl, &text, name) {
+            Ok(expr) => return expr,
+            Err(err) => log::error!("could not parse {}: {:?}", name, err),
+        }
+
+        log::info!("loading built-in {}", name);
+        sail::parser::parse_source(reg, tbl, default, name).unwrap()
+    }
+
+    /// Reads a script from the search path, falling back to its built-in text
+    fn load(&self, name: &str, default: &str) -> String {
+        if let Some(path) = self.find(name) {
+            match fs::read_to_string(&path) {
+                Ok(text) => {
+                    log::info!("loading {}", path.display());
+                    return text;
+                }
+                Err(err) => log::warn!("could not read {}: {}", path.display(), err),
+            }
+        }
+
+        log::info!("loading built-in {}", name);
+        default.to_string()
+    }
+}
+
+/// The user's STARK config directory, if a home can be determined
+pub fn config_dir() -> Option<PathBuf> {
+    // the XDG spec says relative paths in these variables are invalid
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|h| Path::new(&h).join(".config")))?;
+
+    Some(base.join("stark"))
+}
+
+/// Location of the user's init file, loaded after the default scripts
+pub fn user_init() -> Option<PathBuf> {
+    config_dir()
+        .map(|dir| dir.join(INIT_SL))
+        .filter(|path| path.is_file())
+}